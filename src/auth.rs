@@ -15,7 +15,7 @@ use error::MaliciousServerErrorType;
 use error::Result;
 use textnonce::TextNonce;
 
-/// Handles SCRAM-SHA-1 authentication logic.
+/// Handles SCRAM-SHA-1 and MONGODB-X509 authentication logic.
 #[derive(Debug)]
 pub struct Authenticator {
     db: Database,
@@ -55,6 +55,23 @@ impl Authenticator {
         self.finish(conversation_id, auth_data)
     }
 
+    /// Authenticates using the subject of the client certificate presented during the TLS
+    /// handshake. The database should be `$external`. If no user is provided, the server derives
+    /// it from the certificate subject.
+    pub fn auth_x509(self, user: Option<&str>) -> Result<()> {
+        let mut auth_doc = doc! {
+            "authenticate": 1,
+            "mechanism": "MONGODB-X509"
+        };
+
+        if let Some(user) = user {
+            auth_doc.insert("user", user);
+        }
+
+        self.db.command(auth_doc, Suppressed, None)?;
+        Ok(())
+    }
+
     fn start(&self, user: &str) -> Result<InitialData> {
         let text_nonce = match TextNonce::sized(64) {
             Ok(text_nonce) => text_nonce,
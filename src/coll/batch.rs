@@ -8,6 +8,10 @@ use std::convert::From;
 pub struct DeleteModel {
     pub filter: Document,
     pub multi: bool,
+    /// The collation for this statement, if any.
+    pub collation: Option<Document>,
+    /// The index hint for this statement, either an index name or an index specification.
+    pub hint: Option<Bson>,
 }
 
 impl DeleteModel {
@@ -15,6 +19,8 @@ impl DeleteModel {
         DeleteModel {
             filter: filter,
             multi: multi,
+            collation: None,
+            hint: None,
         }
     }
 }
@@ -25,6 +31,10 @@ pub struct UpdateModel {
     pub update: Document,
     pub upsert: Option<bool>,
     pub multi: bool,
+    /// The collation for this statement, if any.
+    pub collation: Option<Document>,
+    /// The index hint for this statement, either an index name or an index specification.
+    pub hint: Option<Bson>,
 }
 
 impl UpdateModel {
@@ -39,6 +49,8 @@ impl UpdateModel {
             update: update,
             upsert: upsert,
             multi: multi,
+            collation: None,
+            hint: None,
         }
     }
 }
@@ -59,156 +71,151 @@ impl From<UpdateModel> for Document {
             document.insert("multi", Bson::Boolean(true));
         }
 
+        if let Some(collation) = model.collation {
+            document.insert("collation", collation);
+        }
+
+        if let Some(hint) = model.hint {
+            document.insert("hint", hint);
+        }
+
         document
     }
 }
 
+/// Maps the position of a statement within a batch to its index in the original bulk write,
+/// given the original index of each statement in the batch.
+pub fn original_index(indexes: &[i64], position: i64) -> i64 {
+    indexes.get(position as usize).cloned().unwrap_or(position)
+}
+
+/// A batch of writes of a single kind. Each statement is paired with its index in the
+/// original bulk write, since unordered bulk writes regroup statements by kind.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Batch {
-    Insert(Vec<Document>),
-    Delete(Vec<DeleteModel>),
-    Update(Vec<UpdateModel>),
+    Insert(Vec<(i64, Document)>),
+    Delete(Vec<(i64, DeleteModel)>),
+    Update(Vec<(i64, UpdateModel)>),
 }
 
-impl From<WriteModel> for Batch {
-    fn from(model: WriteModel) -> Batch {
+impl Batch {
+    /// Creates a batch holding a single write at the given index of the bulk write.
+    pub fn new(index: i64, model: WriteModel) -> Batch {
         match model {
-            WriteModel::InsertOne { document } => Batch::Insert(vec![document]),
-            WriteModel::DeleteOne { filter } => {
-                Batch::Delete(vec![
-                    DeleteModel {
-                        filter: filter,
-                        multi: false,
-                    },
-                ])
+            WriteModel::InsertOne { document } => Batch::Insert(vec![(index, document)]),
+            WriteModel::DeleteOne { filter, collation, hint } => {
+                Batch::Delete(vec![(index, DeleteModel {
+                    filter: filter,
+                    multi: false,
+                    collation: collation,
+                    hint: hint,
+                })])
             }
-            WriteModel::DeleteMany { filter } => {
-                Batch::Delete(vec![
-                    DeleteModel {
-                        filter: filter,
-                        multi: true,
-                    },
-                ])
+            WriteModel::DeleteMany { filter, collation, hint } => {
+                Batch::Delete(vec![(index, DeleteModel {
+                    filter: filter,
+                    multi: true,
+                    collation: collation,
+                    hint: hint,
+                })])
             }
             WriteModel::ReplaceOne {
                 filter,
                 replacement: update,
                 upsert,
+                collation,
+                hint,
             } |
             WriteModel::UpdateOne {
                 filter,
                 update,
                 upsert,
+                collation,
+                hint,
             } => {
-                Batch::Update(vec![
-                    UpdateModel {
-                        filter: filter,
-                        update: update,
-                        upsert: upsert,
-                        multi: false,
-                    },
-                ])
+                Batch::Update(vec![(index, UpdateModel {
+                    filter: filter,
+                    update: update,
+                    upsert: upsert,
+                    multi: false,
+                    collation: collation,
+                    hint: hint,
+                })])
             }
             WriteModel::UpdateMany {
                 filter,
                 update,
                 upsert,
+                collation,
+                hint,
             } => {
-                Batch::Update(vec![
-                    UpdateModel {
-                        filter: filter,
-                        update: update,
-                        upsert: upsert,
-                        multi: true,
-                    },
-                ])
+                Batch::Update(vec![(index, UpdateModel {
+                    filter: filter,
+                    update: update,
+                    upsert: upsert,
+                    multi: true,
+                    collation: collation,
+                    hint: hint,
+                })])
             }
         }
     }
-}
 
-impl Batch {
-    pub fn len(&self) -> i64 {
-        let length = match *self {
-            Batch::Insert(ref v) => v.len(),
-            Batch::Delete(ref v) => v.len(),
-            Batch::Update(ref v) => v.len(),
-        };
-
-        length as i64
+    /// Splits this batch into consecutive batches of at most `max_len` statements each.
+    /// An empty batch yields no batches.
+    pub fn split(self, max_len: usize) -> Vec<Batch> {
+        let max_len = if max_len == 0 { 1 } else { max_len };
+
+        match self {
+            Batch::Insert(docs) => {
+                docs.chunks(max_len).map(|chunk| Batch::Insert(chunk.to_vec())).collect()
+            }
+            Batch::Delete(models) => {
+                models.chunks(max_len).map(|chunk| Batch::Delete(chunk.to_vec())).collect()
+            }
+            Batch::Update(models) => {
+                models.chunks(max_len).map(|chunk| Batch::Update(chunk.to_vec())).collect()
+            }
+        }
     }
 
     /// Attempts to merge another model into this batch.
     ///
     /// # Arguments
     ///
+    ///  `index` - The index of the model in the bulk write.
     ///  `model` - The model to try to merge.
     ///
     /// # Return value
     ///
     /// Returns `None` on success, or the model that couldn't be merged on
     /// failure.
-    pub fn merge_model(&mut self, model: WriteModel) -> Option<WriteModel> {
-        match *self {
-            Batch::Insert(ref mut docs) => {
-                match model {
-                    WriteModel::InsertOne { document } => docs.push(document),
-                    _ => return Some(model),
-                }
-            }
-            Batch::Delete(ref mut models) => {
-                match model {
-                    WriteModel::DeleteOne { filter } => {
-                        models.push(DeleteModel {
-                            filter: filter,
-                            multi: false,
-                        })
-                    }
-                    WriteModel::DeleteMany { filter } => {
-                        models.push(DeleteModel {
-                            filter: filter,
-                            multi: true,
-                        })
-                    }
-                    _ => return Some(model),
-                }
-            }
-            Batch::Update(ref mut models) => {
-                match model {
-                    WriteModel::ReplaceOne {
-                        filter,
-                        replacement: update,
-                        upsert,
-                    } |
-                    WriteModel::UpdateOne {
-                        filter,
-                        update,
-                        upsert,
-                    } => {
-                        models.push(UpdateModel {
-                            filter: filter,
-                            update: update,
-                            upsert: upsert,
-                            multi: false,
-                        })
-                    }
-                    WriteModel::UpdateMany {
-                        filter,
-                        update,
-                        upsert,
-                    } => {
-                        models.push(UpdateModel {
-                            filter: filter,
-                            update: update,
-                            upsert: upsert,
-                            multi: true,
-                        })
-                    }
-                    _ => return Some(model),
-                }
-            }
+    pub fn merge_model(&mut self, index: i64, model: WriteModel) -> Option<WriteModel> {
+        if !self.accepts(&model) {
+            return Some(model);
+        }
+
+        match (self, Batch::new(index, model)) {
+            (&mut Batch::Insert(ref mut docs), Batch::Insert(new)) => docs.extend(new),
+            (&mut Batch::Delete(ref mut models), Batch::Delete(new)) => models.extend(new),
+            (&mut Batch::Update(ref mut models), Batch::Update(new)) => models.extend(new),
+            // Both batches hold the same kind of write, as checked above.
+            _ => (),
         }
 
         None
     }
+
+    // Returns whether the model is of the kind of write held by this batch.
+    fn accepts(&self, model: &WriteModel) -> bool {
+        match (self, model) {
+            (&Batch::Insert(_), &WriteModel::InsertOne { .. }) |
+            (&Batch::Delete(_), &WriteModel::DeleteOne { .. }) |
+            (&Batch::Delete(_), &WriteModel::DeleteMany { .. }) |
+            (&Batch::Update(_), &WriteModel::ReplaceOne { .. }) |
+            (&Batch::Update(_), &WriteModel::UpdateOne { .. }) |
+            (&Batch::Update(_), &WriteModel::UpdateMany { .. }) => true,
+            _ => false,
+        }
+    }
 }
@@ -1,8 +1,9 @@
 //! Write errors for collection-level operations.
 use bson::{self, Bson};
+use super::batch::original_index;
 use super::options::WriteModel;
 use common::WriteConcern;
-use {Error, Result};
+use {Error, ErrorCode, Result};
 use std::{error, fmt};
 
 /// The error type for Write-related MongoDB operations.
@@ -25,6 +26,8 @@ pub struct WriteConcernError {
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct WriteError {
     pub code: i32,
+    /// The name of the error code, if reported by the server.
+    pub code_name: Option<String>,
     pub message: String,
 }
 
@@ -44,10 +47,20 @@ pub struct BulkWriteException {
 pub struct BulkWriteError {
     pub index: i32,
     pub code: i32,
+    /// The name of the error code, if reported by the server.
+    pub code_name: Option<String>,
     pub message: String,
     pub request: Option<WriteModel>,
 }
 
+// Returns the name of the error code in a server error document, if any.
+fn parse_code_name(error: &bson::Document) -> Option<String> {
+    match error.get("codeName") {
+        Some(&Bson::String(ref code_name)) => Some(code_name.to_owned()),
+        _ => None,
+    }
+}
+
 impl error::Error for WriteException {
     fn description(&self) -> &str {
         &self.message
@@ -60,6 +73,18 @@ impl error::Error for BulkWriteException {
     }
 }
 
+impl error::Error for WriteConcernError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for WriteConcernError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "WriteConcernError (code {}): {}", self.code, self.message)
+    }
+}
+
 impl fmt::Display for WriteException {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.write_str("WriteException:\n")?;
@@ -141,11 +166,30 @@ impl WriteException {
     /// last write error to emulate the behavior of continue_on_error.
     pub fn with_bulk_exception(bulk_exception: BulkWriteException) -> WriteException {
         let mut write_errors = bulk_exception.write_errors;
-        let write_error = write_errors.pop().map(|e| WriteError::new(e.code, e.message));
+        let write_error = write_errors.pop().map(|e| {
+            WriteError {
+                code: e.code,
+                code_name: e.code_name,
+                message: e.message,
+            }
+        });
 
         WriteException::new(bulk_exception.write_concern_error, write_error)
     }
 
+    /// Converts the exception into an error, which is a plain write concern error if the write
+    /// itself succeeded.
+    pub fn into_error(self) -> Error {
+        match self {
+            WriteException {
+                write_error: None,
+                write_concern_error: Some(wc_err),
+                ..
+            } => Error::WriteConcernError(wc_err),
+            exception => Error::WriteError(exception),
+        }
+    }
+
     /// Validates a single-write result.
     pub fn validate_write_result(
         result: bson::Document,
@@ -153,13 +197,11 @@ impl WriteException {
     ) -> Result<()> {
         let bulk_err_result = BulkWriteException::validate_bulk_write_result(result, write_concern);
 
-        // Convert a bulk-write error into a write error, if it exists,
-        // or propagate any other results.
+        // Convert a bulk-write error into a write error, or a write concern error if the write
+        // was applied, or propagate any other results.
         match bulk_err_result {
             Err(Error::BulkWriteError(bulk_exception)) => {
-                Err(Error::WriteError(
-                    WriteException::with_bulk_exception(bulk_exception),
-                ))
+                Err(WriteException::with_bulk_exception(bulk_exception).into_error())
             }
             Err(err) => Err(err),
             Ok(()) => Ok(()),
@@ -168,6 +210,12 @@ impl WriteException {
 }
 
 impl WriteConcernError {
+    /// Returns whether the write concern timed out, e.g. before a majority of the replica set
+    /// acknowledged the write. The write was still applied on the primary.
+    pub fn is_timeout(&self) -> bool {
+        self.code == ErrorCode::WriteConcernFailed as i32
+    }
+
     /// Returns a new WriteConcernError containing the provided error information.
     pub fn new<T: ToString>(code: i32, details: WriteConcern, message: T) -> WriteConcernError {
         WriteConcernError {
@@ -196,6 +244,7 @@ impl WriteError {
     pub fn new<T: ToString>(code: i32, message: T) -> WriteError {
         WriteError {
             code: code,
+            code_name: None,
             message: message.to_string(),
         }
     }
@@ -204,7 +253,9 @@ impl WriteError {
     pub fn parse(error: bson::Document) -> Result<WriteError> {
         if let Some(&Bson::I32(code)) = error.get("code") {
             if let Some(&Bson::String(ref message)) = error.get("errmsg") {
-                return Ok(WriteError::new(code, message));
+                let mut write_error = WriteError::new(code, message);
+                write_error.code_name = parse_code_name(&error);
+                return Ok(write_error);
             }
         }
         Err(Error::ResponseError(
@@ -224,6 +275,7 @@ impl BulkWriteError {
         BulkWriteError {
             index: index,
             code: code,
+            code_name: None,
             message: message.to_string(),
             request: request,
         }
@@ -235,7 +287,9 @@ impl BulkWriteError {
             (Some(&Bson::I32(index)),
              Some(&Bson::I32(code)),
              Some(&Bson::String(ref message))) => {
-                Ok(BulkWriteError::new(index, code, message, None))
+                let mut write_error = BulkWriteError::new(index, code, message, None);
+                write_error.code_name = parse_code_name(&error);
+                Ok(write_error)
             }
             _ => Err(Error::ResponseError(
                 format!("WriteError document is invalid: {:?}", error),
@@ -282,10 +336,15 @@ impl BulkWriteException {
     }
 
     /// Adds the data contined by another BulkWriteException to this one.
+    ///
+    /// The other exception is for a batch holding `models`, whose indexes in the original bulk
+    /// write are given by `indexes`. Its write errors are mapped back to those indexes and
+    /// paired with the request that failed.
     pub fn add_bulk_write_exception(
         &mut self,
         exception_opt: Option<BulkWriteException>,
         models: Vec<WriteModel>,
+        indexes: &[i64],
     ) -> bool {
         let exception = match exception_opt {
             Some(exception) => exception,
@@ -304,7 +363,12 @@ impl BulkWriteException {
         }
 
         for err in &exception.write_errors {
-            self.write_errors.push(err.clone());
+            let mut err = err.clone();
+            if err.request.is_none() {
+                err.request = models.get(err.index as usize).cloned();
+            }
+            err.index = original_index(indexes, i64::from(err.index)) as i32;
+            self.write_errors.push(err);
         }
 
         if exception.write_concern_error.is_some() {
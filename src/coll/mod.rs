@@ -13,17 +13,211 @@ use self::options::*;
 use self::results::*;
 
 use ThreadedClient;
-use common::{merge_options, ReadPreference, WriteConcern};
-use cursor::Cursor;
+use common::{merge_options, validate_document_keys, validate_namespace, ReadPreference,
+             WriteConcern};
+use cursor::{Cursor, TailableCursor};
 use db::{Database, ThreadedDatabase};
 
 use Result;
-use Error::{ArgumentError, DecoderError, ResponseError, OperationError, BulkWriteError};
+use Error::{ArgumentError, DecoderError, IoError, ResponseError, OperationError,
+            BulkWriteError};
 
-use wire_protocol::flags::OpQueryFlags;
+use topology::monitor::DEFAULT_MAX_WRITE_BATCH_SIZE;
+use topology::server::ServerDescription;
+use wire_protocol::flags::{OpInsertFlags, OpQueryFlags};
+use wire_protocol::operations::Message;
 use std::collections::{BTreeMap, VecDeque};
 use std::iter::FromIterator;
 
+/// The minimum wire version that supports index hints on delete statements (MongoDB 4.4).
+pub const DELETE_HINT_MIN_WIRE_VERSION: i64 = 9;
+
+// Server error codes after which a write may be safely retried, e.g. a primary stepping down.
+const RETRYABLE_WRITE_ERROR_CODES: [i32; 12] = [
+    6, 7, 89, 91, 189, 262, 9001, 10107, 11600, 11602, 13435, 13436,
+];
+
+/// Returns whether a command failure message reports a primary step-down or a recovering node.
+///
+/// Command failures are reported as an OperationError carrying only the server's message, so
+/// step-downs are recognized by message text as described by the SDAM specification.
+pub fn is_state_change_message(msg: &str) -> bool {
+    msg.contains("not master") || msg.contains("not primary") || msg.contains("node is recovering")
+}
+
+/// Returns whether a write command reply reports an error that makes the write retryable.
+pub fn is_retryable_write_reply(reply: &bson::Document) -> bool {
+    let labeled = match reply.get("errorLabels") {
+        Some(&Bson::Array(ref labels)) => {
+            labels.iter().any(|label| match *label {
+                Bson::String(ref label) => label == "RetryableWriteError",
+                _ => false,
+            })
+        }
+        _ => false,
+    };
+
+    let code = |doc: &bson::Document| match doc.get("code") {
+        Some(&Bson::I32(code)) => RETRYABLE_WRITE_ERROR_CODES.contains(&code),
+        Some(&Bson::I64(code)) => RETRYABLE_WRITE_ERROR_CODES.contains(&(code as i32)),
+        _ => false,
+    };
+
+    let failed = match reply.get("ok") {
+        Some(&Bson::I32(ok)) => ok == 0,
+        Some(&Bson::I64(ok)) => ok == 0,
+        Some(&Bson::FloatingPoint(ok)) => ok == 0.0,
+        _ => false,
+    };
+
+    let write_concern_error = match reply.get("writeConcernError") {
+        Some(&Bson::Document(ref error)) => code(error),
+        _ => false,
+    };
+
+    labeled || (failed && code(reply)) || write_concern_error
+}
+
+/// Builds the `aggregate` command running `pipeline` against `aggregate`, which is either a
+/// collection name or `1` for a collection-less pipeline.
+///
+/// Without options, the server picks the first batch size. The read preference is not part of
+/// the command and is ignored.
+pub fn build_aggregate_command(
+    aggregate: Bson,
+    pipeline: Vec<bson::Document>,
+    options: Option<AggregateOptions>,
+) -> bson::Document {
+    let pipeline: Vec<_> = pipeline.into_iter().map(Bson::Document).collect();
+
+    let spec = doc! {
+        "aggregate": aggregate,
+        "pipeline": pipeline,
+    };
+
+    match options {
+        Some(options) => merge_options(spec, options),
+        None => {
+            let mut spec = spec;
+            spec.insert("cursor", bson::Document::new());
+            spec
+        }
+    }
+}
+
+/// Builds the `find` command for a query on `namespace`, independent of the wire message it
+/// is sent in.
+///
+/// Legacy `$`-prefixed modifiers are translated to their command fields, and are overridden by
+/// the equivalent explicit options. Modifiers without a command equivalent, such as `$explain`,
+/// are rejected with an `ArgumentError`. A negative limit requests a single batch. The read
+/// preference is not part of the command and is ignored.
+pub fn build_find_command(
+    namespace: &str,
+    filter: Option<bson::Document>,
+    options: &FindOptions,
+) -> Result<bson::Document> {
+    let coll_name = match namespace.find('.') {
+        Some(index) => &namespace[index + 1..],
+        None => namespace,
+    };
+
+    let mut command = doc! {
+        "find": coll_name,
+        "filter": filter.unwrap_or_default(),
+    };
+
+    if let Some(ref modifiers) = options.modifiers {
+        for (key, value) in modifiers.iter() {
+            let field = match &key[..] {
+                "$comment" => "comment",
+                "$hint" => "hint",
+                "$max" => "max",
+                "$maxScan" => "maxScan",
+                "$maxTimeMS" => "maxTimeMS",
+                "$min" => "min",
+                "$orderby" => "sort",
+                "$returnKey" => "returnKey",
+                "$showDiskLoc" => "showRecordId",
+                "$snapshot" => "snapshot",
+                _ => {
+                    return Err(ArgumentError(format!(
+                        "The query modifier '{}' has no find command equivalent.",
+                        key
+                    )))
+                }
+            };
+            command.insert(field, value.clone());
+        }
+    }
+
+    if let Some(ref projection) = options.projection {
+        command.insert("projection", projection.clone());
+    }
+
+    if let Some(ref sort) = options.sort {
+        command.insert("sort", sort.clone());
+    }
+
+    if let Some(skip) = options.skip {
+        command.insert("skip", skip);
+    }
+
+    if let Some(limit) = options.limit {
+        if limit < 0 {
+            command.insert("limit", -limit);
+            command.insert("singleBatch", true);
+        } else {
+            command.insert("limit", limit);
+        }
+    }
+
+    if let Some(batch_size) = options.batch_size {
+        command.insert("batchSize", batch_size);
+    }
+
+    if let Some(ref comment) = options.comment {
+        command.insert("comment", comment.to_owned());
+    }
+
+    if let Some(max_time_ms) = options.max_time_ms {
+        command.insert("maxTimeMS", max_time_ms);
+    }
+
+    if let Some(ref collation) = options.collation {
+        command.insert("collation", collation.clone());
+    }
+
+    if let Some(ref read_concern) = options.read_concern {
+        command.insert("readConcern", read_concern.to_document());
+    }
+
+    match options.cursor_type {
+        CursorType::NonTailable => (),
+        CursorType::Tailable => {
+            command.insert("tailable", true);
+        }
+        CursorType::TailableAwait => {
+            command.insert("tailable", true);
+            command.insert("awaitData", true);
+        }
+    }
+
+    if options.oplog_replay {
+        command.insert("oplogReplay", true);
+    }
+
+    if options.no_cursor_timeout {
+        command.insert("noCursorTimeout", true);
+    }
+
+    if options.allow_partial_results {
+        command.insert("allowPartialResults", true);
+    }
+
+    Ok(command)
+}
+
 /// Interfaces with a MongoDB collection.
 #[derive(Debug)]
 pub struct Collection {
@@ -33,6 +227,8 @@ pub struct Collection {
     pub namespace: String,
     read_preference: ReadPreference,
     write_concern: WriteConcern,
+    index_hints: BTreeMap<String, String>,
+    validate_keys: bool,
 }
 
 impl Collection {
@@ -60,9 +256,52 @@ impl Collection {
             namespace: format!("{}.{}", db.name, name),
             read_preference: rp,
             write_concern: wc,
+            index_hints: BTreeMap::new(),
+            validate_keys: false,
         }
     }
 
+    /// Opts into automatic index hints for queries on this collection.
+    ///
+    /// `hints` maps a field name to the name of an index on that field. When a find filter
+    /// consists of a single equality predicate on a mapped field, the query is sent with a
+    /// `$hint` for the corresponding index. Other filters are sent unchanged. Passing an empty
+    /// map turns automatic hints back off.
+    pub fn set_index_hints(&mut self, hints: BTreeMap<String, String>) {
+        self.index_hints = hints;
+    }
+
+    /// Opts into validating the keys of documents written through this collection.
+    ///
+    /// When enabled, inserted documents with a key containing a null byte or a top-level key
+    /// starting with `$` are rejected with an `ArgumentError` before being sent, as are update
+    /// documents with a key containing a null byte.
+    pub fn set_key_validation(&mut self, enabled: bool) {
+        self.validate_keys = enabled;
+    }
+
+    // Returns the index to hint for a filter with a single equality predicate on a field
+    // registered through `set_index_hints`.
+    fn index_hint_for(&self, filter: &bson::Document) -> Option<String> {
+        if filter.len() != 1 {
+            return None;
+        }
+
+        let (field, value) = filter.iter().next()?;
+        if field.starts_with('$') {
+            return None;
+        }
+
+        // Operator documents such as { $gt: 1 } are not equality predicates.
+        if let Bson::Document(ref doc) = *value {
+            if doc.keys().any(|key| key.starts_with('$')) {
+                return None;
+            }
+        }
+
+        self.index_hints.get(field).cloned()
+    }
+
     /// Returns a unique operational request id.
     pub fn get_req_id(&self) -> i32 {
         self.db.client.get_req_id()
@@ -90,33 +329,33 @@ impl Collection {
         self.db.drop_collection(&self.name())
     }
 
-    /// Runs an aggregation framework pipeline.
+    /// Runs an aggregation framework pipeline, returning a cursor over its results.
+    ///
+    /// The `allow_disk_use`, `batch_size`, `max_time_ms`, `collation`, `comment` and
+    /// `read_preference` options are honored. With `explain`, the cursor instead returns the
+    /// execution plan as its only document.
     pub fn aggregate(
         &self,
         pipeline: Vec<bson::Document>,
         options: Option<AggregateOptions>,
     ) -> Result<Cursor> {
-        let pipeline_map: Vec<_> = pipeline.into_iter().map(Bson::Document).collect();
-
-        let mut spec = doc! {
-            "aggregate": self.name(),
-            "pipeline": pipeline_map
-        };
-
-        let mut read_preference = self.read_preference.clone();
-
-        match options {
-            Some(aggregate_options) => {
-                if let Some(ref read_preference_option) = aggregate_options.read_preference {
-                    read_preference = read_preference_option.clone();
-                }
-
-                spec = merge_options(spec, aggregate_options);
-            }
-            None => {
-                spec.insert("cursor", bson::Document::new());
-            }
-        };
+        let read_preference = options
+            .as_ref()
+            .and_then(|opts| opts.read_preference.clone())
+            .unwrap_or_else(|| self.read_preference.clone());
+        let explain = options.as_ref().and_then(|opts| opts.explain) == Some(true);
+
+        let spec = build_aggregate_command(Bson::String(self.name()), pipeline, options);
+
+        if explain {
+            return Cursor::command_reply(
+                self.db.client.clone(),
+                &self.db.name,
+                spec,
+                CommandType::Aggregate,
+                read_preference,
+            );
+        }
 
         self.db.command_cursor(
             spec,
@@ -125,41 +364,123 @@ impl Collection {
         )
     }
 
-    /// Gets the number of documents matching the filter.
+    /// Gets the number of documents matching the filter. This is equivalent to
+    /// `count_documents`.
     pub fn count(
         &self,
         filter: Option<bson::Document>,
         options: Option<CountOptions>,
     ) -> Result<i64> {
+        self.count_documents(filter, options)
+    }
+
+    /// Gets the number of documents matching the filter.
+    ///
+    /// The legacy `count` command can report inaccurate results on sharded clusters, e.g. while
+    /// orphaned documents remain after a chunk migration, so this runs an aggregation that
+    /// matches and counts the documents instead. All of the options are honored.
+    pub fn count_documents(
+        &self,
+        filter: Option<bson::Document>,
+        options: Option<CountOptions>,
+    ) -> Result<i64> {
+        let options = options.unwrap_or_default();
+
+        let mut pipeline = vec![Bson::Document(doc! { "$match": filter.unwrap_or_default() })];
+
+        if let Some(skip) = options.skip {
+            pipeline.push(Bson::Document(doc! { "$skip": skip }));
+        }
+
+        // As with the legacy count command, a limit of 0 means no limit and a negative limit
+        // is treated as its absolute value; $limit accepts neither.
+        match options.limit {
+            Some(0) | None => (),
+            Some(limit) => pipeline.push(Bson::Document(doc! { "$limit": limit.abs() })),
+        }
+
+        pipeline.push(Bson::Document(doc! {
+            "$group": { "_id": 1, "n": { "$sum": 1 } }
+        }));
+
         let mut spec = doc! {
-            "count": self.name()
+            "aggregate": self.name(),
+            "pipeline": pipeline,
+            "cursor": bson::Document::new(),
         };
 
-        if let Some(filter_doc) = filter {
-            spec.insert("query", filter_doc);
+        match (options.hint, options.hint_doc) {
+            (_, Some(hint_doc)) => {
+                spec.insert("hint", hint_doc);
+            }
+            (Some(hint), None) => {
+                spec.insert("hint", hint);
+            }
+            (None, None) => (),
         }
 
-        let mut read_preference = self.read_preference.clone();
+        if let Some(max_time_ms) = options.max_time_ms {
+            spec.insert("maxTimeMS", max_time_ms);
+        }
 
-        if let Some(count_options) = options {
-            if let Some(ref read_preference_option) = count_options.read_preference {
-                read_preference = read_preference_option.clone();
-            }
+        if let Some(collation) = options.collation {
+            spec.insert("collation", collation);
+        }
 
-            spec = merge_options(spec, count_options);
+        if let Some(read_concern) = options.read_concern {
+            spec.insert("readConcern", read_concern.to_document());
         }
 
-        let result = self.db.command(
+        let read_preference = options.read_preference.unwrap_or_else(
+            || self.read_preference.clone(),
+        );
+
+        let mut cursor = self.db.command_cursor(
             spec,
-            CommandType::Count,
-            Some(read_preference),
+            CommandType::Aggregate,
+            read_preference,
         )?;
+
+        // An empty result means no documents matched.
+        match cursor.next() {
+            Some(Ok(result)) => {
+                match result.get("n") {
+                    Some(&Bson::I32(n)) => Ok(n as i64),
+                    Some(&Bson::I64(n)) => Ok(n),
+                    _ => Err(ResponseError(
+                        String::from("No count received from server."),
+                    )),
+                }
+            }
+            Some(Err(err)) => Err(err),
+            None => Ok(0),
+        }
+    }
+
+    /// Gets an estimate of the number of documents in the collection from its metadata, using
+    /// the legacy `count` command without a filter. This is fast, but may be inaccurate on
+    /// sharded clusters or after an unclean shutdown.
+    pub fn estimated_document_count(
+        &self,
+        options: Option<EstimatedDocumentCountOptions>,
+    ) -> Result<i64> {
+        let options = options.unwrap_or_default();
+        let mut spec = doc! { "count": self.name() };
+
+        if let Some(max_time_ms) = options.max_time_ms {
+            spec.insert("maxTimeMS", max_time_ms);
+        }
+
+        let read_preference = options.read_preference.unwrap_or_else(
+            || self.read_preference.clone(),
+        );
+
+        let result = self.db.command(spec, CommandType::Count, Some(read_preference))?;
         match result.get("n") {
             Some(&Bson::I32(n)) => Ok(n as i64),
             Some(&Bson::I64(n)) => Ok(n),
-            _ => Err(ResponseError(
-                String::from("No count received from server."),
-            )),
+            Some(&Bson::FloatingPoint(n)) => Ok(n as i64),
+            _ => Err(ResponseError(String::from("No count received from server."))),
         }
     }
 
@@ -170,6 +491,8 @@ impl Collection {
         filter: Option<bson::Document>,
         options: Option<DistinctOptions>,
     ) -> Result<Vec<Bson>> {
+        let options = options.unwrap_or_default();
+
         let mut spec = doc! {
             "distinct": self.name(),
             "key": field_name,
@@ -179,7 +502,19 @@ impl Collection {
             spec.insert("query", filter_doc);
         }
 
-        let read_preference = options.and_then(|o| o.read_preference).unwrap_or_else(|| {
+        if let Some(max_time_ms) = options.max_time_ms {
+            spec.insert("maxTimeMS", max_time_ms);
+        }
+
+        if let Some(collation) = options.collation {
+            spec.insert("collation", collation);
+        }
+
+        if let Some(read_concern) = options.read_concern {
+            spec.insert("readConcern", read_concern.to_document());
+        }
+
+        let read_preference = options.read_preference.unwrap_or_else(|| {
             self.read_preference.clone()
         });
 
@@ -205,6 +540,23 @@ impl Collection {
         self.find_with_command_type(filter, options, CommandType::Find)
     }
 
+    /// Tails a capped collection, returning a cursor that blocks waiting for new documents
+    /// that match the filter rather than finishing at the end of the collection.
+    ///
+    /// The cursor type of any provided options is overridden with `TailableAwait`.
+    pub fn tail(
+        &self,
+        filter: Option<bson::Document>,
+        options: Option<FindOptions>,
+    ) -> Result<TailableCursor> {
+        let options = FindOptions {
+            cursor_type: CursorType::TailableAwait,
+            ..options.unwrap_or_default()
+        };
+
+        self.find(filter, Some(options)).map(TailableCursor::new)
+    }
+
     fn find_with_command_type(
         &self,
         filter: Option<bson::Document>,
@@ -214,20 +566,39 @@ impl Collection {
         let find_options = options.unwrap_or_default();
         let flags = OpQueryFlags::with_find_options(&find_options);
 
-        let doc = match find_options.sort {
+        let filter = filter.unwrap_or_default();
+        let hint = self.index_hint_for(&filter);
+
+        let read_preference = match find_options.read_preference {
+            Some(ref read_preference_option) => read_preference_option.clone(),
+            None => self.read_preference.clone(),
+        };
+
+        // Legacy queries cannot carry a collation or read concern, so queries with either are
+        // sent as find commands.
+        if find_options.collation.is_some() || find_options.read_concern.is_some() {
+            let mut spec = build_find_command(&self.namespace, Some(filter), &find_options)?;
+            if let Some(hint) = hint {
+                spec.insert("hint", hint);
+            }
+
+            return self.db.command_cursor(spec, cmd_type, read_preference);
+        }
+
+        let mut doc = match find_options.sort {
             Some(ref sort_opt) => {
                 doc! {
-                    "$query": filter.unwrap_or_default(),
+                    "$query": filter,
                     "$orderby": sort_opt.clone(),
                 }
             }
-            None => filter.unwrap_or_default(),
+            None if hint.is_some() => doc! { "$query": filter },
+            None => filter,
         };
 
-        let read_preference = match find_options.read_preference {
-            Some(ref read_preference_option) => read_preference_option.clone(),
-            None => self.read_preference.clone(),
-        };
+        if let Some(hint) = hint {
+            doc.insert("$hint", hint);
+        }
 
         Cursor::query(
             self.db.client.clone(),
@@ -277,7 +648,6 @@ impl Collection {
         &self,
         filter: bson::Document,
         options: bson::Document,
-        _max_time_ms: Option<i64>,
         write_concern: Option<WriteConcern>,
         cmd_type: CommandType,
     ) -> Result<Option<bson::Document>> {
@@ -288,7 +658,7 @@ impl Collection {
 
         cmd = merge_options(cmd, options);
 
-        let res = self.db.command(cmd, cmd_type, None)?;
+        let res = self.write_command(cmd, cmd_type, true)?;
         let wc = write_concern.unwrap_or_else(|| self.write_concern.clone());
         WriteException::validate_write_result(res.clone(), wc)?;
 
@@ -306,10 +676,7 @@ impl Collection {
         filter: bson::Document,
         options: Option<FindOneAndDeleteOptions>,
     ) -> Result<Option<bson::Document>> {
-        let (max_time_ms, write_concern) = match options {
-            Some(ref opts) => (opts.max_time_ms, opts.write_concern.clone()),
-            None => (None, None),
-        };
+        let write_concern = options.as_ref().and_then(|opts| opts.write_concern.clone());
 
         let mut options_doc = doc! { "remove": true };
 
@@ -320,7 +687,6 @@ impl Collection {
         self.find_and_modify(
             filter,
             options_doc,
-            max_time_ms,
             write_concern,
             CommandType::FindOneAndDelete,
         )
@@ -336,10 +702,7 @@ impl Collection {
     ) -> Result<Option<bson::Document>> {
         Collection::validate_replace(&replacement)?;
 
-        let (max_time_ms, write_concern) = match options {
-            Some(ref opts) => (opts.max_time_ms, opts.write_concern.clone()),
-            None => (None, None),
-        };
+        let write_concern = options.as_ref().and_then(|opts| opts.write_concern.clone());
 
         let mut options_doc = doc! { "update": replacement };
 
@@ -350,7 +713,6 @@ impl Collection {
         self.find_and_modify(
             filter,
             options_doc,
-            max_time_ms,
             write_concern,
             CommandType::FindOneAndReplace,
         )
@@ -366,10 +728,7 @@ impl Collection {
     ) -> Result<Option<bson::Document>> {
         Collection::validate_update(&update)?;
 
-        let (max_time_ms, write_concern) = match options {
-            Some(ref opts) => (opts.max_time_ms, opts.write_concern.clone()),
-            None => (None, None),
-        };
+        let write_concern = options.as_ref().and_then(|opts| opts.write_concern.clone());
 
         let mut options_doc = doc! { "update": update };
 
@@ -380,76 +739,29 @@ impl Collection {
         self.find_and_modify(
             filter,
             options_doc,
-            max_time_ms,
             write_concern,
             CommandType::FindOneAndUpdate,
         )
     }
 
     fn get_unordered_batches(requests: Vec<WriteModel>) -> Vec<Batch> {
-        let mut inserts = Vec::new();
-        let mut deletes = Vec::new();
-        let mut updates = Vec::new();
-
-        for req in requests {
-            match req {
-                WriteModel::InsertOne { document } => inserts.push(document),
-                WriteModel::DeleteOne { filter } => {
-                    deletes.push(DeleteModel {
-                        filter: filter,
-                        multi: false,
-                    })
-                }
-                WriteModel::DeleteMany { filter } => {
-                    deletes.push(DeleteModel {
-                        filter: filter,
-                        multi: true,
-                    })
-                }
-                WriteModel::ReplaceOne {
-                    filter,
-                    replacement,
-                    upsert,
-                } => {
-                    updates.push(UpdateModel {
-                        filter: filter,
-                        update: replacement,
-                        upsert: upsert,
-                        multi: false,
-                    })
-                }
-                WriteModel::UpdateOne {
-                    filter,
-                    update,
-                    upsert,
-                } => {
-                    updates.push(UpdateModel {
-                        filter: filter,
-                        update: update,
-                        upsert: upsert,
-                        multi: false,
-                    })
-                }
-                WriteModel::UpdateMany {
-                    filter,
-                    update,
-                    upsert,
-                } => {
-                    updates.push(UpdateModel {
-                        filter: filter,
-                        update: update,
-                        upsert: upsert,
-                        multi: true,
-                    })
-                }
+        let mut batches = vec![
+            Batch::Insert(Vec::new()),
+            Batch::Delete(Vec::new()),
+            Batch::Update(Vec::new()),
+        ];
+
+        for (index, model) in requests.into_iter().enumerate() {
+            let mut unmerged = Some(model);
+            for batch in &mut batches {
+                unmerged = match unmerged {
+                    Some(model) => batch.merge_model(index as i64, model),
+                    None => break,
+                };
             }
         }
 
-        vec![
-            Batch::Insert(inserts),
-            Batch::Delete(deletes),
-            Batch::Update(updates),
-        ]
+        batches
     }
 
     fn get_ordered_batches(mut requests: VecDeque<WriteModel>) -> Vec<Batch> {
@@ -458,13 +770,14 @@ impl Collection {
             None => return Vec::new(),
         };
 
-        let mut batches = vec![Batch::from(first_model)];
+        let mut batches = vec![Batch::new(0, first_model)];
 
-        for model in requests {
+        for (index, model) in requests.into_iter().enumerate() {
+            let index = index as i64 + 1;
             let last_index = batches.len() - 1;
 
-            if let Some(model) = batches[last_index].merge_model(model) {
-                batches.push(Batch::from(model));
+            if let Some(model) = batches[last_index].merge_model(index, model) {
+                batches.push(Batch::new(index, model));
             }
         }
 
@@ -473,12 +786,13 @@ impl Collection {
 
     fn execute_insert_batch(
         &self,
-        documents: Vec<bson::Document>,
-        start_index: i64,
+        documents: Vec<(i64, bson::Document)>,
         ordered: bool,
         result: &mut BulkWriteResult,
         exception: &mut BulkWriteException,
     ) -> bool {
+        let (indexes, documents): (Vec<i64>, Vec<bson::Document>) =
+            documents.into_iter().unzip();
         let models = documents
             .iter()
             .cloned()
@@ -490,9 +804,9 @@ impl Collection {
             ..Default::default()
         });
 
-        match self.insert_many(documents, options) {
+        match self.insert_many_unchecked(documents, options) {
             Ok(insert_result) => {
-                result.process_insert_many_result(insert_result, models, start_index, exception)
+                result.process_insert_many_result(insert_result, models, &indexes, exception)
             }
             Err(_) => {
                 exception.add_unproccessed_models(models);
@@ -503,23 +817,37 @@ impl Collection {
 
     fn execute_delete_batch(
         &self,
-        models: Vec<DeleteModel>,
+        models: Vec<(i64, DeleteModel)>,
         ordered: bool,
         result: &mut BulkWriteResult,
         exception: &mut BulkWriteException,
     ) -> bool {
+        let (indexes, models): (Vec<i64>, Vec<DeleteModel>) = models.into_iter().unzip();
         let original_models = models
             .iter()
             .map(|model| if model.multi {
-                WriteModel::DeleteMany { filter: model.filter.clone() }
+                WriteModel::DeleteMany {
+                    filter: model.filter.clone(),
+                    collation: model.collation.clone(),
+                    hint: model.hint.clone(),
+                }
             } else {
-                WriteModel::DeleteOne { filter: model.filter.clone() }
+                WriteModel::DeleteOne {
+                    filter: model.filter.clone(),
+                    collation: model.collation.clone(),
+                    hint: model.hint.clone(),
+                }
             })
             .collect();
 
         match self.bulk_delete(models, ordered, None, CommandType::DeleteMany) {
             Ok(bulk_delete_result) => {
-                result.process_bulk_delete_result(bulk_delete_result, original_models, exception)
+                result.process_bulk_delete_result(
+                    bulk_delete_result,
+                    original_models,
+                    &indexes,
+                    exception,
+                )
             }
             Err(_) => {
                 exception.add_unproccessed_models(original_models);
@@ -530,12 +858,12 @@ impl Collection {
 
     fn execute_update_batch(
         &self,
-        models: Vec<UpdateModel>,
-        start_index: i64,
+        models: Vec<(i64, UpdateModel)>,
         ordered: bool,
         result: &mut BulkWriteResult,
         exception: &mut BulkWriteException,
     ) -> bool {
+        let (indexes, models): (Vec<i64>, Vec<UpdateModel>) = models.into_iter().unzip();
         let original_models = models
             .iter()
             .map(|model| if model.multi {
@@ -543,12 +871,16 @@ impl Collection {
                     filter: model.filter.clone(),
                     update: model.update.clone(),
                     upsert: model.upsert.clone(),
+                    collation: model.collation.clone(),
+                    hint: model.hint.clone(),
                 }
             } else {
                 WriteModel::UpdateOne {
                     filter: model.filter.clone(),
                     update: model.update.clone(),
                     upsert: model.upsert.clone(),
+                    collation: model.collation.clone(),
+                    hint: model.hint.clone(),
                 }
             })
             .collect();
@@ -558,7 +890,7 @@ impl Collection {
                 result.process_bulk_update_result(
                     bulk_update_result,
                     original_models,
-                    start_index,
+                    &indexes,
                     exception,
                 )
             }
@@ -572,23 +904,23 @@ impl Collection {
     fn execute_batch(
         &self,
         batch: Batch,
-        start_index: i64,
         ordered: bool,
         result: &mut BulkWriteResult,
         exception: &mut BulkWriteException,
     ) -> bool {
         match batch {
-            Batch::Insert(docs) => {
-                self.execute_insert_batch(docs, start_index, ordered, result, exception)
-            }
+            Batch::Insert(docs) => self.execute_insert_batch(docs, ordered, result, exception),
             Batch::Delete(models) => self.execute_delete_batch(models, ordered, result, exception),
-            Batch::Update(models) => {
-                self.execute_update_batch(models, start_index, ordered, result, exception)
-            }
+            Batch::Update(models) => self.execute_update_batch(models, ordered, result, exception),
         }
     }
 
     /// Sends a batch of writes to the server at the same time.
+    ///
+    /// Consecutive writes of the same kind are sent together in a single write command, split
+    /// to respect the server's `maxWriteBatchSize`. When `ordered` is true, execution stops at
+    /// the first failed batch; otherwise writes are grouped by kind and every batch is sent.
+    /// Write errors and unprocessed writes are reported in `bulk_write_exception`.
     pub fn bulk_write(&self, requests: Vec<WriteModel>, ordered: bool) -> BulkWriteResult {
         let batches = if ordered {
             Collection::get_ordered_batches(VecDeque::from_iter(requests.into_iter()))
@@ -596,30 +928,61 @@ impl Collection {
             Collection::get_unordered_batches(requests)
         };
 
+        let max_batch_size = self.max_write_batch_size();
+        let batches: Vec<_> = batches
+            .into_iter()
+            .flat_map(|batch| batch.split(max_batch_size))
+            .collect();
+
         let mut result = BulkWriteResult::new();
         let mut exception = BulkWriteException::new(Vec::new(), Vec::new(), Vec::new(), None);
 
-        let mut start_index = 0;
-
         for batch in batches {
-            let length = batch.len();
-            let success =
-                self.execute_batch(batch, start_index, ordered, &mut result, &mut exception);
+            let success = self.execute_batch(batch, ordered, &mut result, &mut exception);
 
             if !success && ordered {
                 break;
             }
-
-            start_index += length;
         }
 
-        if !exception.unprocessed_requests.is_empty() {
+        if !exception.unprocessed_requests.is_empty() || !exception.write_errors.is_empty() ||
+            exception.write_concern_error.is_some()
+        {
             result.bulk_write_exception = Some(exception);
         }
 
         result
     }
 
+    // Runs a write command. If retryable writes are enabled and the deployment supports them,
+    // the command is tagged with the client's session id and a new transaction number, and is
+    // sent once more with the same transaction number if it fails with a retryable error.
+    fn write_command(
+        &self,
+        mut cmd: bson::Document,
+        cmd_type: CommandType,
+        retryable: bool,
+    ) -> Result<bson::Document> {
+        let client = &self.db.client;
+        if !retryable || !client.retry_writes || !client.supports_retryable_writes() {
+            return self.db.command(cmd, cmd_type, None);
+        }
+
+        cmd.insert("lsid", client.session_id().clone());
+        cmd.insert("txnNumber", client.next_txn_number());
+
+        match self.db.command(cmd.clone(), cmd_type, None) {
+            Ok(ref reply) if is_retryable_write_reply(reply) => (),
+            Err(IoError(_)) => (),
+            Err(OperationError(ref msg)) if is_state_change_message(msg) => (),
+            result => return result,
+        }
+
+        // Retry once, selecting a server again after the monitors have rechecked the topology.
+        client.request_topology_update();
+        self.db.command(cmd, cmd_type, None)
+    }
+
     // Internal insertion helper function. Returns a vec of collected ids and a possible exception.
     fn insert(
         &self,
@@ -628,6 +991,11 @@ impl Collection {
         write_concern: Option<WriteConcern>,
         cmd_type: CommandType,
     ) -> Result<(Vec<Bson>, Option<BulkWriteException>)> {
+        if self.validate_keys {
+            for doc in &docs {
+                validate_document_keys(doc, false)?;
+            }
+        }
 
         let wc = write_concern.unwrap_or_else(|| self.write_concern.clone());
         let mut converted_docs = Vec::with_capacity(docs.len());
@@ -655,7 +1023,7 @@ impl Collection {
             cmd = merge_options(cmd, insert_options);
         }
 
-        let result = self.db.command(cmd, cmd_type, None)?;
+        let result = self.write_command(cmd, cmd_type, wc.w != 0)?;
 
         // Intercept bulk write exceptions and insert into the result
         let exception_res = BulkWriteException::validate_bulk_write_result(result.clone(), wc);
@@ -709,15 +1077,58 @@ impl Collection {
             None => Some(ids[0].to_owned()),
         };
 
+        self.check_write_concern_error(exception.as_ref())?;
         Ok(InsertOneResult::new(id, exception))
     }
 
+    /// Inserts the provided document without waiting for the server to acknowledge it (w: 0).
+    /// If the document is missing an identifier, the driver generates one.
+    ///
+    /// The document is sent as a legacy OP_INSERT and no reply is read, so the call returns as
+    /// soon as the message is written to the socket. Any server-side failure, such as a duplicate
+    /// key or a primary stepping down, is silently lost, and the write may not survive a crash.
+    /// Only use this for data that can be dropped, like logs or metrics.
+    pub fn insert_one_unacknowledged(&self, mut doc: bson::Document) -> Result<()> {
+        validate_namespace(&self.namespace)?;
+
+        if self.validate_keys {
+            validate_document_keys(&doc, false)?;
+        }
+
+        if !doc.contains_key("_id") {
+            doc.insert("_id", oid::ObjectId::new()?);
+        }
+
+        let message = Message::new_insert(
+            self.get_req_id(),
+            OpInsertFlags::empty(),
+            self.namespace.clone(),
+            vec![doc],
+        )?;
+        message.check_document_depth(self.db.client.max_document_depth)?;
+
+        let mut stream = self.db.client.acquire_write_stream()?;
+        stream.write_message(&message)
+    }
+
     /// Inserts the provided documents. If any documents are missing an identifier,
     /// the driver should generate them.
     pub fn insert_many(
         &self,
         docs: Vec<bson::Document>,
         options: Option<InsertManyOptions>,
+    ) -> Result<InsertManyResult> {
+        let result = self.insert_many_unchecked(docs, options)?;
+        self.check_bulk_write_concern_error(result.bulk_write_exception.as_ref())?;
+        Ok(result)
+    }
+
+    // Inserts the provided documents, attaching any write concern error to the result
+    // regardless of client configuration.
+    fn insert_many_unchecked(
+        &self,
+        docs: Vec<bson::Document>,
+        options: Option<InsertManyOptions>,
     ) -> Result<InsertManyResult> {
         let write_concern = options.as_ref().map_or(
             None,
@@ -753,13 +1164,30 @@ impl Collection {
         cmd_type: CommandType,
     ) -> Result<BulkDeleteResult> {
 
+        if models.iter().any(|model| model.hint.is_some()) {
+            self.check_delete_hint_support()?;
+        }
+
         let wc = write_concern.unwrap_or_else(|| self.write_concern.clone());
+        let retryable = wc.w != 0 && models.iter().all(|model| !model.multi);
         let deletes: Vec<_> = models
             .into_iter()
-            .map(|model| bson!({
-                "q": model.filter,
-                "limit": if model.multi { 0_i64 } else { 1_i64 },
-            }))
+            .map(|model| {
+                let mut statement = doc! {
+                    "q": model.filter,
+                    "limit": if model.multi { 0_i64 } else { 1_i64 },
+                };
+
+                if let Some(collation) = model.collation {
+                    statement.insert("collation", collation);
+                }
+
+                if let Some(hint) = model.hint {
+                    statement.insert("hint", hint);
+                }
+
+                Bson::Document(statement)
+            })
             .collect();
 
         let cmd = doc! {
@@ -768,7 +1196,7 @@ impl Collection {
             "ordered": ordered,
             "writeConcern": wc.to_bson(),
         };
-        let result = self.db.command(cmd, cmd_type, None)?;
+        let result = self.write_command(cmd, cmd_type, retryable)?;
 
         // Intercept write exceptions and insert into the result
         let exception_res = BulkWriteException::validate_bulk_write_result(result.clone(), wc);
@@ -781,12 +1209,44 @@ impl Collection {
         Ok(BulkDeleteResult::new(result, exception))
     }
 
+    // Returns the last known description of the server that would receive a write.
+    fn write_server_description(&self) -> Option<ServerDescription> {
+        let client = &self.db.client;
+        let host = client.select_write_server().ok()?;
+        client.server_description(&host)
+    }
+
+    // Returns the max number of statements per write command accepted by the server that would
+    // receive a write.
+    fn max_write_batch_size(&self) -> usize {
+        match self.write_server_description() {
+            Some(ref description) if description.max_write_batch_size > 0 => {
+                description.max_write_batch_size as usize
+            }
+            _ => DEFAULT_MAX_WRITE_BATCH_SIZE as usize,
+        }
+    }
+
+    // Returns an error if the server that would receive a write does not support delete hints.
+    fn check_delete_hint_support(&self) -> Result<()> {
+        let max_wire_version = self.write_server_description()
+            .map_or(0, |description| description.max_wire_version);
+
+        match max_wire_version {
+            version if version >= DELETE_HINT_MIN_WIRE_VERSION => Ok(()),
+            _ => Err(ArgumentError(String::from(
+                "The server does not support hints on delete statements; \
+                 MongoDB 4.4 or newer is required.",
+            ))),
+        }
+    }
+
     // Internal deletion helper function.
     fn delete(
         &self,
         filter: bson::Document,
         multi: bool,
-        write_concern: Option<WriteConcern>,
+        options: DeleteOptions,
     ) -> Result<DeleteResult> {
         let cmd_type = if multi {
             CommandType::DeleteMany
@@ -794,14 +1254,25 @@ impl Collection {
             CommandType::DeleteOne
         };
 
-        self.bulk_delete(
-            vec![DeleteModel::new(filter, multi)],
+        let mut model = DeleteModel::new(filter, multi);
+        model.collation = options.collation;
+        model.hint = match (options.hint, options.hint_doc) {
+            (_, Some(hint_doc)) => Some(Bson::Document(hint_doc)),
+            (Some(hint), None) => Some(Bson::String(hint)),
+            (None, None) => None,
+        };
+
+        let result = self.bulk_delete(
+            vec![model],
             true,
-            write_concern,
+            options.write_concern,
             cmd_type,
         ).map(
             DeleteResult::with_bulk_result
-        )
+        )?;
+
+        self.check_write_concern_error(result.write_exception.as_ref())?;
+        Ok(result)
     }
 
     /// Deletes a single document.
@@ -810,7 +1281,20 @@ impl Collection {
         filter: bson::Document,
         write_concern: Option<WriteConcern>,
     ) -> Result<DeleteResult> {
-        self.delete(filter, false, write_concern)
+        let options = DeleteOptions {
+            write_concern: write_concern,
+            ..Default::default()
+        };
+        self.delete(filter, false, options)
+    }
+
+    /// Deletes a single document, using the provided delete options.
+    pub fn delete_one_with_options(
+        &self,
+        filter: bson::Document,
+        options: Option<DeleteOptions>,
+    ) -> Result<DeleteResult> {
+        self.delete(filter, false, options.unwrap_or_default())
     }
 
     /// Deletes multiple documents.
@@ -819,7 +1303,20 @@ impl Collection {
         filter: bson::Document,
         write_concern: Option<WriteConcern>,
     ) -> Result<DeleteResult> {
-        self.delete(filter, true, write_concern)
+        let options = DeleteOptions {
+            write_concern: write_concern,
+            ..Default::default()
+        };
+        self.delete(filter, true, options)
+    }
+
+    /// Deletes multiple documents, using the provided delete options.
+    pub fn delete_many_with_options(
+        &self,
+        filter: bson::Document,
+        options: Option<DeleteOptions>,
+    ) -> Result<DeleteResult> {
+        self.delete(filter, true, options.unwrap_or_default())
     }
 
     // Sends a batch of replace and update ops to the server at once.
@@ -831,6 +1328,7 @@ impl Collection {
         cmd_type: CommandType,
     ) -> Result<BulkUpdateResult> {
         let wc = write_concern.unwrap_or_else(|| self.write_concern.clone());
+        let retryable = wc.w != 0 && models.iter().all(|model| !model.multi);
         let updates: Vec<_> = models
             .into_iter()
             .map(|model| Bson::Document(bson::Document::from(model)))
@@ -843,7 +1341,7 @@ impl Collection {
             "writeConcern": wc.to_bson()
         };
 
-        let result = self.db.command(cmd, cmd_type, None)?;
+        let result = self.write_command(cmd, cmd_type, retryable)?;
 
         // Intercept write exceptions and insert into the result
         let exception_res = BulkWriteException::validate_bulk_write_result(result.clone(), wc);
@@ -861,10 +1359,12 @@ impl Collection {
         &self,
         filter: bson::Document,
         update: bson::Document,
-        upsert: Option<bool>,
         multi: bool,
-        write_concern: Option<WriteConcern>,
+        options: UpdateOptions,
     ) -> Result<UpdateResult> {
+        if self.validate_keys {
+            validate_document_keys(&update, true)?;
+        }
 
         let cmd_type = if multi {
             CommandType::UpdateMany
@@ -872,14 +1372,25 @@ impl Collection {
             CommandType::UpdateOne
         };
 
-        self.bulk_update(
-            vec![UpdateModel::new(filter, update, upsert, multi)],
+        let mut model = UpdateModel::new(filter, update, options.upsert, multi);
+        model.collation = options.collation;
+        model.hint = match (options.hint, options.hint_doc) {
+            (_, Some(hint_doc)) => Some(Bson::Document(hint_doc)),
+            (Some(hint), None) => Some(Bson::String(hint)),
+            (None, None) => None,
+        };
+
+        let result = self.bulk_update(
+            vec![model],
             true,
-            write_concern,
+            options.write_concern,
             cmd_type,
         ).map(
             UpdateResult::with_bulk_result
-        )
+        )?;
+
+        self.check_write_concern_error(result.write_exception.as_ref())?;
+        Ok(result)
     }
 
     /// Replaces a single document.
@@ -893,13 +1404,7 @@ impl Collection {
 
         Collection::validate_replace(&replacement)?;
 
-        self.update(
-            filter,
-            replacement,
-            options.upsert,
-            false,
-            options.write_concern,
-        )
+        self.update(filter, replacement, false, options)
     }
 
     /// Updates a single document.
@@ -913,13 +1418,7 @@ impl Collection {
 
         Collection::validate_update(&update)?;
 
-        self.update(
-            filter,
-            update,
-            options.upsert,
-            false,
-            options.write_concern
-        )
+        self.update(filter, update, false, options)
     }
 
     /// Updates multiple documents.
@@ -933,32 +1432,61 @@ impl Collection {
 
         Collection::validate_update(&update)?;
 
-        self.update(
-            filter,
-            update,
-            options.upsert,
-            true,
-            options.write_concern
-        )
+        self.update(filter, update, true, options)
+    }
+
+    // Returns a write exception containing a write concern error as an error, unless the
+    // client is configured to attach write concern errors to write results.
+    fn check_write_concern_error(&self, exception: Option<&WriteException>) -> Result<()> {
+        match exception {
+            Some(exc) if exc.write_concern_error.is_some() &&
+                             !self.db.client.attach_write_concern_errors => {
+                Err(exc.clone().into_error())
+            }
+            _ => Ok(()),
+        }
     }
 
+    // Bulk counterpart of `check_write_concern_error`.
+    fn check_bulk_write_concern_error(&self, exception: Option<&BulkWriteException>) -> Result<()> {
+        match exception {
+            Some(exc) if exc.write_concern_error.is_some() &&
+                             !self.db.client.attach_write_concern_errors => {
+                Err(BulkWriteError(exc.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Replacement documents are stored as given, so they may not contain update operators.
     fn validate_replace(replacement: &bson::Document) -> Result<()> {
         for key in replacement.keys() {
             if key.starts_with('$') {
-                return Err(ArgumentError(
-                    String::from("Replacement cannot include $ operators."),
-                ));
+                return Err(ArgumentError(format!(
+                    "Replacement cannot include $ operators, but found '{}'; use update_one to \
+                     apply update operators.",
+                    key
+                )));
             }
         }
         Ok(())
     }
 
+    // Update documents must consist of at least one update operator, and nothing else.
     fn validate_update(update: &bson::Document) -> Result<()> {
+        if update.is_empty() {
+            return Err(ArgumentError(
+                String::from("Update must include at least one $ operator."),
+            ));
+        }
+
         for key in update.keys() {
             if !key.starts_with('$') {
-                return Err(ArgumentError(
-                    String::from("Update only works with $ operators."),
-                ));
+                return Err(ArgumentError(format!(
+                    "Update only works with $ operators, but found '{}'; use replace_one to \
+                     replace a document.",
+                    key
+                )));
             }
         }
         Ok(())
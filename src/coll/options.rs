@@ -1,6 +1,6 @@
 //! Options for collection-level operations.
 use bson::{self, Bson, bson, doc};
-use common::{ReadPreference, WriteConcern};
+use common::{ReadConcern, ReadPreference, WriteConcern};
 use Error::ArgumentError;
 use Result;
 
@@ -35,35 +35,62 @@ impl ReturnDocument {
 }
 
 /// Marker interface for writes that can be batched together.
+///
+/// The `collation` and `hint` of a delete or update are sent with that statement alone; a hint
+/// is either an index name or an index specification.
 #[derive(Debug, Clone, PartialEq)]
 pub enum WriteModel {
     InsertOne { document: bson::Document },
-    DeleteOne { filter: bson::Document },
-    DeleteMany { filter: bson::Document },
+    DeleteOne {
+        filter: bson::Document,
+        collation: Option<bson::Document>,
+        hint: Option<Bson>,
+    },
+    DeleteMany {
+        filter: bson::Document,
+        collation: Option<bson::Document>,
+        hint: Option<Bson>,
+    },
     ReplaceOne {
         filter: bson::Document,
         replacement: bson::Document,
         upsert: Option<bool>,
+        collation: Option<bson::Document>,
+        hint: Option<Bson>,
     },
     UpdateOne {
         filter: bson::Document,
         update: bson::Document,
         upsert: Option<bool>,
+        collation: Option<bson::Document>,
+        hint: Option<Bson>,
     },
     UpdateMany {
         filter: bson::Document,
         update: bson::Document,
         upsert: Option<bool>,
+        collation: Option<bson::Document>,
+        hint: Option<Bson>,
     },
 }
 
 /// Options for aggregation queries.
-#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+///
+/// These options implement `PartialEq` but not `Eq`, `Ord` or `Hash`, since `collation` holds an
+/// arbitrary BSON document.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct AggregateOptions {
     pub allow_disk_use: Option<bool>,
     pub use_cursor: Option<bool>,
     pub batch_size: i32,
     pub max_time_ms: Option<i64>,
+    /// The collation to use for string comparisons in the pipeline.
+    pub collation: Option<bson::Document>,
+    /// Whether to return the pipeline's execution plan instead of running it.
+    pub explain: Option<bool>,
+    /// A comment to help trace the operation in the profiler and server logs.
+    pub comment: Option<String>,
+    pub read_concern: Option<ReadConcern>,
     pub read_preference: Option<ReadPreference>,
 }
 
@@ -83,13 +110,31 @@ impl From<AggregateOptions> for bson::Document {
 
         // useCursor not currently used by the driver.
 
+        // Explained pipelines reply with a plan rather than a cursor.
+        if options.explain == Some(true) {
+            document.insert("explain", true);
+        } else {
+            let cursor = doc! { "batchSize": options.batch_size };
+            document.insert("cursor", cursor);
+        }
 
-        let cursor = doc! { "batchSize": options.batch_size };
-        document.insert("cursor", cursor);
+        if let Some(max_time_ms) = options.max_time_ms {
+            document.insert("maxTimeMS", max_time_ms);
+        }
+
+        if let Some(collation) = options.collation {
+            document.insert("collation", collation);
+        }
+
+        if let Some(comment) = options.comment {
+            document.insert("comment", comment);
+        }
 
-        // maxTimeMS is not currently used by the driver.
+        if let Some(read_concern) = options.read_concern {
+            document.insert("readConcern", read_concern.to_document());
+        }
 
-        // read_preference is used directly by Collection::aggregate.
+        // read_preference is used directly by Collection::aggregate and Database::aggregate.
 
         document
     }
@@ -103,6 +148,9 @@ pub struct CountOptions {
     pub hint: Option<String>,
     pub hint_doc: Option<bson::Document>,
     pub max_time_ms: Option<i64>,
+    /// The collation to use for string comparisons in the filter.
+    pub collation: Option<bson::Document>,
+    pub read_concern: Option<ReadConcern>,
     pub read_preference: Option<ReadPreference>,
 }
 
@@ -112,38 +160,28 @@ impl CountOptions {
     }
 }
 
-impl From<CountOptions> for bson::Document {
-    fn from(options: CountOptions) -> Self {
-        let mut document = bson::Document::new();
-
-        if let Some(skip) = options.skip {
-            document.insert("skip", skip);
-        }
-
-        if let Some(limit) = options.limit {
-            document.insert("limit", limit);
-        }
-
-        if let Some(hint) = options.hint {
-            document.insert("hint", hint);
-        }
-
-        if let Some(hint_doc) = options.hint_doc {
-            document.insert("hint_doc", hint_doc);
-        }
-
-        // maxTimeMS is not currently used by the driver.
-
-        // read_preference is used directly by Collection::count.
+/// Options for estimated document counts.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EstimatedDocumentCountOptions {
+    pub max_time_ms: Option<i64>,
+    pub read_preference: Option<ReadPreference>,
+}
 
-        document
+impl EstimatedDocumentCountOptions {
+    pub fn new() -> Self {
+        Default::default()
     }
 }
 
 /// Options for distinct queries.
-#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+///
+/// As with `AggregateOptions`, the `collation` document limits these options to `PartialEq`.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct DistinctOptions {
     pub max_time_ms: Option<i64>,
+    /// The collation to use for string comparisons.
+    pub collation: Option<bson::Document>,
+    pub read_concern: Option<ReadConcern>,
     pub read_preference: Option<ReadPreference>,
 }
 
@@ -168,6 +206,11 @@ pub struct FindOptions {
     pub modifiers: Option<bson::Document>,
     pub projection: Option<bson::Document>,
     pub sort: Option<bson::Document>,
+    /// The collation to use for string comparisons in the filter and sort. Queries with a
+    /// collation are sent as `find` commands.
+    pub collation: Option<bson::Document>,
+    /// The read concern of the query. Queries with a read concern are sent as `find` commands.
+    pub read_concern: Option<ReadConcern>,
     pub read_preference: Option<ReadPreference>,
 }
 
@@ -209,6 +252,14 @@ impl From<FindOptions> for bson::Document {
             document.insert("sort", sort);
         }
 
+        if let Some(collation) = options.collation {
+            document.insert("collation", collation);
+        }
+
+        if let Some(read_concern) = options.read_concern {
+            document.insert("readConcern", read_concern.to_document());
+        }
+
         document
     }
 }
@@ -220,6 +271,8 @@ pub struct FindOneAndDeleteOptions {
     pub projection: Option<bson::Document>,
     pub sort: Option<bson::Document>,
     pub write_concern: Option<WriteConcern>,
+    /// The collation to use for string comparisons in the filter and sort.
+    pub collation: Option<bson::Document>,
 }
 
 impl FindOneAndDeleteOptions {
@@ -232,7 +285,9 @@ impl From<FindOneAndDeleteOptions> for bson::Document {
     fn from(options: FindOneAndDeleteOptions) -> Self {
         let mut document = bson::Document::new();
 
-        // max_time_ms is not currently used by the driver
+        if let Some(max_time_ms) = options.max_time_ms {
+            document.insert("maxTimeMS", max_time_ms);
+        }
 
         if let Some(projection) = options.projection {
             document.insert("fields", projection);
@@ -246,6 +301,10 @@ impl From<FindOneAndDeleteOptions> for bson::Document {
             document.insert("writeConcern", write_concern.to_bson());
         }
 
+        if let Some(collation) = options.collation {
+            document.insert("collation", collation);
+        }
+
         document
     }
 }
@@ -259,6 +318,8 @@ pub struct FindOneAndUpdateOptions {
     pub sort: Option<bson::Document>,
     pub upsert: Option<bool>,
     pub write_concern: Option<WriteConcern>,
+    /// The collation to use for string comparisons in the filter and sort.
+    pub collation: Option<bson::Document>,
 }
 
 impl FindOneAndUpdateOptions {
@@ -275,7 +336,9 @@ impl From<FindOneAndUpdateOptions> for bson::Document {
             document.insert("new", return_document.as_bool());
         }
 
-        // max_time_ms is not currently used by the driver
+        if let Some(max_time_ms) = options.max_time_ms {
+            document.insert("maxTimeMS", max_time_ms);
+        }
 
         if let Some(projection) = options.projection {
             document.insert("fields", projection);
@@ -293,6 +356,10 @@ impl From<FindOneAndUpdateOptions> for bson::Document {
             document.insert("writeConcern", write_concern.to_bson());
         }
 
+        if let Some(collation) = options.collation {
+            document.insert("collation", collation);
+        }
+
         document
     }
 }
@@ -321,6 +388,14 @@ pub struct IndexOptions {
     #[serde(rename="v", skip_serializing_if="Option::is_none")]
     pub version: Option<i32>,
 
+    /// Restricts the index to documents matching this filter.
+    #[serde(rename="partialFilterExpression", skip_serializing_if="Option::is_none")]
+    pub partial_filter_expression: Option<bson::Document>,
+
+    /// The collation used for string comparisons on the indexed fields.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub collation: Option<bson::Document>,
+
     // Options for text indexes
     #[serde(skip_serializing_if="Option::is_none")]
     pub default_language: Option<String>,
@@ -440,6 +515,12 @@ impl IndexModel {
         if let Some(val) = self.options.version {
             doc.insert("v", val);
         }
+        if let Some(ref val) = self.options.partial_filter_expression {
+            doc.insert("partialFilterExpression", val.clone());
+        }
+        if let Some(ref val) = self.options.collation {
+            doc.insert("collation", val.clone());
+        }
         if let Some(ref val) = self.options.default_language {
             doc.insert("default_language", val);
         }
@@ -502,10 +583,19 @@ impl From<InsertManyOptions> for bson::Document {
 }
 
 /// Options for update operations.
-#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+///
+/// Because `collation` and `hint_doc` are BSON documents, which may hold floating-point values,
+/// these options are only `PartialEq`; they no longer implement `Eq` or `Hash`.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct UpdateOptions {
     pub upsert: Option<bool>,
     pub write_concern: Option<WriteConcern>,
+    /// The collation to use for this update statement.
+    pub collation: Option<bson::Document>,
+    /// The name of the index to use for this update statement.
+    pub hint: Option<String>,
+    /// The index specification to use for this update statement; takes precedence over `hint`.
+    pub hint_doc: Option<bson::Document>,
 }
 
 impl UpdateOptions {
@@ -516,6 +606,24 @@ impl UpdateOptions {
 
 pub type ReplaceOptions = UpdateOptions;
 
+/// Options for delete operations.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeleteOptions {
+    pub write_concern: Option<WriteConcern>,
+    /// The collation to use for this delete statement.
+    pub collation: Option<bson::Document>,
+    /// The name of the index to use for this delete statement.
+    pub hint: Option<String>,
+    /// The index specification to use for this delete statement; takes precedence over `hint`.
+    pub hint_doc: Option<bson::Document>,
+}
+
+impl DeleteOptions {
+    pub fn new() -> DeleteOptions {
+        Default::default()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -530,6 +638,8 @@ mod test {
         opts.storage_engine = Some(doc!{"mmapv1": true}); // Not sure about the actual shape `:)`.
         opts.unique = Some(true);
         opts.version = Some(2);
+        opts.partial_filter_expression = Some(doc!{"test_field": {"$exists": true}});
+        opts.collation = Some(doc!{"locale": "en", "strength": 2});
         opts.default_language = Some("en_us".to_string());
         opts.language_override = Some("en_us".to_string());
         opts.text_version = Some(3);
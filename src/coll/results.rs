@@ -2,6 +2,7 @@
 use bson;
 use bson::Bson;
 use std::collections::BTreeMap;
+use super::batch::original_index;
 use super::error::{BulkWriteException, WriteException};
 use super::options::WriteModel;
 
@@ -92,9 +93,10 @@ impl BulkWriteResult {
         &mut self,
         result: BulkDeleteResult,
         models: Vec<WriteModel>,
+        indexes: &[i64],
         exception: &mut BulkWriteException,
     ) -> bool {
-        let ok = exception.add_bulk_write_exception(result.write_exception, models);
+        let ok = exception.add_bulk_write_exception(result.write_exception, models, indexes);
         self.deleted_count += result.deleted_count;
 
         ok
@@ -105,14 +107,15 @@ impl BulkWriteResult {
         &mut self,
         result: InsertManyResult,
         models: Vec<WriteModel>,
-        start_index: i64,
+        indexes: &[i64],
         exception: &mut BulkWriteException,
     ) -> bool {
-        let ok = exception.add_bulk_write_exception(result.bulk_write_exception, models);
+        let ok =
+            exception.add_bulk_write_exception(result.bulk_write_exception, models, indexes);
 
         if let Some(ids) = result.inserted_ids {
             for (i, id) in ids {
-                self.inserted_ids.insert(start_index + i, id);
+                self.inserted_ids.insert(original_index(indexes, i), id);
                 self.inserted_count += 1;
             }
         }
@@ -124,18 +127,18 @@ impl BulkWriteResult {
     // the tree of upserted ids.
     fn parse_upserted_id(
         mut document: bson::Document,
-        start_index: i64,
+        indexes: &[i64],
         upserted_ids: &mut BTreeMap<i64, Bson>,
     ) -> i32 {
         let (index, id) = (document.remove("index"), document.remove("_id"));
 
         match (index, id) {
             (Some(Bson::I32(i)), Some(bson_id)) => {
-                let _ = upserted_ids.insert(start_index + i as i64, bson_id);
+                let _ = upserted_ids.insert(original_index(indexes, i as i64), bson_id);
                 1
             }
             (Some(Bson::I64(i)), Some(bson_id)) => {
-                let _ = upserted_ids.insert(start_index + i, bson_id.clone());
+                let _ = upserted_ids.insert(original_index(indexes, i), bson_id.clone());
                 1
             }
             _ => 0,
@@ -146,19 +149,19 @@ impl BulkWriteResult {
     // them to the tree of upserted ids.
     fn parse_upserted_ids(
         bson: Bson,
-        start_index: i64,
+        indexes: &[i64],
         upserted_ids: &mut BTreeMap<i64, Bson>,
     ) -> i32 {
         match bson {
             Bson::Document(doc) => {
-                BulkWriteResult::parse_upserted_id(doc, start_index, upserted_ids)
+                BulkWriteResult::parse_upserted_id(doc, indexes, upserted_ids)
             }
             Bson::Array(vec) => {
                 let mut count = 0;
 
                 for bson in vec {
                     if let Bson::Document(doc) = bson {
-                        count += BulkWriteResult::parse_upserted_id(doc, start_index, upserted_ids)
+                        count += BulkWriteResult::parse_upserted_id(doc, indexes, upserted_ids)
                     }
                 }
 
@@ -173,10 +176,10 @@ impl BulkWriteResult {
         &mut self,
         result: BulkUpdateResult,
         models: Vec<WriteModel>,
-        start_index: i64,
+        indexes: &[i64],
         exception: &mut BulkWriteException,
     ) -> bool {
-        let ok = exception.add_bulk_write_exception(result.write_exception, models);
+        let ok = exception.add_bulk_write_exception(result.write_exception, models, indexes);
 
         self.matched_count += result.matched_count;
         self.modified_count += result.modified_count;
@@ -184,7 +187,7 @@ impl BulkWriteResult {
         if let Some(upserted_ids) = result.upserted_ids {
             self.upserted_count += BulkWriteResult::parse_upserted_ids(
                 upserted_ids,
-                start_index,
+                indexes,
                 &mut self.upserted_ids,
             );
         }
@@ -3,8 +3,10 @@
 /// Executable command types that can be monitored by the driver.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum CommandType {
+    AbortTransaction,
     Aggregate,
     BuildInfo,
+    CommitTransaction,
     Count,
     CreateCollection,
     CreateIndexes,
@@ -37,8 +39,10 @@ pub enum CommandType {
 impl CommandType {
     pub fn to_str(&self) -> &str {
         match *self {
+            CommandType::AbortTransaction => "abort_transaction",
             CommandType::Aggregate => "aggregate",
             CommandType::BuildInfo => "buildinfo",
+            CommandType::CommitTransaction => "commit_transaction",
             CommandType::Count => "count",
             CommandType::CreateCollection => "create_collection",
             CommandType::CreateIndexes => "create_indexes",
@@ -71,6 +75,8 @@ impl CommandType {
 
     pub fn is_write_command(&self) -> bool {
         match *self {
+            CommandType::AbortTransaction |
+            CommandType::CommitTransaction |
             CommandType::CreateCollection |
             CommandType::CreateIndexes |
             CommandType::CreateUser |
@@ -3,6 +3,7 @@ use Error::{self, ArgumentError};
 use Result;
 
 use bson::{self, Bson, bson, doc};
+use db::EXTERNAL_AUTH_DB;
 use std::collections::BTreeMap;
 use std::str::FromStr;
 
@@ -34,12 +35,17 @@ impl FromStr for ReadMode {
     }
 }
 
+/// The smallest allowed `max_staleness_seconds`, per the max staleness specification.
+pub const MIN_MAX_STALENESS_SECONDS: i64 = 90;
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ReadPreference {
     /// Indicates how a server should be selected during read operations.
     pub mode: ReadMode,
     /// Filters servers based on the first tag set that matches at least one server.
     pub tag_sets: Vec<BTreeMap<String, String>>,
+    /// The maximum replication lag, in seconds, of a secondary that may be selected.
+    pub max_staleness_seconds: Option<i64>,
 }
 
 impl ReadPreference {
@@ -47,9 +53,22 @@ impl ReadPreference {
         ReadPreference {
             mode: mode,
             tag_sets: tag_sets.unwrap_or_else(Vec::new),
+            max_staleness_seconds: None,
         }
     }
 
+    /// Returns the read preference equivalent to the legacy secondaryOk (slaveOk) query flag:
+    /// `SecondaryPreferred` if the flag is set, and `Primary` otherwise.
+    pub fn from_secondary_ok(secondary_ok: bool) -> ReadPreference {
+        let mode = if secondary_ok {
+            ReadMode::SecondaryPreferred
+        } else {
+            ReadMode::Primary
+        };
+
+        ReadPreference::new(mode, None)
+    }
+
     pub fn to_document(&self) -> bson::Document {
         let mut doc = doc! { "mode": stringify!(self.mode).to_ascii_lowercase() };
         let bson_tag_sets: Vec<_> = self.tag_sets
@@ -64,6 +83,11 @@ impl ReadPreference {
             .collect();
 
         doc.insert("tag_sets", Bson::Array(bson_tag_sets));
+
+        if let Some(max_staleness_seconds) = self.max_staleness_seconds {
+            doc.insert("maxStalenessSeconds", max_staleness_seconds);
+        }
+
         doc
     }
 }
@@ -105,6 +129,211 @@ impl Default for WriteConcern {
     }
 }
 
+/// The isolation level of a read concern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReadConcernLevel {
+    Local,
+    Available,
+    Majority,
+    Linearizable,
+    Snapshot,
+}
+
+impl ReadConcernLevel {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ReadConcernLevel::Local => "local",
+            ReadConcernLevel::Available => "available",
+            ReadConcernLevel::Majority => "majority",
+            ReadConcernLevel::Linearizable => "linearizable",
+            ReadConcernLevel::Snapshot => "snapshot",
+        }
+    }
+}
+
+/// A read concern, which may carry a level, an `afterClusterTime`, or both. Leaving the level
+/// unset uses the server's default level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ReadConcern {
+    pub level: Option<ReadConcernLevel>,
+    /// Reads wait until the server has observed this cluster time.
+    pub after_cluster_time: Option<Timestamp>,
+}
+
+impl ReadConcern {
+    pub fn new() -> ReadConcern {
+        ReadConcern {
+            level: None,
+            after_cluster_time: None,
+        }
+    }
+
+    pub fn with_level(level: ReadConcernLevel) -> ReadConcern {
+        ReadConcern {
+            level: Some(level),
+            after_cluster_time: None,
+        }
+    }
+
+    pub fn after_cluster_time(cluster_time: Timestamp) -> ReadConcern {
+        ReadConcern {
+            level: None,
+            after_cluster_time: Some(cluster_time),
+        }
+    }
+
+    /// Returns the `readConcern` document, containing only the fields that are set.
+    pub fn to_document(&self) -> bson::Document {
+        let mut doc = bson::Document::new();
+
+        if let Some(level) = self.level {
+            doc.insert("level", level.as_str());
+        }
+
+        if let Some(cluster_time) = self.after_cluster_time {
+            doc.insert("afterClusterTime", cluster_time);
+        }
+
+        doc
+    }
+}
+
+/// A BSON timestamp split into its seconds and increment components.
+///
+/// Ordering compares seconds first and then the increment, matching the server's ordering of
+/// operation times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch.
+    pub seconds: u32,
+    /// Ordinal of the operation within the given second.
+    pub increment: u32,
+}
+
+impl Timestamp {
+    pub fn new(seconds: u32, increment: u32) -> Timestamp {
+        Timestamp {
+            seconds: seconds,
+            increment: increment,
+        }
+    }
+
+    /// Splits the raw value of a `Bson::TimeStamp`.
+    pub fn from_raw(raw: i64) -> Timestamp {
+        Timestamp::new((raw >> 32) as u32, raw as u32)
+    }
+
+    /// Returns the raw value stored in a `Bson::TimeStamp`.
+    pub fn to_raw(&self) -> i64 {
+        (i64::from(self.seconds) << 32) | i64::from(self.increment)
+    }
+
+    /// Reads a timestamp out of a BSON value, returning `None` if it is not a `Bson::TimeStamp`.
+    pub fn from_bson(bson: &Bson) -> Option<Timestamp> {
+        match *bson {
+            Bson::TimeStamp(raw) => Some(Timestamp::from_raw(raw)),
+            _ => None,
+        }
+    }
+
+    pub fn to_bson(&self) -> Bson {
+        Bson::TimeStamp(self.to_raw())
+    }
+}
+
+impl From<Timestamp> for Bson {
+    fn from(timestamp: Timestamp) -> Bson {
+        timestamp.to_bson()
+    }
+}
+
+/// Checks that a `database.collection` namespace names a non-empty database and collection,
+/// neither containing a null byte or `$`. The only names allowed to contain `$` are the
+/// `$external` database used for external authentication, the `$cmd` collection used for
+/// commands and the `oplog.$main` collection of master/slave replication.
+pub fn validate_namespace(namespace: &str) -> Result<()> {
+    let (db_name, coll_name) = match namespace.find('.') {
+        Some(index) => (&namespace[..index], &namespace[index + 1..]),
+        None => {
+            return Err(ArgumentError(format!(
+                "Namespace '{}' must be of the form 'database.collection'.",
+                namespace
+            )))
+        }
+    };
+
+    if db_name != EXTERNAL_AUTH_DB {
+        validate_name("database", db_name)?;
+    }
+
+    match coll_name {
+        "$cmd" | "oplog.$main" => Ok(()),
+        _ => validate_name("collection", coll_name),
+    }
+}
+
+fn validate_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(ArgumentError(format!("The {} name must not be empty.", kind)));
+    }
+
+    if name.contains('\0') || name.contains('$') {
+        return Err(ArgumentError(format!(
+            "The {} name '{}' must not contain a null byte or '$'.",
+            kind,
+            name.replace('\0', "\\0")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Validates the keys of a document before it is sent to the server.
+///
+/// Keys containing a null byte cannot be encoded and are always rejected, at any depth. Unless
+/// `allow_operators` is set, as for update documents, top-level keys starting with `$` are
+/// rejected too, since they are not valid field names in stored documents.
+pub fn validate_document_keys(doc: &bson::Document, allow_operators: bool) -> Result<()> {
+    for key in doc.keys() {
+        if !allow_operators && key.starts_with('$') {
+            return Err(ArgumentError(format!(
+                "The key '{}' must not start with '$' in a stored document.",
+                key
+            )));
+        }
+    }
+
+    validate_nested_keys(doc)
+}
+
+fn validate_nested_keys(doc: &bson::Document) -> Result<()> {
+    for (key, value) in doc.iter() {
+        if key.contains('\0') {
+            return Err(ArgumentError(format!(
+                "The key '{}' must not contain a null byte.",
+                key.replace('\0', "\\0")
+            )));
+        }
+
+        validate_value_keys(value)?;
+    }
+
+    Ok(())
+}
+
+fn validate_value_keys(value: &Bson) -> Result<()> {
+    match *value {
+        Bson::Document(ref doc) => validate_nested_keys(doc),
+        Bson::Array(ref values) => {
+            for value in values {
+                validate_value_keys(value)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
 pub fn merge_options<T: Into<bson::Document>>(
     document: bson::Document,
     options: T,
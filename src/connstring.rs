@@ -1,10 +1,22 @@
 //! Connection string parsing and options.
 use Result;
-use Error::ArgumentError;
+use Error::{self, ArgumentError};
+#[cfg(feature = "dns")]
+use Error::OperationError;
+use common::{ReadMode, ReadPreference, MIN_MAX_STALENESS_SECONDS};
+use pool::DEFAULT_POOL_SIZE;
 use std::collections::BTreeMap;
+use stream::StreamConnector;
+#[cfg(feature = "ssl")]
+use stream::TlsOptions;
+
+#[cfg(feature = "dns")]
+use trust_dns_resolver::Resolver;
 
 pub const DEFAULT_PORT: u16 = 27017;
 pub const URI_SCHEME: &'static str = "mongodb://";
+pub const SRV_URI_SCHEME: &'static str = "mongodb+srv://";
+pub const MAX_APP_NAME_LENGTH: usize = 128;
 
 /// Encapsulates the hostname and port of a host.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -75,6 +87,48 @@ pub struct ConnectionString {
     pub database: Option<String>,
     pub collection: Option<String>,
     pub options: Option<ConnectionOptions>,
+    /// The name of the replica set to connect to, from the `replicaSet` option.
+    pub replica_set: Option<String>,
+    /// The database to authenticate against, from the `authSource` option.
+    pub auth_source: Option<String>,
+    /// The application name sent to the server during the handshake, from the `appName` option.
+    pub app_name: Option<String>,
+    /// The connection timeout in milliseconds, from the `connectTimeoutMS` option.
+    pub connect_timeout_ms: Option<u64>,
+    /// The maximum number of connections per server pool, from the `maxPoolSize` option.
+    pub max_pool_size: Option<usize>,
+    /// The number of connections kept open per server, from the `minPoolSize` option.
+    pub min_pool_size: Option<usize>,
+    /// How long a pooled connection may remain idle before it is closed, from the
+    /// `maxIdleTimeMS` option.
+    pub max_idle_time_ms: Option<u64>,
+    /// How long a pooled connection may remain idle before it is pinged to keep it alive, from
+    /// the driver-specific `keepAliveIntervalMS` option. Keep-alives are disabled by default.
+    pub keep_alive_interval_ms: Option<u64>,
+    /// Whether to connect with TLS, from the `tls` or `ssl` option.
+    pub tls: Option<bool>,
+    /// The file of trusted CA certificates, from the `tlsCAFile` option.
+    pub tls_ca_file: Option<String>,
+    /// The file containing the client certificate and private key, from the
+    /// `tlsCertificateKeyFile` option.
+    pub tls_certificate_key_file: Option<String>,
+    /// Whether to skip verification of the server certificate, from the
+    /// `tlsAllowInvalidCertificates` option.
+    pub tls_allow_invalid_certificates: Option<bool>,
+    /// Whether to skip verification of the server hostname, from the
+    /// `tlsAllowInvalidHostnames` option.
+    pub tls_allow_invalid_hostnames: Option<bool>,
+    /// Whether to skip verification of both the server certificate and hostname, from the
+    /// `tlsInsecure` option.
+    pub tls_insecure: Option<bool>,
+    /// The read preference mode, from the `readPreference` option.
+    pub read_preference_mode: Option<ReadMode>,
+    /// The maximum replication lag of eligible secondaries, from the `maxStalenessSeconds`
+    /// option.
+    pub max_staleness_seconds: Option<i64>,
+    /// Whether to retry writes once after a network error or primary step-down, from the
+    /// `retryWrites` option.
+    pub retry_writes: Option<bool>,
 }
 
 impl ConnectionString {
@@ -93,21 +147,245 @@ impl ConnectionString {
             database: Some(String::from("test")),
             collection: None,
             options: None,
+            replica_set: None,
+            auth_source: None,
+            app_name: None,
+            connect_timeout_ms: None,
+            max_pool_size: None,
+            min_pool_size: None,
+            max_idle_time_ms: None,
+            keep_alive_interval_ms: None,
+            tls: None,
+            tls_ca_file: None,
+            tls_certificate_key_file: None,
+            tls_allow_invalid_certificates: None,
+            tls_allow_invalid_hostnames: None,
+            tls_insecure: None,
+            read_preference_mode: None,
+            max_staleness_seconds: None,
+            retry_writes: None,
         }
     }
+
+    /// Returns the stream connector described by the TLS options of the connection string,
+    /// or `None` if TLS was not requested.
+    pub fn stream_connector(&self) -> Result<Option<StreamConnector>> {
+        if !self.tls.unwrap_or(false) {
+            return Ok(None);
+        }
+
+        self.tls_stream_connector().map(Some)
+    }
+
+    #[cfg(feature = "ssl")]
+    fn tls_stream_connector(&self) -> Result<StreamConnector> {
+        let insecure = self.tls_insecure.unwrap_or(false);
+        let mut options = TlsOptions::new()
+            .verify_peer(!self.tls_allow_invalid_certificates.unwrap_or(insecure))
+            .verify_hostname(!self.tls_allow_invalid_hostnames.unwrap_or(insecure));
+
+        if let Some(ref file) = self.tls_ca_file {
+            options = options.ca_file(file);
+        }
+        if let Some(ref file) = self.tls_certificate_key_file {
+            options = options.client_certificate(file, file);
+        }
+
+        Ok(StreamConnector::with_tls(options))
+    }
+
+    // The OpenSSL backend is preferred when both are enabled, since only it can relax
+    // certificate and hostname verification.
+    #[cfg(all(feature = "tls-rustls", not(feature = "ssl")))]
+    fn tls_stream_connector(&self) -> Result<StreamConnector> {
+        let relaxed = [
+            ("tlsAllowInvalidCertificates", self.tls_allow_invalid_certificates),
+            ("tlsAllowInvalidHostnames", self.tls_allow_invalid_hostnames),
+            ("tlsInsecure", self.tls_insecure),
+        ];
+        if let Some(&(name, _)) = relaxed.iter().find(|&&(_, value)| value == Some(true)) {
+            return Err(ArgumentError(format!(
+                "{} is not supported by the 'tls-rustls' backend, which always verifies the \
+                 server's certificate and hostname.",
+                name
+            )));
+        }
+
+        let ca_file = self.tls_ca_file.as_deref();
+        let connector = match self.tls_certificate_key_file {
+            Some(ref file) => StreamConnector::with_rustls(ca_file, file, file)?,
+            None => StreamConnector::with_unauthenticated_rustls(ca_file)?,
+        };
+        Ok(connector)
+    }
+
+    #[cfg(not(any(feature = "ssl", feature = "tls-rustls")))]
+    fn tls_stream_connector(&self) -> Result<StreamConnector> {
+        Err(ArgumentError(String::from(
+            "TLS connections require the 'ssl' or 'tls-rustls' feature to be enabled.",
+        )))
+    }
+
+    /// Returns the read preference described by the `readPreference`, `readPreferenceTags`,
+    /// and `maxStalenessSeconds` options, or `None` if no mode was given.
+    pub fn read_preference(&self) -> Option<ReadPreference> {
+        let mode = self.read_preference_mode?;
+        let tag_sets = self.options
+            .as_ref()
+            .map_or_else(Vec::new, |options| {
+                options.read_pref_tags.iter().map(|tags| parse_tag_set(tags)).collect()
+            });
+
+        let mut read_preference = ReadPreference::new(mode, Some(tag_sets));
+        read_preference.max_staleness_seconds = self.max_staleness_seconds;
+        Some(read_preference)
+    }
+
+    // Populates the typed option fields from the parsed connection options.
+    fn set_typed_options(&mut self) -> Result<()> {
+        let options = match self.options {
+            Some(ref options) => options.options.clone(),
+            None => return Ok(()),
+        };
+
+        for (key, val) in options {
+            match key.to_ascii_lowercase().as_str() {
+                "replicaset" => self.replica_set = Some(val),
+                "authsource" => self.auth_source = Some(val),
+                "appname" => {
+                    if val.len() > MAX_APP_NAME_LENGTH {
+                        return Err(ArgumentError(format!(
+                            "appName must not exceed {} bytes.",
+                            MAX_APP_NAME_LENGTH
+                        )));
+                    }
+                    self.app_name = Some(val);
+                }
+                "connecttimeoutms" => self.connect_timeout_ms = Some(parse_number(&key, &val)?),
+                "maxpoolsize" => {
+                    let size = parse_number(&key, &val)?;
+                    if size == 0 {
+                        return Err(ArgumentError(
+                            String::from("maxPoolSize must be greater than zero."),
+                        ));
+                    }
+                    self.max_pool_size = Some(size as usize);
+                }
+                "minpoolsize" => self.min_pool_size = Some(parse_number(&key, &val)? as usize),
+                "maxidletimems" => self.max_idle_time_ms = Some(parse_number(&key, &val)?),
+                "keepaliveintervalms" => {
+                    let interval = parse_number(&key, &val)?;
+                    if interval == 0 {
+                        return Err(ArgumentError(
+                            String::from("keepAliveIntervalMS must be greater than zero."),
+                        ));
+                    }
+                    self.keep_alive_interval_ms = Some(interval);
+                }
+                "tls" | "ssl" => {
+                    let tls = parse_bool(&key, &val)?;
+                    if self.tls.map_or(false, |other| other != tls) {
+                        return Err(ArgumentError(String::from(
+                            "The 'tls' and 'ssl' options must not have conflicting values.",
+                        )));
+                    }
+                    self.tls = Some(tls);
+                }
+                "tlscafile" => self.tls_ca_file = Some(val),
+                "tlscertificatekeyfile" => self.tls_certificate_key_file = Some(val),
+                "tlsallowinvalidcertificates" => {
+                    self.tls_allow_invalid_certificates = Some(parse_bool(&key, &val)?)
+                }
+                "tlsallowinvalidhostnames" => {
+                    self.tls_allow_invalid_hostnames = Some(parse_bool(&key, &val)?)
+                }
+                "tlsinsecure" => self.tls_insecure = Some(parse_bool(&key, &val)?),
+                "readpreference" => self.read_preference_mode = Some(parse_read_mode(&val)?),
+                "maxstalenessseconds" => {
+                    let seconds = val.parse::<i64>().map_err(|_| {
+                        ArgumentError(format!(
+                            "The '{}' option must be an integer, but '{}' was given.",
+                            key,
+                            val
+                        ))
+                    })?;
+
+                    // A value of -1 explicitly means there is no maximum.
+                    self.max_staleness_seconds = if seconds == -1 { None } else { Some(seconds) };
+                }
+                "retrywrites" => self.retry_writes = Some(parse_bool(&key, &val)?),
+                _ => (),
+            }
+        }
+
+        if let Some(min_pool_size) = self.min_pool_size {
+            if min_pool_size > self.max_pool_size.unwrap_or(DEFAULT_POOL_SIZE) {
+                return Err(ArgumentError(
+                    String::from("minPoolSize must not exceed maxPoolSize."),
+                ));
+            }
+        }
+
+        if let Some(seconds) = self.max_staleness_seconds {
+            if seconds < MIN_MAX_STALENESS_SECONDS {
+                return Err(ArgumentError(format!(
+                    "maxStalenessSeconds must be at least {}, but {} was given.",
+                    MIN_MAX_STALENESS_SECONDS,
+                    seconds
+                )));
+            }
+
+            if self.read_preference_mode.unwrap_or(ReadMode::Primary) == ReadMode::Primary {
+                return Err(ArgumentError(String::from(
+                    "maxStalenessSeconds cannot be used with the primary read preference.",
+                )));
+            }
+        }
+
+        // tlsInsecure implies both of the more specific options, so it must agree with them.
+        if let Some(insecure) = self.tls_insecure {
+            let conflicts = |option: Option<bool>| option.map_or(false, |val| val != insecure);
+            if conflicts(self.tls_allow_invalid_certificates) ||
+                conflicts(self.tls_allow_invalid_hostnames)
+            {
+                return Err(ArgumentError(String::from(
+                    "The 'tlsInsecure' option conflicts with 'tlsAllowInvalidCertificates' or \
+                     'tlsAllowInvalidHostnames'.",
+                )));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// Parses a MongoDB connection string URI as defined by
 /// [the manual](http://docs.mongodb.org/manual/reference/connection-string/).
+///
+/// `mongodb+srv://` connection strings are also accepted when the `dns` feature is enabled;
+/// the seed list is then resolved from the `_mongodb._tcp` SRV records of the given host, and
+/// default options are read from its TXT record.
 pub fn parse(address: &str) -> Result<ConnectionString> {
-    if !address.starts_with(URI_SCHEME) {
-        return Err(ArgumentError(String::from(
-            "MongoDB connection string must start with 'mongodb://'.",
+    let srv = address.starts_with(SRV_URI_SCHEME);
+    if !srv && !address.starts_with(URI_SCHEME) {
+        // Only the scheme is echoed back, since the rest may contain credentials.
+        let given = match address.find("://") {
+            Some(idx) => format!("the scheme '{}://' was given", &address[..idx]),
+            None => String::from("no scheme was given"),
+        };
+
+        return Err(ArgumentError(format!(
+            "MongoDB connection string must start with 'mongodb://' or 'mongodb+srv://', but {}.",
+            given
         )));
     }
 
     // Remove scheme
-    let addr = &address[URI_SCHEME.len()..];
+    let addr = if srv {
+        &address[SRV_URI_SCHEME.len()..]
+    } else {
+        &address[URI_SCHEME.len()..]
+    };
 
     let hosts: Vec<Host>;
     let mut user: Option<String> = None;
@@ -137,14 +415,25 @@ pub fn parse(address: &str) -> Result<ConnectionString> {
     }
 
     // Split on authentication and hosts
-    if host_str.contains('@') {
+    let host_string = if host_str.contains('@') {
         let (user_info, host_string) = rpartition(host_str, "@");
         let (u, p) = parse_user_info(user_info)?;
         user = Some(String::from(u));
         password = Some(String::from(p));
-        hosts = split_hosts(host_string)?;
+        host_string
+    } else {
+        host_str
+    };
+
+    if srv {
+        if host_string.contains(',') || host_string.contains(':') {
+            return Err(ArgumentError(String::from(
+                "A 'mongodb+srv://' connection string must contain exactly one host name and no port.",
+            )));
+        }
+        hosts = resolve_srv_hosts(&host_string.to_ascii_lowercase())?;
     } else {
-        hosts = split_hosts(host_str)?;
+        hosts = split_hosts(host_string)?;
     }
 
     let mut opts = "";
@@ -164,10 +453,21 @@ pub fn parse(address: &str) -> Result<ConnectionString> {
 
     // Collect options if any exist
     if !opts.is_empty() {
-        options = Some(split_options(opts).unwrap());
+        options = Some(split_options(opts)?);
     }
 
-    Ok(ConnectionString {
+    if srv {
+        let host_name = host_string.to_ascii_lowercase();
+        let mut srv_options = match options {
+            Some(options) => options,
+            None => ConnectionOptions::new(BTreeMap::new(), Vec::new()),
+        };
+
+        apply_srv_defaults(&mut srv_options, resolve_txt_options(&host_name)?);
+        options = Some(srv_options);
+    }
+
+    let mut connstring = ConnectionString {
         hosts: hosts,
         string: Some(String::from(address)),
         user: user,
@@ -175,9 +475,231 @@ pub fn parse(address: &str) -> Result<ConnectionString> {
         database: database,
         collection: collection,
         options: options,
+        replica_set: None,
+        auth_source: None,
+        app_name: None,
+        connect_timeout_ms: None,
+        max_pool_size: None,
+        min_pool_size: None,
+        max_idle_time_ms: None,
+        keep_alive_interval_ms: None,
+        tls: None,
+        tls_ca_file: None,
+        tls_certificate_key_file: None,
+        tls_allow_invalid_certificates: None,
+        tls_allow_invalid_hostnames: None,
+        tls_insecure: None,
+        read_preference_mode: None,
+        max_staleness_seconds: None,
+        retry_writes: None,
+    };
+
+    connstring.set_typed_options()?;
+    Ok(connstring)
+}
+
+// Parses the value of a boolean connection string option.
+fn parse_bool(key: &str, val: &str) -> Result<bool> {
+    match val {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(ArgumentError(format!(
+            "The '{}' option must be 'true' or 'false', but '{}' was given.",
+            key,
+            val
+        ))),
+    }
+}
+
+// Parses the value of the readPreference connection string option.
+fn parse_read_mode(val: &str) -> Result<ReadMode> {
+    match val.to_ascii_lowercase().as_str() {
+        "primary" => Ok(ReadMode::Primary),
+        "primarypreferred" => Ok(ReadMode::PrimaryPreferred),
+        "secondary" => Ok(ReadMode::Secondary),
+        "secondarypreferred" => Ok(ReadMode::SecondaryPreferred),
+        "nearest" => Ok(ReadMode::Nearest),
+        _ => Err(ArgumentError(
+            format!("'{}' is not a valid readPreference mode.", val),
+        )),
+    }
+}
+
+// Parses a readPreferenceTags value such as 'dc:ny,rack:1' into a tag set.
+fn parse_tag_set(tags: &str) -> BTreeMap<String, String> {
+    tags.split(',')
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| {
+            let (key, val) = partition(tag, ":");
+            (String::from(key), String::from(val))
+        })
+        .collect()
+}
+
+// Parses the value of a numeric connection string option.
+fn parse_number(key: &str, val: &str) -> Result<u64> {
+    val.parse::<u64>().map_err(|_| {
+        ArgumentError(format!(
+            "The '{}' option must be a non-negative integer, but '{}' was given.",
+            key,
+            val
+        ))
     })
 }
 
+// Resolves the seed list of a 'mongodb+srv://' connection string. Every returned host must
+// share the parent domain of the SRV host name.
+#[cfg(feature = "dns")]
+fn resolve_srv_hosts(host_name: &str) -> Result<Vec<Host>> {
+    let parent_domain = srv_parent_domain(host_name)?;
+    let resolver = dns_resolver()?;
+
+    let lookup = resolver
+        .srv_lookup(format!("_mongodb._tcp.{}", host_name).as_str())
+        .map_err(|err| {
+            OperationError(format!("Failed to look up SRV record for {}: {}", host_name, err))
+        })?;
+
+    let mut hosts = Vec::new();
+    for record in lookup.iter() {
+        let target = record.target().to_utf8();
+        let target = target.trim_end_matches('.').to_ascii_lowercase();
+
+        if !target.ends_with(&format!(".{}", parent_domain)) {
+            return Err(ArgumentError(format!(
+                "SRV record target '{}' is not a subdomain of '{}'.",
+                target,
+                parent_domain
+            )));
+        }
+
+        hosts.push(Host::new(target, record.port()));
+    }
+
+    if hosts.is_empty() {
+        return Err(OperationError(
+            format!("No SRV records were found for {}.", host_name),
+        ));
+    }
+
+    Ok(hosts)
+}
+
+#[cfg(not(feature = "dns"))]
+fn resolve_srv_hosts(_: &str) -> Result<Vec<Host>> {
+    Err(ArgumentError(String::from(
+        "'mongodb+srv://' connection strings require the 'dns' feature to be enabled.",
+    )))
+}
+
+// Adds the default options of a 'mongodb+srv://' connection string: those from the TXT record,
+// and TLS. Options given in the connection string take precedence, whatever their case.
+fn apply_srv_defaults(options: &mut ConnectionOptions, txt_options: Option<ConnectionOptions>) {
+    if let Some(txt_options) = txt_options {
+        for (key, val) in txt_options.options {
+            if !contains_option(&options.options, &key) {
+                options.options.insert(key, val);
+            }
+        }
+    }
+
+    if !contains_option(&options.options, "ssl") && !contains_option(&options.options, "tls") {
+        options.options.insert(String::from("ssl"), String::from("true"));
+    }
+}
+
+// Returns whether an option is set, matching its name case-insensitively.
+fn contains_option(options: &BTreeMap<String, String>, name: &str) -> bool {
+    options.keys().any(|key| key.eq_ignore_ascii_case(name))
+}
+
+// Resolves the default options of a 'mongodb+srv://' connection string from the TXT record
+// of the SRV host name, if one exists. Only 'authSource' and 'replicaSet' may be specified.
+#[cfg(feature = "dns")]
+fn resolve_txt_options(host_name: &str) -> Result<Option<ConnectionOptions>> {
+    use trust_dns_resolver::error::ResolveErrorKind;
+
+    let resolver = dns_resolver()?;
+    let lookup = match resolver.txt_lookup(host_name) {
+        Ok(lookup) => lookup,
+        Err(ref err) if is_no_records_found(err.kind()) => return Ok(None),
+        Err(err) => {
+            return Err(OperationError(
+                format!("Failed to look up TXT record for {}: {}", host_name, err),
+            ))
+        }
+    };
+
+    fn is_no_records_found(kind: &ResolveErrorKind) -> bool {
+        match *kind {
+            ResolveErrorKind::NoRecordsFound { .. } => true,
+            _ => false,
+        }
+    }
+
+    let mut records = lookup.iter();
+    let record = match records.next() {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    if records.next().is_some() {
+        return Err(ArgumentError(format!(
+            "Multiple TXT records were found for {}.",
+            host_name
+        )));
+    }
+
+    let mut txt = String::new();
+    for data in record.txt_data() {
+        txt.push_str(&String::from_utf8_lossy(data));
+    }
+
+    if txt.is_empty() {
+        return Ok(None);
+    }
+
+    let options = split_options(&txt)?;
+    for key in options.options.keys() {
+        match key.to_ascii_lowercase().as_str() {
+            "authsource" | "replicaset" => (),
+            _ => {
+                return Err(ArgumentError(format!(
+                    "TXT record option '{}' is not allowed; only 'authSource' and 'replicaSet' may be specified.",
+                    key
+                )))
+            }
+        }
+    }
+
+    Ok(Some(options))
+}
+
+#[cfg(not(feature = "dns"))]
+fn resolve_txt_options(_: &str) -> Result<Option<ConnectionOptions>> {
+    Ok(None)
+}
+
+#[cfg(feature = "dns")]
+fn dns_resolver() -> Result<Resolver> {
+    Ok(Resolver::from_system_conf()?)
+}
+
+// Returns the domain that all SRV targets must belong to, which is the SRV host name
+// without its first label. The host name must contain at least three labels.
+#[cfg(feature = "dns")]
+fn srv_parent_domain(host_name: &str) -> Result<&str> {
+    if host_name.split('.').count() < 3 {
+        return Err(ArgumentError(format!(
+            "The SRV host name '{}' must contain at least three parts separated by '.'.",
+            host_name
+        )));
+    }
+
+    let (_, parent_domain) = partition(host_name, ".");
+    Ok(parent_domain)
+}
+
 // Parse user information of the form user:password
 fn parse_user_info(user_info: &str) -> Result<(&str, &str)> {
     let (user, password) = rpartition(user_info, ":");
@@ -194,6 +716,48 @@ fn parse_user_info(user_info: &str) -> Result<(&str, &str)> {
     Ok((user, password))
 }
 
+// Decodes %XX escape sequences in a connection string component.
+fn percent_decode(entity: &str) -> Result<String> {
+    let bytes = entity.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = entity
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.chars().all(|c| c.is_digit(16)))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            match byte {
+                Some(byte) => decoded.push(byte),
+                None => {
+                    return Err(ArgumentError(format!(
+                        "Invalid percent-encoding in '{}'.",
+                        entity
+                    )))
+                }
+            }
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| {
+        ArgumentError(format!("'{}' does not decode to valid UTF-8.", entity))
+    })
+}
+
+// Describes a port that is not an integer from 0 to 65535, along with the host it came from.
+fn invalid_port(port: &str, entity: &str) -> Error {
+    ArgumentError(format!(
+        "Invalid port '{}' in host '{}'; the port must be an integer from 0 to 65535.",
+        port,
+        entity
+    ))
+}
+
 // Parses a literal IPv6 literal host entity of the form [host] or [host]:port
 fn parse_ipv6_literal_host(entity: &str) -> Result<Host> {
     match entity.find(']') {
@@ -203,7 +767,7 @@ fn parse_ipv6_literal_host(entity: &str) -> Result<Host> {
                     let port = &entity[idx + 2..];
                     match port.parse::<u16>() {
                         Ok(val) => Ok(Host::new(entity[1..idx].to_ascii_lowercase(), val)),
-                        Err(_) => Err(ArgumentError(String::from("Port must be an integer."))),
+                        Err(_) => Err(invalid_port(port, entity)),
                     }
                 }
                 None => Ok(Host::new(entity[1..].to_ascii_lowercase(), DEFAULT_PORT)),
@@ -235,13 +799,11 @@ pub fn parse_host(entity: &str) -> Result<Host> {
         }
         match port.parse::<u16>() {
             Ok(val) => Ok(Host::new(host.to_ascii_lowercase(), val)),
-            Err(_) => Err(ArgumentError(
-                String::from("Port must be an unsigned integer."),
-            )),
+            Err(_) => Err(invalid_port(port, entity)),
         }
     } else if entity.contains(".sock") {
-        // IPC socket
-        Ok(Host::with_ipc(entity.to_ascii_lowercase()))
+        // IPC socket, whose path may be percent-encoded (e.g. %2Ftmp%2Fmongodb-27017.sock)
+        Ok(Host::with_ipc(percent_decode(entity)?))
     } else {
         // Host with no port specified
         Ok(Host::new(entity.to_ascii_lowercase(), DEFAULT_PORT))
@@ -263,8 +825,10 @@ fn split_hosts(host_str: &str) -> Result<Vec<Host>> {
     Ok(hosts)
 }
 
-// Parses the delimited string into its options and Read Preference Tags.
-fn parse_options(opts: &str, delim: Option<&str>) -> ConnectionOptions {
+// Parses the delimited string into its options and Read Preference Tags. Only
+// readPreferenceTags may be repeated, with each value adding a tag set; any other repeated
+// option is an error.
+fn parse_options(opts: &str, delim: Option<&str>) -> Result<ConnectionOptions> {
     let mut options = BTreeMap::new();
     let mut read_pref_tags = Vec::new();
 
@@ -279,12 +843,17 @@ fn parse_options(opts: &str, delim: Option<&str>) -> ConnectionOptions {
         let (key, val) = partition(opt, "=");
         if key.to_ascii_lowercase() == "readpreferencetags" {
             read_pref_tags.push(String::from(val));
+        } else if options.keys().any(|other: &String| other.eq_ignore_ascii_case(key)) {
+            return Err(ArgumentError(format!(
+                "The '{}' option must not be given more than once.",
+                key
+            )));
         } else {
             options.insert(String::from(key), String::from(val));
         }
     }
 
-    ConnectionOptions::new(options, read_pref_tags)
+    Ok(ConnectionOptions::new(options, read_pref_tags))
 }
 
 // Determines the option delimiter and offloads parsing to parse_options.
@@ -306,8 +875,7 @@ fn split_options(opts: &str) -> Result<ConnectionOptions> {
             "InvalidURI: MongoDB URI options are key=value pairs.",
         )));
     }
-    let options = parse_options(opts, delim);
-    Ok(options)
+    parse_options(opts, delim)
 }
 
 // Partitions a string around the left-most occurrence of the separator, if it exists.
@@ -333,3 +901,32 @@ fn rsplit<'a>(string: &'a str, sep: &str) -> (&'a str, &'a str) {
         None => (string, ""),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn connection_options(pairs: &[(&str, &str)]) -> ConnectionOptions {
+        let options = pairs
+            .iter()
+            .map(|&(key, val)| (String::from(key), String::from(val)))
+            .collect();
+        ConnectionOptions::new(options, Vec::new())
+    }
+
+    #[test]
+    fn srv_defaults_respect_options_in_any_case() {
+        let mut options = connection_options(&[("TLS", "false"), ("AuthSource", "admin")]);
+        let txt_options = connection_options(&[("authSource", "other"), ("replicaSet", "rs0")]);
+        apply_srv_defaults(&mut options, Some(txt_options));
+
+        let expected = connection_options(
+            &[("AuthSource", "admin"), ("TLS", "false"), ("replicaSet", "rs0")],
+        );
+        assert_eq!(expected.options, options.options);
+
+        let mut options = connection_options(&[]);
+        apply_srv_defaults(&mut options, None);
+        assert_eq!(connection_options(&[("ssl", "true")]).options, options.options);
+    }
+}
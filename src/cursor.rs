@@ -27,20 +27,25 @@ use {Client, CommandType, Error, ErrorCode, Result, ThreadedClient};
 use apm::{CommandStarted, CommandResult, EventRunner};
 
 use bson::{self, bson, doc, Bson};
-use common::{merge_options, ReadMode, ReadPreference};
+use common::{merge_options, validate_namespace, ReadPreference};
 use coll::options::FindOptions;
+use connstring::Host;
 use pool::PooledStream;
 use time;
 use wire_protocol::flags::OpQueryFlags;
 use wire_protocol::operations::Message;
 
 use std::{ i32, usize };
+use std::cmp;
 use std::mem::size_of;
 use std::collections::vec_deque::VecDeque;
 
 // Allows the server to decide the batch size.
 pub const DEFAULT_BATCH_SIZE: i32 = 0;
 
+// The minimum wire version that supports the killCursors command (MongoDB 3.2).
+const KILL_CURSORS_COMMAND_MIN_WIRE_VERSION: i64 = 4;
+
 /// Maintains a connection to the server and lazily returns documents from a
 /// query.
 #[derive(Debug)]
@@ -51,15 +56,18 @@ pub struct Cursor {
     namespace: String,
     // How many documents to fetch at a given time from the server.
     batch_size: i32,
+    // The time limit for each getMore, which requires sending getMores as commands.
+    max_time_ms: Option<i64>,
     // Uniquely identifies the cursor being returned by the reply.
     cursor_id: i64,
+    // The server that owns the cursor.
+    host: Host,
     // An upper bound on the total number of documents this cursor should return.
     limit: i32,
     // How many documents have been returned so far.
     count: i32,
     // A cache for documents received from the query that have not yet been returned.
     buffer: VecDeque<bson::Document>,
-    read_preference: ReadPreference,
     cmd_type: CommandType,
 }
 
@@ -125,6 +133,30 @@ impl Cursor {
         )
     }
 
+    /// Executes a command that does not open a server cursor, such as an explained aggregation,
+    /// returning a cursor whose only document is the command reply.
+    pub fn command_reply(
+        client: Client,
+        db: &str,
+        doc: bson::Document,
+        cmd_type: CommandType,
+        read_pref: ReadPreference,
+    ) -> Result<Cursor> {
+        let mut options = FindOptions::new();
+        options.batch_size = Some(1);
+
+        Cursor::query(
+            client.clone(),
+            format!("{}.$cmd", db),
+            OpQueryFlags::empty(),
+            doc,
+            options,
+            cmd_type,
+            false,
+            read_pref,
+        )
+    }
+
     fn get_bson_and_cid_from_message(
         message: Message,
     ) -> Result<(bson::Document, VecDeque<bson::Document>, i64)> {
@@ -217,6 +249,7 @@ impl Cursor {
         is_cmd_cursor: bool,
         read_pref: ReadPreference,
     ) -> Result<Cursor> {
+        validate_namespace(&namespace)?;
 
         // Select a server stream from the topology.
         let (mut stream, slave_ok, send_read_pref) = if cmd_type.is_write_command() {
@@ -257,7 +290,6 @@ impl Cursor {
             options,
             cmd_type,
             is_cmd_cursor,
-            Some(read_pref),
         )
     }
 
@@ -270,17 +302,16 @@ impl Cursor {
         options: FindOptions,
         cmd_type: CommandType,
         is_cmd_cursor: bool,
-        read_pref: Option<ReadPreference>,
     ) -> Result<Cursor> {
+        validate_namespace(&namespace)?;
 
-        let socket = stream.get_socket();
         let req_id = client.get_req_id();
 
         let index = namespace.find('.').unwrap_or_else(|| namespace.len());
         let db_name = String::from(&namespace[..index]);
         let coll_name = String::from(&namespace[index + 1..]);
         let cmd_name = cmd_type.to_str();
-        let connstring = socket.get_ref().peer_addr()?.to_string();
+        let connstring = stream.get_socket().get_ref().peer_addr()?.to_string();
 
         let filter = match query.get("$query") {
             Some(&Bson::Document(ref doc)) => doc.clone(),
@@ -288,7 +319,7 @@ impl Cursor {
         };
 
         let command = match cmd_type {
-            CommandType::Find => {
+            CommandType::Find if !is_cmd_cursor => {
                 let document = doc! {
                     "find": coll_name,
                     "filter": filter
@@ -309,6 +340,7 @@ impl Cursor {
             query,
             options.projection,
         )?;
+        message.check_document_depth(client.max_document_depth)?;
 
         if cmd_type != CommandType::Suppressed {
             let hook_result = client.run_start_hooks(&CommandStarted {
@@ -329,7 +361,7 @@ impl Cursor {
             cmd_name,
             req_id,
             connstring,
-            message.write(socket),
+            stream.write_message(&message),
             client
         );
         let reply = try_or_emit!(
@@ -337,7 +369,7 @@ impl Cursor {
             cmd_name,
             req_id,
             connstring,
-            Message::read(socket),
+            Message::read(stream.get_socket()),
             client
         );
 
@@ -365,7 +397,7 @@ impl Cursor {
         };
 
         let reply = match cmd_type {
-            CommandType::Find => doc! {
+            CommandType::Find if !is_cmd_cursor => doc! {
                 "cursor": {
                     "id": cursor_id,
                     "ns": &namespace,
@@ -386,9 +418,6 @@ impl Cursor {
             });
         }
 
-        let read_preference =
-            read_pref.unwrap_or_else(|| ReadPreference::new(ReadMode::Primary, None));
-
         // Check if actual batch size fits into an `i32`.
         if size_of::<i32>() <= size_of::<usize>() && buf.len() > i32::MAX as usize {
             return Err(Error::DefaultError(
@@ -400,33 +429,85 @@ impl Cursor {
             client: client,
             namespace: namespace,
             batch_size: buf.len() as i32,
+            max_time_ms: None,
             cursor_id: cursor_id,
+            host: stream.host().clone(),
             limit: options.limit.unwrap_or(0) as i32,
             count: 0,
             buffer: buf,
-            read_preference: read_preference,
             cmd_type: cmd_type.clone(),
         })
     }
 
-    fn get_from_stream(&mut self) -> Result<()> {
-        let (mut stream, _, _) = self.client.acquire_stream(self.read_preference.to_owned())?;
-        let socket = stream.get_socket();
+    /// Sets how many documents each subsequent getMore requests from the server.
+    pub fn batch_size(mut self, batch_size: i32) -> Cursor {
+        self.batch_size = batch_size;
+        self
+    }
 
-        let req_id = self.client.get_req_id();
-        let get_more = Message::new_get_more(
-            req_id,
-            self.namespace.to_owned(),
-            self.batch_size,
-            self.cursor_id,
-        );
+    /// Limits the cursor to returning at most `limit` documents in total, counting those
+    /// already returned. Once the limit is reached, the server cursor is killed rather than
+    /// left open until it times out.
+    pub fn limit(mut self, limit: i32) -> Cursor {
+        self.limit = limit;
+        self
+    }
 
-        let index = self.namespace.rfind('.').unwrap_or_else(
+    /// Sets a time limit for each subsequent getMore. Since OP_GET_MORE cannot carry a time
+    /// limit, getMores are then sent as getMore commands, which require MongoDB 3.2 or newer.
+    pub fn max_time_ms(mut self, max_time_ms: i64) -> Cursor {
+        self.max_time_ms = Some(max_time_ms);
+        self
+    }
+
+    fn get_from_stream(&mut self) -> Result<()> {
+        // Only the server that owns the cursor knows about it, so no server selection is done.
+        let mut stream = self.client.acquire_stream_to_host(&self.host)?;
+
+        let index = self.namespace.find('.').unwrap_or_else(
             || self.namespace.len(),
         );
         let db_name = String::from(&self.namespace[..index]);
+
+        // Never ask for more documents than remain under the limit.
+        let batch_size = if self.limit > 0 {
+            let remaining = self.limit - self.count - self.buffer.len() as i32;
+            if self.batch_size > 0 {
+                cmp::min(self.batch_size, remaining)
+            } else {
+                remaining
+            }
+        } else {
+            self.batch_size
+        };
+
+        let req_id = self.client.get_req_id();
+        let get_more = match self.max_time_ms {
+            Some(max_time_ms) => {
+                let mut command = doc! {
+                    "getMore": self.cursor_id,
+                    "collection": &self.namespace[index + 1..],
+                    "maxTimeMS": max_time_ms,
+                };
+
+                if batch_size > 0 {
+                    command.insert("batchSize", batch_size);
+                }
+
+                // The owning server may be a secondary, which only accepts the command with
+                // slaveOk.
+                self.command_message(req_id, true, command)?
+            }
+            None => Message::new_get_more(
+                req_id,
+                self.namespace.to_owned(),
+                batch_size,
+                self.cursor_id,
+            ),
+        };
+
         let cmd_name = String::from("get_more");
-        let connstring = socket.get_ref().peer_addr()?.to_string();
+        let connstring = stream.get_socket().get_ref().peer_addr()?.to_string();
 
         if self.cmd_type != CommandType::Suppressed {
             let hook_result = self.client.run_start_hooks(&CommandStarted {
@@ -447,14 +528,41 @@ impl Cursor {
             cmd_name,
             req_id,
             connstring,
-            get_more.write(socket.get_mut()),
+            stream.write_message(&get_more),
             self.client
         );
-        let reply = Message::read(socket.get_mut())?;
+        let reply = Message::read(stream.get_socket().get_mut())?;
+
+        let (doc, v, cursor_id) = Cursor::get_bson_and_cid_from_message(reply)?;
+
+        if self.max_time_ms.is_none() {
+            self.cursor_id = cursor_id;
+            self.buffer.extend(v);
+            return Ok(());
+        }
+
+        // The getMore command replies with the batch nested in a cursor document.
+        match doc.get("cursor") {
+            Some(&Bson::Document(ref cursor)) => {
+                self.cursor_id = match cursor.get("id") {
+                    Some(&Bson::I64(id)) => id,
+                    _ => return Err(Error::CursorNotFoundError),
+                };
+
+                if let Some(&Bson::Array(ref batch)) = cursor.get("nextBatch") {
+                    self.buffer.extend(batch.iter().filter_map(|bdoc| match *bdoc {
+                        Bson::Document(ref doc) => Some(doc.clone()),
+                        _ => None,
+                    }));
+                }
 
-        let (_, v, _) = Cursor::get_bson_and_cid_from_message(reply)?;
-        self.buffer.extend(v);
-        Ok(())
+                Ok(())
+            }
+            _ => match doc.get("errmsg") {
+                Some(&Bson::String(ref msg)) => Err(Error::OperationError(msg.to_owned())),
+                _ => Err(Error::CursorNotFoundError),
+            },
+        }
     }
 
     /// Attempts to read a specified number of BSON documents from the cursor.
@@ -515,6 +623,112 @@ impl Cursor {
         Ok(self.buffer.drain(..).collect())
     }
 
+    /// Drains the entire cursor into a single vector, issuing getMores as needed.
+    ///
+    /// Unlike iterating over the cursor, documents are moved out of each batch in bulk and
+    /// errors are checked once per batch rather than wrapped per document. Note that every
+    /// remaining document is held in memory at once, so this should only be used for result
+    /// sets known to fit comfortably in memory.
+    ///
+    /// # Return value
+    ///
+    /// Returns a vector containing all remaining documents, or the first error encountered
+    /// while fetching them; documents read before the error are discarded.
+    pub fn collect_documents(&mut self) -> Result<Vec<bson::Document>> {
+        let mut documents = Vec::new();
+
+        while self.has_next()? {
+            let available = if self.limit > 0 {
+                cmp::min((self.limit - self.count) as usize, self.buffer.len())
+            } else {
+                self.buffer.len()
+            };
+
+            documents.extend(self.buffer.drain(..available));
+            self.count += available as i32;
+        }
+
+        Ok(documents)
+    }
+
+    // Wraps a command on the cursor's database in an OP_QUERY message.
+    fn command_message(
+        &self,
+        req_id: i32,
+        slave_ok: bool,
+        command: bson::Document,
+    ) -> Result<Message> {
+        let index = self.namespace.find('.').unwrap_or_else(
+            || self.namespace.len(),
+        );
+
+        let flags = if slave_ok {
+            OpQueryFlags::SLAVE_OK
+        } else {
+            OpQueryFlags::empty()
+        };
+
+        Message::new_query(
+            req_id,
+            flags,
+            format!("{}.$cmd", &self.namespace[..index]),
+            0,
+            1,
+            command,
+            None,
+        )
+    }
+
+    /// Closes the cursor, releasing the server cursor right away if it is still open.
+    ///
+    /// Servers running MongoDB 3.2 or newer are sent the killCursors command, so failures are
+    /// reported; older servers are sent OP_KILL_CURSORS, which has no reply.
+    pub fn close(mut self) -> Result<()> {
+        if self.cursor_id == 0 {
+            return Ok(());
+        }
+
+        self.kill_server_cursor()
+    }
+
+    /// Kills the server cursor on the server that owns it, without going through server
+    /// selection. The cursor id is cleared first, so the cursor is never killed twice, even if
+    /// sending the kill fails.
+    fn kill_server_cursor(&mut self) -> Result<()> {
+        let cursor_id = self.cursor_id;
+        self.cursor_id = 0;
+
+        let mut stream = self.client.acquire_stream_to_host(&self.host)?;
+        let req_id = self.client.get_req_id();
+
+        let max_wire_version = self.client
+            .server_description(&self.host)
+            .map_or(0, |description| description.max_wire_version);
+
+        if max_wire_version < KILL_CURSORS_COMMAND_MIN_WIRE_VERSION {
+            return stream.write_message(&Message::new_kill_cursors(req_id, vec![cursor_id]));
+        }
+
+        let index = self.namespace.find('.').unwrap_or_else(
+            || self.namespace.len(),
+        );
+        let command = doc! {
+            "killCursors": &self.namespace[index + 1..],
+            "cursors": [cursor_id],
+        };
+
+        // The owning server may be a secondary, which only accepts the command with slaveOk.
+        let message = self.command_message(req_id, true, command)?;
+        stream.write_message(&message)?;
+        let reply = Message::read(stream.get_socket().get_mut())?;
+
+        let (doc, _, _) = Cursor::get_bson_and_cid_from_message(reply)?;
+        match doc.get("errmsg") {
+            Some(&Bson::String(ref msg)) => Err(Error::OperationError(msg.to_owned())),
+            _ => Ok(()),
+        }
+    }
+
     /// Checks whether there are any more documents for the cursor to return.
     ///
     /// # Return value
@@ -522,6 +736,11 @@ impl Cursor {
     /// Returns `true` if the cursor is not yet exhausted, or `false` if it is.
     pub fn has_next(&mut self) -> Result<bool> {
         if self.limit > 0 && self.count >= self.limit {
+            // Every document has been returned at this point, so failing to kill the server
+            // cursor does not fail the iteration; the server times it out eventually.
+            if self.cursor_id != 0 {
+                let _ = self.kill_server_cursor();
+            }
             Ok(false)
         } else {
             if self.buffer.is_empty() && self.limit != 1 && self.cursor_id != 0 {
@@ -532,6 +751,60 @@ impl Cursor {
     }
 }
 
+impl Drop for Cursor {
+    /// Kills the server cursor if it has not been exhausted, so that abandoning a cursor early
+    /// does not hold server resources until the cursor times out.
+    fn drop(&mut self) {
+        if self.cursor_id != 0 {
+            let _ = self.kill_server_cursor();
+        }
+    }
+}
+
+/// A cursor over a capped collection that keeps waiting for new documents instead of
+/// finishing when a batch comes back empty.
+///
+/// Iteration only ends once the server closes the underlying cursor, for instance because
+/// the collection was dropped or the cursor was invalidated by capped-collection rollover.
+#[derive(Debug)]
+pub struct TailableCursor {
+    cursor: Cursor,
+}
+
+impl TailableCursor {
+    /// Wraps a cursor created with a tailable cursor type.
+    pub fn new(cursor: Cursor) -> TailableCursor {
+        TailableCursor { cursor: cursor }
+    }
+}
+
+impl Iterator for TailableCursor {
+    type Item = Result<bson::Document>;
+
+    /// Returns the next buffered document, issuing getMores until one arrives.
+    ///
+    /// # Return value
+    ///
+    /// Returns a BSON document once one is available; `None` if the server has closed the
+    /// cursor; or an Error if a getMore fails.
+    fn next(&mut self) -> Option<Result<bson::Document>> {
+        loop {
+            if let Some(doc) = self.cursor.buffer.pop_front() {
+                self.cursor.count += 1;
+                return Some(Ok(doc));
+            }
+
+            if self.cursor.cursor_id == 0 {
+                return None;
+            }
+
+            if let Err(err) = self.cursor.get_from_stream() {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
 impl Iterator for Cursor {
     type Item = Result<bson::Document>;
 
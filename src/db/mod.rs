@@ -3,7 +3,7 @@
 //! # Usage
 //!
 //! The database API provides methods for opening, creating, deleting, and listing collections.
-//! It also handles user-level authentication over SCRAM-SHA-1.
+//! It also handles user-level authentication over SCRAM-SHA-1 and MONGODB-X509.
 //!
 //! ## Collection Operations
 //!
@@ -61,16 +61,22 @@ pub mod roles;
 use auth::Authenticator;
 use bson::{self, bson, doc, Bson};
 use {Client, CommandType, ThreadedClient, Result};
-use Error::{CursorNotFoundError, OperationError, ResponseError};
-use coll::Collection;
-use coll::options::FindOptions;
+use Error::{ArgumentError, CursorNotFoundError, OperationError, ResponseError};
+use coll::{build_aggregate_command, Collection};
+use coll::options::{AggregateOptions, FindOptions};
 use common::{ReadPreference, merge_options, WriteConcern};
+use connstring::Host;
 use cursor::{Cursor, DEFAULT_BATCH_SIZE};
 use self::options::{CreateCollectionOptions, CreateUserOptions, UserInfoOptions};
 use semver::Version;
+use session::ClientSession;
+use wire_protocol::flags::OpQueryFlags;
 use std::error::Error;
 use std::sync::Arc;
 
+/// The database that holds users authenticated by external sources, such as X.509 certificates.
+pub const EXTERNAL_AUTH_DB: &'static str = "$external";
+
 /// Interfaces with a MongoDB database.
 #[derive(Debug)]
 pub struct DatabaseInner {
@@ -99,6 +105,9 @@ pub trait ThreadedDatabase {
     fn version(&self) -> Result<Version>;
     /// Logs in a user using the SCRAM-SHA-1 mechanism.
     fn auth(&self, user: &str, password: &str) -> Result<()>;
+    /// Logs in using the MONGODB-X509 mechanism against the `$external` database. The user is
+    /// derived from the client certificate subject if it is not provided.
+    fn auth_x509(&self, user: Option<&str>) -> Result<()>;
     /// Creates a collection representation with inherited read and write controls.
     fn collection(&self, coll_name: &str) -> Collection;
     /// Creates a collection representation with custom read and write controls.
@@ -125,6 +134,34 @@ pub trait ThreadedDatabase {
         cmd_type: CommandType,
         read_preference: Option<ReadPreference>,
     ) -> Result<bson::Document>;
+    /// Sends an administrative command within an explicit session, attaching the session id,
+    /// cluster time and any transaction fields, and recording the cluster time and operation time
+    /// of the reply.
+    fn command_with_session(
+        &self,
+        spec: bson::Document,
+        cmd_type: CommandType,
+        read_preference: Option<ReadPreference>,
+        session: &mut ClientSession,
+    ) -> Result<bson::Document>;
+    /// Sends an administrative command to a specific server in the topology, bypassing
+    /// server selection.
+    fn command_on_host(
+        &self,
+        spec: bson::Document,
+        cmd_type: CommandType,
+        host: &Host,
+    ) -> Result<bson::Document>;
+    /// Runs a collection-less aggregation pipeline against this database, such as one starting
+    /// with `$listLocalSessions`, or with `$currentOp`, which must be run against `admin`.
+    /// Options are handled as by `Collection::aggregate`.
+    ///
+    /// Returns an `ArgumentError` if a `$currentOp` pipeline is run against any other database.
+    fn aggregate(
+        &self,
+        pipeline: Vec<bson::Document>,
+        options: Option<AggregateOptions>,
+    ) -> Result<Cursor>;
     /// Returns a list of collections within the database.
     fn list_collections(&self, filter: Option<bson::Document>) -> Result<Cursor>;
     /// Returns a list of collections within the database with a custom batch size.
@@ -191,6 +228,12 @@ impl ThreadedDatabase for Database {
         authenticator.auth(user, password)
     }
 
+    fn auth_x509(&self, user: Option<&str>) -> Result<()> {
+        let external = self.client.db(EXTERNAL_AUTH_DB);
+        let authenticator = Authenticator::new(external);
+        authenticator.auth_x509(user)
+    }
+
     fn collection(&self, coll_name: &str) -> Collection {
         Collection::new(
             self.clone(),
@@ -259,6 +302,90 @@ impl ThreadedDatabase for Database {
         })
     }
 
+    fn command_with_session(
+        &self,
+        mut spec: bson::Document,
+        cmd_type: CommandType,
+        read_preference: Option<ReadPreference>,
+        session: &mut ClientSession,
+    ) -> Result<bson::Document> {
+        session.apply_to(&mut spec);
+        let reply = self.command(spec, cmd_type, read_preference)?;
+        session.update_from_reply(&reply);
+        Ok(reply)
+    }
+
+    fn command_on_host(
+        &self,
+        spec: bson::Document,
+        cmd_type: CommandType,
+        host: &Host,
+    ) -> Result<bson::Document> {
+        let mut stream = self.client.acquire_stream_to_host(host)?;
+
+        let options = FindOptions {
+            batch_size: Some(1),
+            limit: Some(1),
+            ..FindOptions::new()
+        };
+
+        // The chosen server may be a secondary, so always allow it to run the command.
+        let flags = OpQueryFlags::with_find_options(&options) | OpQueryFlags::SLAVE_OK;
+
+        let mut cursor = Cursor::query_with_stream(
+            &mut stream,
+            self.client.clone(),
+            format!("{}.$cmd", self.name),
+            flags,
+            spec.clone(),
+            options,
+            cmd_type,
+            false,
+        )?;
+
+        match cursor.next() {
+            Some(Ok(doc)) => Ok(doc),
+            Some(Err(err)) => Err(err),
+            None => Err(OperationError(
+                format!("Failed to execute command with spec {:?}.", spec),
+            )),
+        }
+    }
+
+    fn aggregate(
+        &self,
+        pipeline: Vec<bson::Document>,
+        options: Option<AggregateOptions>,
+    ) -> Result<Cursor> {
+        let current_op = pipeline.first().map_or(false, |stage| stage.contains_key("$currentOp"));
+        if current_op && self.name != "admin" {
+            return Err(ArgumentError(format!(
+                "$currentOp must be run against the admin database, not '{}'.",
+                self.name
+            )));
+        }
+
+        let read_preference = options
+            .as_ref()
+            .and_then(|opts| opts.read_preference.clone())
+            .unwrap_or_else(|| self.read_preference.clone());
+        let explain = options.as_ref().and_then(|opts| opts.explain) == Some(true);
+
+        let spec = build_aggregate_command(Bson::I32(1), pipeline, options);
+
+        if explain {
+            Cursor::command_reply(
+                self.client.clone(),
+                &self.name,
+                spec,
+                CommandType::Aggregate,
+                read_preference,
+            )
+        } else {
+            self.command_cursor(spec, CommandType::Aggregate, read_preference)
+        }
+    }
+
     fn list_collections(&self, filter: Option<bson::Document>) -> Result<Cursor> {
         self.list_collections_with_batch_size(filter, DEFAULT_BATCH_SIZE)
     }
@@ -1,7 +1,8 @@
 //! MongoDB Errors and Error Codes.
 use bson::{self, oid};
-use coll::error::{WriteException, BulkWriteException};
+use coll::error::{WriteConcernError, WriteException, BulkWriteException};
 use data_encoding;
+use topology::error::ServerSelectionError;
 use std::{error, fmt, io, result, sync};
 
 /// A type for results generated by MongoDB related functions, where the Err type is
@@ -49,12 +50,17 @@ pub enum Error {
     WriteError(WriteException),
     /// A bulk-write operation failed due to one or more lower-level write-related errors.
     BulkWriteError(BulkWriteException),
+    /// A single-write operation was applied, but its write concern was not satisfied, e.g. a
+    /// majority write that timed out before replicating.
+    WriteConcernError(WriteConcernError),
     /// An invalid function or operational argument was provided.
     ArgumentError(String),
     /// A database operation failed to send or receive a reply.
     OperationError(String),
     /// A database operation returned an invalid reply.
     ResponseError(String),
+    /// No suitable server was found within the server selection timeout.
+    ServerSelectionError(ServerSelectionError),
     /// A cursor operation failed to return a cursor.
     CursorNotFoundError,
     /// The application failed to secure a mutex due to a poisoned lock.
@@ -104,6 +110,12 @@ impl From<BulkWriteException> for Error {
     }
 }
 
+impl From<WriteConcernError> for Error {
+    fn from(err: WriteConcernError) -> Error {
+        Error::WriteConcernError(err)
+    }
+}
+
 impl From<bson::EncoderError> for Error {
     fn from(err: bson::EncoderError) -> Error {
         Error::EncoderError(err)
@@ -128,6 +140,12 @@ impl From<data_encoding::DecodeError> for Error {
     }
 }
 
+impl From<ServerSelectionError> for Error {
+    fn from(err: ServerSelectionError) -> Error {
+        Error::ServerSelectionError(err)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Error::IoError(err)
@@ -140,11 +158,25 @@ impl<T> From<sync::PoisonError<T>> for Error {
     }
 }
 
+impl Error {
+    /// Returns the write concern error reported by a write, whether it is the whole error or
+    /// accompanies write errors.
+    pub fn write_concern_error(&self) -> Option<&WriteConcernError> {
+        match *self {
+            Error::WriteConcernError(ref err) => Some(err),
+            Error::WriteError(ref exc) => exc.write_concern_error.as_ref(),
+            Error::BulkWriteError(ref exc) => exc.write_concern_error.as_ref(),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::WriteError(ref inner) => inner.fmt(fmt),
             Error::BulkWriteError(ref inner) => inner.fmt(fmt),
+            Error::WriteConcernError(ref inner) => inner.fmt(fmt),
             Error::EncoderError(ref inner) => inner.fmt(fmt),
             Error::DecoderError(ref inner) => inner.fmt(fmt),
             Error::OIDError(ref inner) => inner.fmt(fmt),
@@ -153,6 +185,7 @@ impl fmt::Display for Error {
             Error::ArgumentError(ref inner) => inner.fmt(fmt),
             Error::OperationError(ref inner) => inner.fmt(fmt),
             Error::ResponseError(ref inner) => inner.fmt(fmt),
+            Error::ServerSelectionError(ref inner) => inner.fmt(fmt),
             Error::CursorNotFoundError => fmt.write_str("No cursor found for cursor operation."),
             Error::PoisonLockError => fmt.write_str("Socket lock poisoned while attempting to access."),
             Error::CodedError(ref err) => write!(fmt, "{}", err),
@@ -179,11 +212,13 @@ impl error::Error for Error {
         match *self {
             Error::WriteError(ref inner) => inner.description(),
             Error::BulkWriteError(ref inner) => inner.description(),
+            Error::WriteConcernError(ref inner) => inner.description(),
             Error::EncoderError(ref inner) => inner.description(),
             Error::DecoderError(ref inner) => inner.description(),
             Error::OIDError(ref inner) => inner.description(),
             Error::FromHexError(ref inner) => inner.description(),
             Error::IoError(ref inner) => inner.description(),
+            Error::ServerSelectionError(ref inner) => inner.description(),
             Error::CursorNotFoundError => "No cursor found for cursor operation.",
             Error::PoisonLockError => "Socket lock poisoned while attempting to access.",
             Error::CodedError(ref err) => err.to_str(),
@@ -205,11 +240,13 @@ impl error::Error for Error {
         match *self {
             Error::WriteError(ref inner) => Some(inner),
             Error::BulkWriteError(ref inner) => Some(inner),
+            Error::WriteConcernError(ref inner) => Some(inner),
             Error::EncoderError(ref inner) => Some(inner),
             Error::DecoderError(ref inner) => Some(inner),
             Error::OIDError(ref inner) => Some(inner),
             Error::FromHexError(ref inner) => Some(inner),
             Error::IoError(ref inner) => Some(inner),
+            Error::ServerSelectionError(ref inner) => Some(inner),
             Error::ArgumentError(_) |
             Error::OperationError(_) |
             Error::ResponseError(_) |
@@ -134,6 +134,8 @@ extern crate data_encoding;
 #[cfg(feature = "ssl")]
 extern crate openssl;
 extern crate rand;
+#[cfg(feature = "tls-rustls")]
+extern crate rustls;
 #[macro_use]
 extern crate scan_fmt;
 extern crate semver;
@@ -143,6 +145,10 @@ extern crate serde_derive;
 extern crate separator;
 extern crate textnonce;
 extern crate time;
+#[cfg(feature = "dns")]
+extern crate trust_dns_resolver;
+#[cfg(feature = "tls-rustls")]
+extern crate webpki_roots;
 extern crate md5;
 extern crate sha1;
 extern crate hmac;
@@ -157,6 +163,7 @@ pub mod cursor;
 pub mod error;
 pub mod gridfs;
 pub mod pool;
+pub mod session;
 pub mod stream;
 pub mod topology;
 pub mod wire_protocol;
@@ -177,17 +184,23 @@ use std::io::Write;
 use std::ops::DerefMut;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicIsize, Ordering};
+use std::time::Duration;
 
 use apm::Listener;
 use common::{ReadPreference, ReadMode, WriteConcern};
-use connstring::ConnectionString;
+use connstring::{ConnectionString, Host};
 use db::{Database, ThreadedDatabase};
-use error::Error::ResponseError;
+use error::Error::{ArgumentError, ResponseError};
 use pool::PooledStream;
+use rand::Rng;
+use session::ClientSession;
 use stream::StreamConnector;
+#[cfg(feature = "ssl")]
+use openssl::ssl::SslContext;
 use topology::{Topology, TopologyDescription, TopologyType, DEFAULT_HEARTBEAT_FREQUENCY_MS,
                DEFAULT_LOCAL_THRESHOLD_MS, DEFAULT_SERVER_SELECTION_TIMEOUT_MS};
-use topology::server::Server;
+use topology::server::{Server, ServerDescription};
+use wire_protocol::operations::DEFAULT_MAX_DOCUMENT_DEPTH;
 
 pub const DRIVER_NAME: &'static str = "mongo-rust-driver-prototype";
 
@@ -198,10 +211,19 @@ pub struct ClientInner {
     /// Describes the guarantees provided by MongoDB when reporting the success of a write
     /// operation.
     pub write_concern: WriteConcern,
+    /// Whether write concern errors are attached to write results rather than returned as errors.
+    pub attach_write_concern_errors: bool,
+    /// Whether single-statement writes are retried once on a retryable error.
+    pub retry_writes: bool,
+    /// The maximum nesting depth of documents sent to the server.
+    pub max_document_depth: usize,
+    handshake_cache_ttl: Option<Duration>,
     req_id: Arc<AtomicIsize>,
+    session_id: bson::Document,
+    txn_number: Arc<AtomicIsize>,
     topology: Topology,
-    listener: Listener,
-    log_file: Option<Mutex<File>>,
+    listener: Arc<Listener>,
+    log_file: Option<Arc<Mutex<File>>>,
 }
 
 impl fmt::Debug for ClientInner {
@@ -209,6 +231,8 @@ impl fmt::Debug for ClientInner {
         f.debug_struct("ClientInner")
             .field("read_preference", &self.read_preference)
             .field("write_concern", &self.write_concern)
+            .field("retry_writes", &self.retry_writes)
+            .field("max_document_depth", &self.max_document_depth)
             .field("req_id", &self.req_id)
             .field("topology", &self.topology)
             .field("listener", &"Listener { .. }")
@@ -234,6 +258,23 @@ pub struct ClientOptions {
     pub local_threshold_ms: i64,
     /// Options for how to connect to the server.
     pub stream_connector: StreamConnector,
+    /// Whether a write concern error on an otherwise successful write is attached to the write
+    /// result instead of being returned as an error; default false.
+    pub attach_write_concern_errors: bool,
+    /// Whether single-statement writes are retried once after a network error or primary
+    /// step-down; default false. Also enabled by `retryWrites=true` in the connection string.
+    pub retry_writes: bool,
+    /// The maximum number of connections per server pool, overriding the `maxPoolSize`
+    /// connection string option.
+    pub max_pool_size: Option<usize>,
+    /// The maximum nesting depth of documents sent to the server, counting the top-level
+    /// document and each nested document or array as one level; default 100. Operations with
+    /// deeper documents fail with an `ArgumentError` before anything is sent.
+    pub max_document_depth: Option<usize>,
+    /// How long the most recent handshake with a server is reused by new connections to it;
+    /// disabled by default. Connections opened from the cache skip the isMaster handshake, so
+    /// they do not report client metadata to the server.
+    pub handshake_cache_ttl: Option<Duration>,
 }
 
 impl ClientOptions {
@@ -247,9 +288,20 @@ impl ClientOptions {
             server_selection_timeout_ms: DEFAULT_SERVER_SELECTION_TIMEOUT_MS,
             local_threshold_ms: DEFAULT_LOCAL_THRESHOLD_MS,
             stream_connector: StreamConnector::default(),
+            attach_write_concern_errors: false,
+            retry_writes: false,
+            max_pool_size: None,
+            max_document_depth: None,
+            handshake_cache_ttl: None,
         }
     }
 
+    /// Creates a builder that assembles an options struct one setting at a time, starting from
+    /// the defaults.
+    pub fn builder() -> ClientOptionsBuilder {
+        ClientOptionsBuilder { options: ClientOptions::new() }
+    }
+
     /// Creates a new options struct with a specified log file.
     pub fn with_log_file(file: &str) -> ClientOptions {
         let mut options = ClientOptions::new();
@@ -278,6 +330,134 @@ impl ClientOptions {
         options.stream_connector = StreamConnector::with_unauthenticated_ssl(ca_file, verify_peer);
         options
     }
+
+    #[cfg(feature = "ssl")]
+    /// Creates a new options struct that connects with SSL using a pre-built OpenSSL context.
+    pub fn with_ssl_context(context: SslContext) -> ClientOptions {
+        let mut options = ClientOptions::new();
+        options.stream_connector = StreamConnector::with_ssl_context(context);
+        options
+    }
+}
+
+/// A fluent builder for `ClientOptions`.
+///
+/// ```no_run
+/// # use mongodb::{Client, ClientOptions, ThreadedClient};
+/// # use mongodb::common::{ReadMode, ReadPreference, WriteConcern};
+/// let options = ClientOptions::builder()
+///     .max_pool_size(20)
+///     .read_preference(ReadPreference::new(ReadMode::SecondaryPreferred, None))
+///     .write_concern(WriteConcern::new())
+///     .server_selection_timeout_ms(5000)
+///     .build();
+/// let client = Client::connect_with_options("localhost", 27017, options).unwrap();
+/// ```
+pub struct ClientOptionsBuilder {
+    options: ClientOptions,
+}
+
+impl ClientOptionsBuilder {
+    /// Sets the file path for command logging.
+    pub fn log_file(mut self, file: &str) -> ClientOptionsBuilder {
+        self.options.log_file = Some(String::from(file));
+        self
+    }
+
+    /// Sets the client-level read preference.
+    pub fn read_preference(mut self, read_preference: ReadPreference) -> ClientOptionsBuilder {
+        self.options.read_preference = Some(read_preference);
+        self
+    }
+
+    /// Sets the client-level write concern.
+    pub fn write_concern(mut self, write_concern: WriteConcern) -> ClientOptionsBuilder {
+        self.options.write_concern = Some(write_concern);
+        self
+    }
+
+    /// Sets the frequency of server monitor updates.
+    pub fn heartbeat_frequency_ms(mut self, heartbeat_frequency_ms: u32) -> ClientOptionsBuilder {
+        self.options.heartbeat_frequency_ms = heartbeat_frequency_ms;
+        self
+    }
+
+    /// Sets the timeout for selecting a server for an operation.
+    pub fn server_selection_timeout_ms(mut self, timeout_ms: i64) -> ClientOptionsBuilder {
+        self.options.server_selection_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Sets the size of the latency window for selecting suitable servers.
+    pub fn local_threshold_ms(mut self, local_threshold_ms: i64) -> ClientOptionsBuilder {
+        self.options.local_threshold_ms = local_threshold_ms;
+        self
+    }
+
+    /// Sets how to connect to the server.
+    pub fn stream_connector(mut self, connector: StreamConnector) -> ClientOptionsBuilder {
+        self.options.stream_connector = connector;
+        self
+    }
+
+    #[cfg(feature = "ssl")]
+    /// Connects with SSL using the specified certificate and key files.
+    pub fn ssl(
+        self,
+        ca_file: Option<&str>,
+        certificate_file: &str,
+        key_file: &str,
+        verify_peer: bool,
+    ) -> ClientOptionsBuilder {
+        self.stream_connector(
+            StreamConnector::with_ssl(ca_file, certificate_file, key_file, verify_peer),
+        )
+    }
+
+    #[cfg(feature = "ssl")]
+    /// Connects with SSL without a client certificate.
+    pub fn unauthenticated_ssl(
+        self,
+        ca_file: Option<&str>,
+        verify_peer: bool,
+    ) -> ClientOptionsBuilder {
+        self.stream_connector(StreamConnector::with_unauthenticated_ssl(ca_file, verify_peer))
+    }
+
+    /// Sets whether write concern errors are attached to write results.
+    pub fn attach_write_concern_errors(mut self, attach: bool) -> ClientOptionsBuilder {
+        self.options.attach_write_concern_errors = attach;
+        self
+    }
+
+    /// Sets whether single-statement writes are retried once on a retryable error.
+    pub fn retry_writes(mut self, retry_writes: bool) -> ClientOptionsBuilder {
+        self.options.retry_writes = retry_writes;
+        self
+    }
+
+    /// Sets the maximum number of connections per server pool.
+    pub fn max_pool_size(mut self, max_pool_size: usize) -> ClientOptionsBuilder {
+        self.options.max_pool_size = Some(max_pool_size);
+        self
+    }
+
+    /// Sets the maximum nesting depth of documents sent to the server.
+    pub fn max_document_depth(mut self, max_document_depth: usize) -> ClientOptionsBuilder {
+        self.options.max_document_depth = Some(max_document_depth);
+        self
+    }
+
+    /// Sets how long the most recent handshake with a server is reused by new connections.
+    pub fn handshake_cache_ttl(mut self, ttl: Duration) -> ClientOptionsBuilder {
+        self.options.handshake_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the assembled options.
+    pub fn build(self) -> ClientOptions {
+        self.options
+    }
 }
 
 pub trait ThreadedClient: Sync + Sized {
@@ -311,6 +491,8 @@ pub trait ThreadedClient: Sync + Sized {
     fn acquire_stream(&self, read_pref: ReadPreference) -> Result<(PooledStream, bool, bool)>;
     /// Acquires a connection stream from the pool for write operations.
     fn acquire_write_stream(&self) -> Result<PooledStream>;
+    /// Acquires a connection stream from the pool of a specific server in the topology.
+    fn acquire_stream_to_host(&self, host: &Host) -> Result<PooledStream>;
     /// Returns a unique operational request id.
     fn get_req_id(&self) -> i32;
     /// Returns a list of all database names that exist on the server.
@@ -327,6 +509,110 @@ pub trait ThreadedClient: Sync + Sized {
 
 pub type Client = Arc<ClientInner>;
 
+impl ClientInner {
+    /// Returns the logical session id attached to this client's retryable writes.
+    pub fn session_id(&self) -> &bson::Document {
+        &self.session_id
+    }
+
+    /// Returns a new transaction number for a retryable write within this client's session.
+    pub fn next_txn_number(&self) -> i64 {
+        self.txn_number.fetch_add(1, Ordering::SeqCst) as i64 + 1
+    }
+
+    /// Returns whether the connected deployment supports retryable writes.
+    pub fn supports_retryable_writes(&self) -> bool {
+        self.topology.supports_retryable_writes()
+    }
+
+    /// Creates a client sharing this client's connections and topology monitoring, but
+    /// using a different default read preference.
+    pub fn with_read_preference(&self, read_preference: ReadPreference) -> Client {
+        let mut inner = self.share();
+        inner.read_preference = read_preference;
+        Arc::new(inner)
+    }
+
+    /// Creates a client sharing this client's connections and topology monitoring, but
+    /// using a different default write concern.
+    pub fn with_write_concern(&self, write_concern: WriteConcern) -> Client {
+        let mut inner = self.share();
+        inner.write_concern = write_concern;
+        Arc::new(inner)
+    }
+
+    /// Returns the most recent isMaster-derived description of a server in the topology.
+    pub fn server_description(&self, host: &Host) -> Option<ServerDescription> {
+        self.topology.server_description(host)
+    }
+
+    /// Selects a server in the topology that satisfies the read preference.
+    pub fn select_server(&self, read_preference: &ReadPreference) -> Result<Host> {
+        self.topology.select_server(read_preference)
+    }
+
+    /// Starts an explicit session with its own logical session id and cluster time.
+    pub fn start_session(self: &Client) -> ClientSession {
+        ClientSession::new(self.clone())
+    }
+
+    /// Runs a heavy, read-only command on a secondary when one is available, falling back to
+    /// the primary otherwise, to keep analytics workloads off the primary.
+    pub fn run_analytics_command(
+        self: &Client,
+        db_name: &str,
+        spec: bson::Document,
+        cmd_type: CommandType,
+    ) -> Result<bson::Document> {
+        let read_preference = ReadPreference::new(ReadMode::SecondaryPreferred, None);
+        self.db(db_name).command(spec, cmd_type, Some(read_preference))
+    }
+
+    /// Selects the server that would receive a write, without acquiring a stream to it.
+    pub fn select_write_server(&self) -> Result<Host> {
+        self.topology.select_write_server()
+    }
+
+    /// Requests an immediate check of every server and waits briefly for the topology to be
+    /// updated, so that a retried write selects a server from a fresh description.
+    pub fn request_topology_update(&self) {
+        self.topology.request_update();
+    }
+
+    /// Returns whether both clients share the same connection pools and topology monitoring.
+    pub fn shares_topology_with(&self, other: &ClientInner) -> bool {
+        Arc::ptr_eq(&self.topology.description, &other.topology.description)
+    }
+
+    // Copies this client, sharing its topology, session, and event listeners.
+    fn share(&self) -> ClientInner {
+        ClientInner {
+            read_preference: self.read_preference.clone(),
+            write_concern: self.write_concern,
+            attach_write_concern_errors: self.attach_write_concern_errors,
+            retry_writes: self.retry_writes,
+            max_document_depth: self.max_document_depth,
+            handshake_cache_ttl: self.handshake_cache_ttl,
+            req_id: self.req_id.clone(),
+            session_id: self.session_id.clone(),
+            txn_number: self.txn_number.clone(),
+            topology: self.topology.clone(),
+            listener: self.listener.clone(),
+            log_file: self.log_file.clone(),
+        }
+    }
+}
+
+// Generates a logical session id of the form { id: <UUID v4> }.
+fn new_session_id() -> bson::Document {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    doc! { "id": (bson::spec::BinarySubtype::Uuid, bytes.to_vec()) }
+}
+
 impl ThreadedClient for Client {
     fn connect(host: &str, port: u16) -> Result<Client> {
         let config = ConnectionString::new(host, port);
@@ -354,32 +640,56 @@ impl ThreadedClient for Client {
     }
 
     fn with_config(
-        config: ConnectionString,
+        mut config: ConnectionString,
         options: Option<ClientOptions>,
         description: Option<TopologyDescription>,
     ) -> Result<Client> {
 
-        let client_options = options.unwrap_or_else(ClientOptions::new);
+        let mut client_options = options.unwrap_or_else(ClientOptions::new);
 
-        let rp = client_options.read_preference.unwrap_or_else(|| {
-            ReadPreference::new(ReadMode::Primary, None)
-        });
+        if let Some(max_pool_size) = client_options.max_pool_size {
+            if max_pool_size == 0 {
+                return Err(ArgumentError(
+                    String::from("The max pool size must be greater than zero."),
+                ));
+            }
+            if config.min_pool_size.map_or(false, |min_pool_size| min_pool_size > max_pool_size) {
+                return Err(ArgumentError(
+                    String::from("minPoolSize must not exceed the max pool size."),
+                ));
+            }
+            config.max_pool_size = Some(max_pool_size);
+        }
+
+        // TLS options in the connection string apply unless a connector was configured explicitly.
+        if !client_options.stream_connector.is_tls() {
+            if let Some(connector) = config.stream_connector()? {
+                client_options.stream_connector = connector;
+            }
+        }
+
+        let rp = client_options
+            .read_preference
+            .or_else(|| config.read_preference())
+            .unwrap_or_else(|| ReadPreference::new(ReadMode::Primary, None));
         let wc = client_options.write_concern.unwrap_or_else(
             WriteConcern::new,
         );
 
+        let retry_writes = client_options.retry_writes || config.retry_writes.unwrap_or(false);
+
         let listener = Listener::new();
         let file = match client_options.log_file {
             Some(string) => {
                 let _ = listener.add_start_hook(log_command_started);
                 let _ = listener.add_completion_hook(log_command_completed);
-                Some(Mutex::new(
+                Some(Arc::new(Mutex::new(
                     OpenOptions::new()
                         .write(true)
                         .append(true)
                         .create(true)
                         .open(&string)?
-                ))
+                )))
             }
             None => None,
         };
@@ -391,9 +701,17 @@ impl ThreadedClient for Client {
                 description,
                 client_options.stream_connector.clone(),
             )?,
-            listener: listener,
+            listener: Arc::new(listener),
             read_preference: rp,
             write_concern: wc,
+            attach_write_concern_errors: client_options.attach_write_concern_errors,
+            retry_writes: retry_writes,
+            max_document_depth: client_options.max_document_depth.unwrap_or(
+                DEFAULT_MAX_DOCUMENT_DEPTH,
+            ),
+            handshake_cache_ttl: client_options.handshake_cache_ttl,
+            session_id: new_session_id(),
+            txn_number: Arc::new(AtomicIsize::new(0)),
             log_file: file,
         });
 
@@ -445,6 +763,10 @@ impl ThreadedClient for Client {
         self.topology.acquire_write_stream(self.clone())
     }
 
+    fn acquire_stream_to_host(&self, host: &Host) -> Result<PooledStream> {
+        self.topology.acquire_stream_to_host(self.clone(), host)
+    }
+
     fn get_req_id(&self) -> i32 {
         self.req_id.fetch_add(1, Ordering::SeqCst) as i32
     }
@@ -9,16 +9,24 @@ use connstring::Host;
 use cursor::Cursor;
 use stream::{Stream, StreamConnector};
 use wire_protocol::flags::OpQueryFlags;
+use wire_protocol::operations::Message;
 
 use bson::{bson, doc};
 use bufstream::BufStream;
 
 use std::fmt;
-use std::sync::{Arc, Condvar, Mutex};
+use std::io;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub static DEFAULT_POOL_SIZE: usize = 5;
 
+/// The default interval between background pool maintenance runs.
+pub static DEFAULT_MAINTENANCE_INTERVAL_MS: u64 = 1000;
+
 /// Handles threaded connections to a MongoDB server.
 #[derive(Clone)]
 pub struct ConnectionPool {
@@ -40,16 +48,49 @@ impl fmt::Debug for ConnectionPool {
     }
 }
 
+/// The time spent establishing a pooled connection, broken down by phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionMetrics {
+    /// The time spent establishing the TCP connection.
+    pub tcp_connect: Duration,
+    /// The time spent on the TLS handshake, if the connection is encrypted.
+    pub tls_handshake: Option<Duration>,
+    /// The time spent on the isMaster handshake.
+    pub handshake: Duration,
+}
+
+impl ConnectionMetrics {
+    /// Returns the total time spent establishing the connection.
+    pub fn total(&self) -> Duration {
+        self.tcp_connect + self.tls_handshake.unwrap_or_default() + self.handshake
+    }
+}
+
 struct Pool {
     /// The maximum number of concurrent connections allowed.
     pub size: usize,
+    // The number of connections that background maintenance keeps open.
+    min_size: usize,
+    // How long a connection may sit idle in the pool before it is closed.
+    max_idle_time: Option<Duration>,
+    // How long a connection may sit idle in the pool before background maintenance pings it.
+    keep_alive_interval: Option<Duration>,
     // The current number of open connections.
     pub len: Arc<AtomicUsize>,
-    // The idle socket pool.
-    sockets: Vec<BufStream<Stream>>,
+    // The idle socket pool, each paired with its reusable message serialization buffer, the
+    // time at which it was returned to the pool, and the metrics of its establishment.
+    sockets: Vec<(BufStream<Stream>, Vec<u8>, Instant, ConnectionMetrics)>,
     // The pool iteration. When a server monitor fails to execute ismaster,
     // the connection pool is cleared and the iteration is incremented.
     iteration: usize,
+    // The metrics of the most recently established connection.
+    last_metrics: Option<ConnectionMetrics>,
+    // How long after the most recent handshake new connections skip it, if at all.
+    handshake_cache_ttl: Option<Duration>,
+    // When the most recent full handshake completed.
+    last_handshake: Option<Instant>,
+    // How long to wait for a new TCP connection to be established, if bounded.
+    connect_timeout: Option<Duration>,
 }
 
 /// Holds an available socket, with logic to return the socket
@@ -58,6 +99,11 @@ pub struct PooledStream {
     // This socket option will always be Some(stream) until it is
     // returned to the pool using take().
     socket: Option<BufStream<Stream>>,
+    // The server the socket is connected to.
+    host: Host,
+    // The scratch buffer that messages are serialized into before being written to the socket.
+    // It belongs to this connection and is returned to the pool along with the socket.
+    write_buffer: Vec<u8>,
     // A reference to the pool that the stream was taken from.
     pool: Arc<Mutex<Pool>>,
     // A reference to the waiting condvar associated with the pool.
@@ -66,6 +112,8 @@ pub struct PooledStream {
     iteration: usize,
     // Whether the handshake occurred successfully.
     successful_handshake: bool,
+    // The time spent establishing this connection.
+    metrics: ConnectionMetrics,
 }
 
 impl PooledStream {
@@ -73,6 +121,27 @@ impl PooledStream {
     pub fn get_socket(&mut self) -> &mut BufStream<Stream> {
         self.socket.as_mut().unwrap()
     }
+
+    /// Returns the server the stream is connected to.
+    pub fn host(&self) -> &Host {
+        &self.host
+    }
+
+    /// Serializes the message into this connection's reusable buffer and writes it to the socket.
+    pub fn write_message(&mut self, message: &Message) -> Result<()> {
+        let socket = self.socket.as_mut().unwrap();
+        message.write_with_buffer(socket, &mut self.write_buffer)
+    }
+
+    /// Returns whether the underlying socket is encrypted with TLS.
+    pub fn is_tls(&self) -> bool {
+        self.socket.as_ref().map_or(false, |socket| socket.get_ref().is_tls())
+    }
+
+    /// Returns the time spent establishing this connection, broken down by phase.
+    pub fn connection_metrics(&self) -> ConnectionMetrics {
+        self.metrics
+    }
 }
 
 impl Drop for PooledStream {
@@ -86,7 +155,9 @@ impl Drop for PooledStream {
         // or give up if the pool lock has been poisoned.
         if let Ok(mut locked) = self.pool.lock() {
             if self.iteration == locked.iteration {
-                locked.sockets.push(self.socket.take().unwrap());
+                let write_buffer = mem::replace(&mut self.write_buffer, Vec::new());
+                let socket = self.socket.take().unwrap();
+                locked.sockets.push((socket, write_buffer, Instant::now(), self.metrics));
                 // Notify waiting threads that the pool has been repopulated.
                 self.wait_lock.notify_one();
             }
@@ -108,8 +179,15 @@ impl ConnectionPool {
             inner: Arc::new(Mutex::new(Pool {
                 len: Arc::new(AtomicUsize::new(0)),
                 size: size,
+                min_size: 0,
+                max_idle_time: None,
+                keep_alive_interval: None,
                 sockets: Vec::with_capacity(size),
                 iteration: 0,
+                last_metrics: None,
+                handshake_cache_ttl: None,
+                last_handshake: None,
+                connect_timeout: None,
             })),
             stream_connector: connector,
         }
@@ -128,12 +206,191 @@ impl ConnectionPool {
         }
     }
 
+    /// Sets the number of connections that background maintenance keeps open.
+    pub fn set_min_size(&self, min_size: usize) -> Result<()> {
+        let mut locked = self.inner.lock()?;
+        if min_size > locked.size {
+            return Err(ArgumentError(String::from(
+                "The minimum connection pool size must not exceed the maximum size.",
+            )));
+        }
+
+        locked.min_size = min_size;
+        Ok(())
+    }
+
+    /// Sets how long a connection may sit idle in the pool before it is closed.
+    pub fn set_max_idle_time(&self, max_idle_time: Option<Duration>) -> Result<()> {
+        let mut locked = self.inner.lock()?;
+        locked.max_idle_time = max_idle_time;
+        Ok(())
+    }
+
+    /// Sets how long a connection may sit idle in the pool before background maintenance runs an
+    /// `isMaster` command on it, so that idle connections are kept open by intermediate firewalls
+    /// and dead connections are closed before they are next acquired.
+    pub fn set_keep_alive_interval(&self, interval: Option<Duration>) -> Result<()> {
+        let mut locked = self.inner.lock()?;
+        locked.keep_alive_interval = interval;
+        Ok(())
+    }
+
+    /// Sets how long after a full handshake new connections skip the isMaster handshake
+    /// entirely. Caching is disabled by default, since the handshake is also how each
+    /// connection reports its client metadata to the server.
+    pub fn set_handshake_cache_ttl(&self, ttl: Option<Duration>) -> Result<()> {
+        let mut locked = self.inner.lock()?;
+        locked.handshake_cache_ttl = ttl;
+        Ok(())
+    }
+
+    /// Sets how long to wait for the TCP connection of a new stream to be established before
+    /// giving up. Connections wait for the operating system's timeout by default.
+    pub fn set_connect_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        let mut locked = self.inner.lock()?;
+        locked.connect_timeout = timeout;
+        Ok(())
+    }
+
+    /// Closes the idle connections that have exceeded the max idle time, returning the number
+    /// of connections closed.
+    pub fn reap_idle(&self) -> usize {
+        let mut locked = match self.inner.lock() {
+            Ok(locked) => locked,
+            Err(_) => return 0,
+        };
+
+        let max_idle_time = match locked.max_idle_time {
+            Some(max_idle_time) => max_idle_time,
+            None => return 0,
+        };
+
+        let before = locked.sockets.len();
+        locked.sockets.retain(|&(_, _, idle_since, _)| idle_since.elapsed() < max_idle_time);
+
+        let reaped = before - locked.sockets.len();
+        let _ = locked.len.fetch_sub(reaped, Ordering::SeqCst);
+        reaped
+    }
+
+    /// Runs an `isMaster` command on each connection that has been idle for longer than the
+    /// keep-alive interval, closing the connections that fail to reply. Returns the number of
+    /// connections pinged.
+    pub fn keep_alive_idle(&self, client: Client) -> usize {
+        let (streams, iteration) = {
+            let mut locked = match self.inner.lock() {
+                Ok(locked) => locked,
+                Err(_) => return 0,
+            };
+
+            let interval = match locked.keep_alive_interval {
+                Some(interval) => interval,
+                None => return 0,
+            };
+
+            let (idle, fresh): (Vec<_>, Vec<_>) = locked
+                .sockets
+                .drain(..)
+                .partition(|&(_, _, idle_since, _)| idle_since.elapsed() >= interval);
+            locked.sockets = fresh;
+            (idle, locked.iteration)
+        };
+
+        let pinged = streams.len();
+        for (socket, write_buffer, _, metrics) in streams {
+            let mut stream = PooledStream {
+                socket: Some(socket),
+                host: self.host.clone(),
+                write_buffer: write_buffer,
+                pool: self.inner.clone(),
+                wait_lock: self.wait_lock.clone(),
+                iteration: iteration,
+                successful_handshake: true,
+                metrics: metrics,
+            };
+
+            // Dropping the stream returns it to the pool with a fresh idle time, unless the
+            // server failed to reply.
+            if self.ping(client.clone(), &mut stream).is_err() {
+                stream.successful_handshake = false;
+                if let Ok(locked) = self.inner.lock() {
+                    if locked.iteration == iteration {
+                        let _ = locked.len.fetch_sub(1, Ordering::SeqCst);
+                    }
+                }
+
+                // Wake a thread waiting for the connection slot that was just freed.
+                self.wait_lock.notify_one();
+            }
+        }
+
+        pinged
+    }
+
+    /// Opens new connections until the pool holds at least its minimum number of connections.
+    pub fn ensure_min_size(&self, client: Client) -> Result<()> {
+        loop {
+            let stream = {
+                let locked = self.inner.lock()?;
+                let len = locked.len.load(Ordering::SeqCst);
+                if len >= locked.min_size || len >= locked.size {
+                    return Ok(());
+                }
+                self.open_stream(locked, client.clone())?
+            };
+
+            // Dropping the stream returns it to the idle pool.
+            drop(stream);
+        }
+    }
+
+    /// Starts a background thread that reaps idle connections and restores the minimum pool
+    /// size every `interval`. The thread exits once the pool or the client is dropped.
+    pub fn start_maintenance(&self, client: &Client, interval: Duration) {
+        let inner = Arc::downgrade(&self.inner);
+        let client = Arc::downgrade(client);
+        let host = self.host.clone();
+        let wait_lock = self.wait_lock.clone();
+        let stream_connector = self.stream_connector.clone();
+
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+
+            let (inner, client) = match (inner.upgrade(), client.upgrade()) {
+                (Some(inner), Some(client)) => (inner, client),
+                _ => return,
+            };
+
+            let pool = ConnectionPool {
+                host: host.clone(),
+                inner: inner,
+                wait_lock: wait_lock.clone(),
+                stream_connector: stream_connector.clone(),
+            };
+
+            pool.reap_idle();
+            pool.keep_alive_idle(client.clone());
+
+            // Connection failures are retried at the next interval.
+            let _ = pool.ensure_min_size(client);
+        });
+    }
+
     // Clear all open socket connections.
     pub fn clear(&self) {
         if let Ok(mut locked) = self.inner.lock() {
             locked.iteration += 1;
             locked.sockets.clear();
             locked.len.store(0, Ordering::SeqCst);
+            locked.last_handshake = None;
+        }
+    }
+
+    /// Returns the connection metrics of the most recently established connection, if any.
+    pub fn last_connection_metrics(&self) -> Option<ConnectionMetrics> {
+        match self.inner.lock() {
+            Ok(locked) => locked.last_metrics,
+            Err(_) => None,
         }
     }
 
@@ -150,31 +407,29 @@ impl ConnectionPool {
 
         loop {
             // Acquire available existing socket
-            if let Some(stream) = locked.sockets.pop() {
+            if let Some((stream, write_buffer, idle_since, metrics)) = locked.sockets.pop() {
+                // Close connections that have been idle for too long.
+                if locked.max_idle_time.map_or(false, |max| idle_since.elapsed() >= max) {
+                    let _ = locked.len.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+
                 return Ok(PooledStream {
                     socket: Some(stream),
+                    host: self.host.clone(),
+                    write_buffer: write_buffer,
                     pool: self.inner.clone(),
                     wait_lock: self.wait_lock.clone(),
                     iteration: locked.iteration,
                     successful_handshake: true,
+                    metrics: metrics,
                 });
             }
 
             // Attempt to make a new connection
             let len = locked.len.load(Ordering::SeqCst);
             if len < locked.size {
-                let socket = self.connect()?;
-                let mut stream = PooledStream {
-                    socket: Some(socket),
-                    pool: self.inner.clone(),
-                    wait_lock: self.wait_lock.clone(),
-                    iteration: locked.iteration,
-                    successful_handshake: false,
-                };
-
-                self.handshake(client, &mut stream)?;
-                let _ = locked.len.fetch_add(1, Ordering::SeqCst);
-                return Ok(stream);
+                return self.open_stream(locked, client);
             }
 
             // Release lock and wait for pool to be repopulated
@@ -182,17 +437,135 @@ impl ConnectionPool {
         }
     }
 
+    // Counts a new connection against the pool size, then releases the lock while the new
+    // stream connects and handshakes so that other threads can use the pool in the meantime.
+    fn open_stream(&self, locked: MutexGuard<Pool>, client: Client) -> Result<PooledStream> {
+        let _ = locked.len.fetch_add(1, Ordering::SeqCst);
+        let iteration = locked.iteration;
+        let cached = match (locked.handshake_cache_ttl, locked.last_handshake) {
+            (Some(ttl), Some(last_handshake)) => last_handshake.elapsed() < ttl,
+            _ => false,
+        };
+        let connect_timeout = locked.connect_timeout;
+        drop(locked);
+
+        match self.connect_stream(client, iteration, cached, connect_timeout) {
+            Ok(stream) => Ok(stream),
+            Err(err) => {
+                // Give up the reserved connection and wake a thread waiting for one.
+                if let Ok(locked) = self.inner.lock() {
+                    if locked.iteration == iteration {
+                        let _ = locked.len.fetch_sub(1, Ordering::SeqCst);
+                    }
+                }
+                self.wait_lock.notify_one();
+                Err(err)
+            }
+        }
+    }
+
+    // Connects and handshakes a new stream, skipping the handshake if a recent one is cached.
+    fn connect_stream(
+        &self,
+        client: Client,
+        iteration: usize,
+        cached: bool,
+        connect_timeout: Option<Duration>,
+    ) -> Result<PooledStream> {
+        let (socket, tcp_connect, tls_handshake) = self.connect(connect_timeout)?;
+        let mut stream = PooledStream {
+            socket: Some(socket),
+            host: self.host.clone(),
+            write_buffer: Vec::new(),
+            pool: self.inner.clone(),
+            wait_lock: self.wait_lock.clone(),
+            iteration: iteration,
+            successful_handshake: false,
+            metrics: ConnectionMetrics {
+                tcp_connect: tcp_connect,
+                tls_handshake: tls_handshake,
+                handshake: Duration::from_millis(0),
+            },
+        };
+
+        let handshake_start = Instant::now();
+        if cached {
+            stream.successful_handshake = true;
+        } else {
+            self.handshake(client, &mut stream)?;
+        }
+        stream.metrics.handshake = handshake_start.elapsed();
+
+        let mut locked = self.inner.lock()?;
+        if !cached {
+            locked.last_handshake = Some(Instant::now());
+        }
+        locked.last_metrics = Some(stream.metrics);
+
+        Ok(stream)
+    }
+
     // Connects to a MongoDB server as defined by the initial configuration.
-    fn connect(&self) -> Result<BufStream<Stream>> {
-        match self.stream_connector.connect(
-            &self.host.host_name[..],
-            self.host.port,
-        ) {
-            Ok(s) => Ok(BufStream::new(s)),
+    fn connect(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<(BufStream<Stream>, Duration, Option<Duration>)> {
+        let connected = if self.host.has_ipc() {
+            self.connect_ipc()
+        } else {
+            self.stream_connector.connect_with_timeout(
+                &self.host.host_name[..],
+                self.host.port,
+                timeout,
+            )
+        };
+
+        match connected {
+            Ok((s, tcp_time, tls_time)) => Ok((BufStream::new(s), tcp_time, tls_time)),
             Err(e) => Err(Error::from(e)),
         }
     }
 
+    #[cfg(unix)]
+    fn connect_ipc(&self) -> io::Result<(Stream, Duration, Option<Duration>)> {
+        self.stream_connector.connect_unix_with_timing(&self.host.ipc)
+    }
+
+    #[cfg(not(unix))]
+    fn connect_ipc(&self) -> io::Result<(Stream, Duration, Option<Duration>)> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Unix domain sockets are not supported on this platform.",
+        ))
+    }
+
+    // Runs a lightweight `isMaster` command on an idle connection. Unlike `hello`, it is
+    // supported by every server version this driver talks to.
+    fn ping(&self, client: Client, stream: &mut PooledStream) -> Result<()> {
+        let mut options = FindOptions::new();
+        options.limit = Some(1);
+        options.batch_size = Some(1);
+
+        let flags = OpQueryFlags::with_find_options(&options);
+
+        let mut cursor = Cursor::query_with_stream(
+            stream,
+            client,
+            String::from("admin.$cmd"),
+            flags,
+            doc! { "isMaster": 1i32 },
+            options,
+            CommandType::Suppressed,
+            false,
+        )?;
+
+        match cursor.next() {
+            Some(Ok(_)) => Ok(()),
+            Some(Err(err)) => Err(err),
+            None => Err(OperationError(String::from("No reply to keep-alive command."))),
+        }
+    }
+
     // This sends the client metadata to the server as described by the handshake spec.
     //
     // See https://github.com/mongodb/specifications/blob/master/source/mongodb-handshake/handshake.rst
@@ -203,6 +576,21 @@ impl ConnectionPool {
 
         let flags = OpQueryFlags::with_find_options(&options);
 
+        let mut metadata = doc! {
+            "driver": {
+                "name": ::DRIVER_NAME,
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "os": {
+                "type": ::std::env::consts::OS,
+                "architecture": ::std::env::consts::ARCH
+            }
+        };
+
+        if let Some(ref app_name) = client.topology.config.app_name {
+            metadata.insert("application", doc! { "name": app_name.to_owned() });
+        }
+
         Cursor::query_with_stream(
             stream,
             client,
@@ -210,21 +598,11 @@ impl ConnectionPool {
             flags,
             doc! {
                 "isMaster": 1i32,
-                "client": {
-                    "driver": {
-                        "name": ::DRIVER_NAME,
-                        "version": env!("CARGO_PKG_VERSION"),
-                    },
-                    "os": {
-                        "type": ::std::env::consts::OS,
-                        "architecture": ::std::env::consts::ARCH
-                    }
-                },
+                "client": metadata,
             },
             options,
             CommandType::IsMaster,
             false,
-            None,
         )?;
 
         stream.successful_handshake = true;
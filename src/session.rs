@@ -0,0 +1,295 @@
+//! Explicit client sessions and multi-document transactions.
+use bson::{Bson, Document, bson, doc};
+use coll::{is_retryable_write_reply, is_state_change_message};
+use coll::error::WriteConcernError;
+use common::{ReadConcern, Timestamp, WriteConcern};
+use db::ThreadedDatabase;
+use Error::{ArgumentError, IoError, OperationError};
+
+use {Client, CommandType, Result, ThreadedClient};
+
+// The write concern timeout used when retrying a commit whose write concern sets none.
+const DEFAULT_COMMIT_RETRY_W_TIMEOUT_MS: i32 = 10000;
+
+/// Options applied to a multi-document transaction.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionOptions {
+    /// The read concern sent with the first statement of the transaction.
+    pub read_concern: Option<ReadConcern>,
+    /// The write concern sent with `commitTransaction` and `abortTransaction`.
+    pub write_concern: Option<WriteConcern>,
+}
+
+impl TransactionOptions {
+    pub fn new() -> TransactionOptions {
+        Default::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransactionState {
+    None,
+    // Started, but no statement has been sent yet.
+    Starting,
+    InProgress,
+    Committed,
+    Aborted,
+}
+
+/// A logical session on which commands are run with a shared `lsid`, gossiping the most recent
+/// cluster time seen by the session back to the server.
+#[derive(Debug)]
+pub struct ClientSession {
+    client: Client,
+    id: Document,
+    cluster_time: Option<Document>,
+    operation_time: Option<Timestamp>,
+    txn_number: i64,
+    transaction_state: TransactionState,
+    transaction_options: TransactionOptions,
+}
+
+impl ClientSession {
+    /// Starts a session with a newly generated logical session id.
+    pub fn new(client: Client) -> ClientSession {
+        ClientSession {
+            client: client,
+            id: ::new_session_id(),
+            cluster_time: None,
+            operation_time: None,
+            txn_number: 0,
+            transaction_state: TransactionState::None,
+            transaction_options: TransactionOptions::new(),
+        }
+    }
+
+    /// Returns the client the session was started from.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Returns the logical session id, of the form `{ id: <UUID> }`.
+    pub fn id(&self) -> &Document {
+        &self.id
+    }
+
+    /// Returns the greatest `$clusterTime` document seen by the session, if any.
+    pub fn cluster_time(&self) -> Option<&Document> {
+        self.cluster_time.as_ref()
+    }
+
+    /// Returns the `operationTime` of the last reply seen by the session, if any.
+    pub fn operation_time(&self) -> Option<Timestamp> {
+        self.operation_time
+    }
+
+    /// Advances the session's cluster time if `cluster_time` is greater than the current one.
+    pub fn advance_cluster_time(&mut self, cluster_time: &Document) {
+        let new_time = match cluster_time.get("clusterTime").and_then(Timestamp::from_bson) {
+            Some(time) => time,
+            None => return,
+        };
+
+        let current = self.cluster_time
+            .as_ref()
+            .and_then(|doc| doc.get("clusterTime"))
+            .and_then(Timestamp::from_bson);
+
+        if current.map_or(true, |time| new_time > time) {
+            self.cluster_time = Some(cluster_time.clone());
+        }
+    }
+
+    /// Advances the session's operation time if `operation_time` is greater than the current one.
+    pub fn advance_operation_time(&mut self, operation_time: Timestamp) {
+        if self.operation_time.map_or(true, |time| operation_time > time) {
+            self.operation_time = Some(operation_time);
+        }
+    }
+
+    /// Returns whether a transaction has been started and not yet committed or aborted.
+    pub fn in_transaction(&self) -> bool {
+        match self.transaction_state {
+            TransactionState::Starting | TransactionState::InProgress => true,
+            _ => false,
+        }
+    }
+
+    /// Attaches the session id and, when known, the session's cluster time to a command. Within
+    /// a transaction, the transaction number is attached as well, and the first statement is
+    /// marked as starting the transaction.
+    pub fn apply_to(&mut self, cmd: &mut Document) {
+        self.apply_session_fields(cmd);
+
+        match self.transaction_state {
+            TransactionState::Starting => {
+                cmd.insert("txnNumber", self.txn_number);
+                cmd.insert("startTransaction", true);
+                cmd.insert("autocommit", false);
+                if let Some(read_concern) = self.transaction_options.read_concern {
+                    cmd.insert("readConcern", read_concern.to_document());
+                }
+                self.transaction_state = TransactionState::InProgress;
+            }
+            TransactionState::InProgress => {
+                cmd.insert("txnNumber", self.txn_number);
+                cmd.insert("autocommit", false);
+            }
+            _ => (),
+        }
+    }
+
+    /// Starts a multi-document transaction. Commands run with `command_with_session` are part
+    /// of the transaction until it is committed or aborted.
+    pub fn start_transaction(&mut self, options: Option<TransactionOptions>) -> Result<()> {
+        if self.in_transaction() {
+            return Err(ArgumentError(String::from("A transaction is already in progress.")));
+        }
+
+        self.txn_number += 1;
+        self.transaction_state = TransactionState::Starting;
+        self.transaction_options = options.unwrap_or_else(TransactionOptions::new);
+        Ok(())
+    }
+
+    /// Commits the transaction against the `admin` database. The commit is retried once with a
+    /// majority write concern if its outcome is unknown, e.g. after a network error or a reply
+    /// labeled `UnknownTransactionCommitResult`.
+    pub fn commit_transaction(&mut self) -> Result<()> {
+        match self.transaction_state {
+            TransactionState::None => {
+                return Err(ArgumentError(String::from("No transaction started.")))
+            }
+            TransactionState::Aborted => {
+                return Err(ArgumentError(String::from(
+                    "Cannot commit a transaction after it was aborted.",
+                )))
+            }
+            // A transaction without statements is never sent to the server.
+            TransactionState::Starting => {
+                self.transaction_state = TransactionState::Committed;
+                return Ok(());
+            }
+            TransactionState::InProgress | TransactionState::Committed => (),
+        }
+
+        self.transaction_state = TransactionState::Committed;
+
+        let mut cmd = doc! { "commitTransaction": 1 };
+        if let Some(ref write_concern) = self.transaction_options.write_concern {
+            cmd.insert("writeConcern", write_concern.to_bson());
+        }
+
+        let result = self.run_transaction_command(cmd.clone(), CommandType::CommitTransaction);
+        let result = if is_unknown_commit_result(&result) {
+            let w_timeout = match self.transaction_options.write_concern {
+                Some(ref write_concern) if write_concern.w_timeout > 0 => write_concern.w_timeout,
+                _ => DEFAULT_COMMIT_RETRY_W_TIMEOUT_MS,
+            };
+            cmd.insert("writeConcern", doc! { "w": "majority", "wtimeout": w_timeout });
+            self.run_transaction_command(cmd, CommandType::CommitTransaction)
+        } else {
+            result
+        };
+
+        let reply = result?;
+        match reply.get("writeConcernError") {
+            Some(&Bson::Document(ref error)) => {
+                let write_concern = self.transaction_options
+                    .write_concern
+                    .clone()
+                    .unwrap_or_default();
+                Err(WriteConcernError::parse(error.clone(), write_concern)?.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Aborts the transaction against the `admin` database, discarding its writes. Errors from
+    /// the server are ignored, since the transaction is aborted when it times out regardless.
+    pub fn abort_transaction(&mut self) -> Result<()> {
+        match self.transaction_state {
+            TransactionState::None => {
+                return Err(ArgumentError(String::from("No transaction started.")))
+            }
+            TransactionState::Committed => {
+                return Err(ArgumentError(String::from(
+                    "Cannot abort a transaction after it was committed.",
+                )))
+            }
+            TransactionState::Aborted => {
+                return Err(ArgumentError(String::from("The transaction was already aborted.")))
+            }
+            TransactionState::Starting => {
+                self.transaction_state = TransactionState::Aborted;
+                return Ok(());
+            }
+            TransactionState::InProgress => (),
+        }
+
+        self.transaction_state = TransactionState::Aborted;
+
+        let mut cmd = doc! { "abortTransaction": 1 };
+        if let Some(ref write_concern) = self.transaction_options.write_concern {
+            cmd.insert("writeConcern", write_concern.to_bson());
+        }
+
+        let _ = self.run_transaction_command(cmd, CommandType::AbortTransaction);
+        Ok(())
+    }
+
+    fn apply_session_fields(&self, cmd: &mut Document) {
+        cmd.insert("lsid", self.id.clone());
+        if let Some(ref cluster_time) = self.cluster_time {
+            cmd.insert("$clusterTime", cluster_time.clone());
+        }
+    }
+
+    // Runs commitTransaction or abortTransaction against admin for the current transaction.
+    fn run_transaction_command(
+        &mut self,
+        mut cmd: Document,
+        cmd_type: CommandType,
+    ) -> Result<Document> {
+        self.apply_session_fields(&mut cmd);
+        cmd.insert("txnNumber", self.txn_number);
+        cmd.insert("autocommit", false);
+
+        let reply = self.client.db("admin").command(cmd, cmd_type, None)?;
+        self.update_from_reply(&reply);
+        Ok(reply)
+    }
+
+    /// Records the `$clusterTime` and `operationTime` of a command reply.
+    pub fn update_from_reply(&mut self, reply: &Document) {
+        if let Some(&Bson::Document(ref cluster_time)) = reply.get("$clusterTime") {
+            self.advance_cluster_time(cluster_time);
+        }
+
+        if let Some(time) = reply.get("operationTime").and_then(Timestamp::from_bson) {
+            self.advance_operation_time(time);
+        }
+    }
+}
+
+// Returns whether a commitTransaction attempt failed without the outcome of the commit being
+// known, so that it may be safely retried.
+fn is_unknown_commit_result(result: &Result<Document>) -> bool {
+    match *result {
+        Ok(ref reply) => {
+            let labeled = match reply.get("errorLabels") {
+                Some(&Bson::Array(ref labels)) => {
+                    labels.iter().any(|label| match *label {
+                        Bson::String(ref label) => label == "UnknownTransactionCommitResult",
+                        _ => false,
+                    })
+                }
+                _ => false,
+            };
+            labeled || is_retryable_write_reply(reply)
+        }
+        Err(IoError(_)) => true,
+        Err(OperationError(ref msg)) => is_state_change_message(msg),
+        Err(_) => false,
+    }
+}
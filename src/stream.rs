@@ -1,10 +1,25 @@
-use std::io::{BufReader, Read, Result, Write};
-#[cfg(feature = "ssl")]
-use std::io::{Error, ErrorKind};
-use std::net::{SocketAddr, TcpStream};
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::net::Ipv4Addr;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "ssl")]
 use openssl::ssl::{Ssl, SslContext, SslFiletype, SslMethod, SslOptions, SslStream, SslVerifyMode};
+#[cfg(feature = "tls-rustls")]
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+#[cfg(feature = "tls-rustls")]
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+#[cfg(feature = "tls-rustls")]
+use rustls::pki_types::pem::PemObject;
+#[cfg(feature = "tls-rustls")]
+use std::convert::TryFrom;
+#[cfg(feature = "tls-rustls")]
+use std::sync::Arc;
 
 /// Encapsulates the functionality for how to connect to the server.
 #[derive(Clone)]
@@ -15,12 +30,107 @@ pub enum StreamConnector {
     /// Connect to the server through a TCP stream encrypted with SSL.
     ///
     /// Note that it's invalid to have one of certificate_file and key_file set but not the other.
+    ///
+    /// When `verify_peer` is set, the server's certificate must also match the hostname
+    /// connected to.
     Ssl {
         ca_file: Option<String>,
         certificate_file: Option<String>,
         key_file: Option<String>,
         verify_peer: bool,
     },
+    #[cfg(feature = "ssl")]
+    /// Connect to the server through a TCP stream encrypted with SSL, configured through
+    /// `TlsOptions`.
+    Tls(TlsOptions),
+    #[cfg(feature = "ssl")]
+    /// Connect to the server through a TCP stream encrypted with SSL, using a pre-built
+    /// OpenSSL context.
+    ///
+    /// The context is used as-is, so certificate and hostname verification are whatever the
+    /// context was configured with.
+    SslContext(SslContext),
+    #[cfg(feature = "tls-rustls")]
+    /// Connect to the server through a TCP stream encrypted with rustls, using the provided
+    /// client configuration.
+    Rustls(Arc<ClientConfig>),
+}
+
+#[cfg(feature = "ssl")]
+/// TLS settings for connectors that need more control than `StreamConnector::with_ssl`, such as
+/// accepting invalid hostnames or advertising ALPN protocols.
+///
+/// ```no_run
+/// # use mongodb::stream::{StreamConnector, TlsOptions};
+/// let options = TlsOptions::new()
+///     .ca_file("/certs/ca.pem")
+///     .verify_hostname(false)
+///     .alpn_protocols(vec![String::from("mongodb")]);
+/// let connector = StreamConnector::with_tls(options);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsOptions {
+    ca_file: Option<String>,
+    certificate_file: Option<String>,
+    key_file: Option<String>,
+    verify_peer: bool,
+    verify_hostname: bool,
+    alpn_protocols: Vec<String>,
+}
+
+#[cfg(feature = "ssl")]
+impl TlsOptions {
+    /// Creates TLS settings without a client certificate that verify both the server's
+    /// certificate and its hostname.
+    pub fn new() -> TlsOptions {
+        TlsOptions {
+            ca_file: None,
+            certificate_file: None,
+            key_file: None,
+            verify_peer: true,
+            verify_hostname: true,
+            alpn_protocols: Vec::new(),
+        }
+    }
+
+    /// Sets the path to the file containing trusted CA certificates.
+    pub fn ca_file(mut self, ca_file: &str) -> TlsOptions {
+        self.ca_file = Some(String::from(ca_file));
+        self
+    }
+
+    /// Sets the paths to the files containing the client certificate and private key.
+    pub fn client_certificate(mut self, certificate_file: &str, key_file: &str) -> TlsOptions {
+        self.certificate_file = Some(String::from(certificate_file));
+        self.key_file = Some(String::from(key_file));
+        self
+    }
+
+    /// Sets whether to verify that the server's certificate is trusted.
+    pub fn verify_peer(mut self, verify_peer: bool) -> TlsOptions {
+        self.verify_peer = verify_peer;
+        self
+    }
+
+    /// Sets whether to verify that the server's certificate matches the hostname connected to.
+    pub fn verify_hostname(mut self, verify_hostname: bool) -> TlsOptions {
+        self.verify_hostname = verify_hostname;
+        self
+    }
+
+    /// Sets the protocols to advertise through ALPN during the handshake, in order of
+    /// preference.
+    pub fn alpn_protocols(mut self, alpn_protocols: Vec<String>) -> TlsOptions {
+        self.alpn_protocols = alpn_protocols;
+        self
+    }
+}
+
+#[cfg(feature = "ssl")]
+impl Default for TlsOptions {
+    fn default() -> Self {
+        TlsOptions::new()
+    }
 }
 
 impl Default for StreamConnector {
@@ -48,7 +158,8 @@ impl StreamConnector {
     /// `ca_file` - Path to the file containing trusted CA certificates.
     /// `certificate_file` - Path to the file containing the client certificate.
     /// `key_file` - Path to the file containing the client private key.
-    /// `verify_peer` - Whether or not to verify that the server's certificate is trusted.
+    /// `verify_peer` - Whether or not to verify that the server's certificate is trusted and
+    ///                 matches the server's hostname.
     pub fn with_ssl(
         ca_file: Option<&str>,
         certificate_file: &str,
@@ -79,7 +190,8 @@ impl StreamConnector {
     /// # Arguments
     ///
     /// `ca_file` - Path to the file containing trusted CA certificates.
-    /// `verify_peer` - Whether or not to verify that the server's certificate is trusted.
+    /// `verify_peer` - Whether or not to verify that the server's certificate is trusted and
+    ///                 matches the server's hostname.
     pub fn with_unauthenticated_ssl(ca_file: Option<&str>, verify_peer: bool) -> Self {
         StreamConnector::Ssl {
             ca_file: ca_file.map(String::from),
@@ -89,15 +201,102 @@ impl StreamConnector {
         }
     }
 
+    #[cfg(feature = "ssl")]
+    /// Creates a StreamConnector that will connect with SSL encryption using the provided
+    /// OpenSSL context, for callers that need more control than the file-based options allow
+    /// (e.g. custom verification callbacks or hardware-backed keys).
+    pub fn with_ssl_context(context: SslContext) -> Self {
+        StreamConnector::SslContext(context)
+    }
+
+    #[cfg(feature = "ssl")]
+    /// Creates a StreamConnector that will connect with SSL encryption using the provided
+    /// TLS settings. Ciphers and protocols are restricted as for `with_ssl`.
+    pub fn with_tls(options: TlsOptions) -> Self {
+        StreamConnector::Tls(options)
+    }
+
+    #[cfg(feature = "tls-rustls")]
+    /// Creates a StreamConnector that will connect with TLS encryption through rustls,
+    /// presenting the client certificate in `certificate_file` and its private key in
+    /// `key_file`.
+    ///
+    /// The server's certificate is trusted if it chains to one of the Mozilla root certificates
+    /// bundled by `webpki-roots` or to a certificate in `ca_file`, and must always match the
+    /// hostname connected to; rustls offers no way to skip verification.
+    pub fn with_rustls(
+        ca_file: Option<&str>,
+        certificate_file: &str,
+        key_file: &str,
+    ) -> Result<Self> {
+        let config = rustls_config(ca_file, Some((certificate_file, key_file)))?;
+        Ok(StreamConnector::Rustls(Arc::new(config)))
+    }
+
+    #[cfg(feature = "tls-rustls")]
+    /// Creates an unauthenticated StreamConnector that will connect with TLS encryption through
+    /// rustls. The server's certificate is verified as for `with_rustls`.
+    pub fn with_unauthenticated_rustls(ca_file: Option<&str>) -> Result<Self> {
+        let config = rustls_config(ca_file, None)?;
+        Ok(StreamConnector::Rustls(Arc::new(config)))
+    }
+
+    #[cfg(feature = "tls-rustls")]
+    /// Creates a StreamConnector that will connect with TLS encryption through rustls using the
+    /// provided client configuration, which is used as-is.
+    pub fn with_rustls_config(config: Arc<ClientConfig>) -> Self {
+        StreamConnector::Rustls(config)
+    }
+
+    /// Returns whether streams created by this connector are encrypted with TLS.
+    pub fn is_tls(&self) -> bool {
+        match *self {
+            StreamConnector::Tcp => false,
+            #[cfg(feature = "ssl")]
+            StreamConnector::Ssl { .. } |
+            StreamConnector::Tls(_) |
+            StreamConnector::SslContext(_) => true,
+            #[cfg(feature = "tls-rustls")]
+            StreamConnector::Rustls(_) => true,
+        }
+    }
+
     pub fn connect(&self, hostname: &str, port: u16) -> Result<Stream> {
+        let (stream, _, _) = self.connect_with_timing(hostname, port)?;
+        Ok(stream)
+    }
+
+    /// Connects to the server, returning the stream along with the time spent establishing the
+    /// TCP connection and, for encrypted streams, the time spent on the TLS handshake.
+    pub fn connect_with_timing(
+        &self,
+        hostname: &str,
+        port: u16,
+    ) -> Result<(Stream, Duration, Option<Duration>)> {
+        self.connect_with_timeout(hostname, port, None)
+    }
+
+    /// Connects to the server as by `connect_with_timing`, giving up on each resolved address
+    /// if the TCP connection is not established within `timeout`.
+    pub fn connect_with_timeout(
+        &self,
+        hostname: &str,
+        port: u16,
+        timeout: Option<Duration>,
+    ) -> Result<(Stream, Duration, Option<Duration>)> {
+        let start = Instant::now();
+
         match *self {
             StreamConnector::Tcp => {
-                let stream = TcpStream::connect((hostname, port))?;
+                let stream = connect_tcp(hostname, port, timeout)?;
                 stream.set_nodelay(true)?;
-                Ok(Stream::Tcp {
+                let tcp_time = start.elapsed();
+
+                let stream = Stream::Tcp {
                     read_half: BufReader::new(stream.try_clone()?),
                     write_half: stream,
-                })
+                };
+                Ok((stream, tcp_time, None))
             }
             #[cfg(feature = "ssl")]
             StreamConnector::Ssl {
@@ -106,43 +305,209 @@ impl StreamConnector {
                 ref key_file,
                 verify_peer,
             } => {
-                let inner_stream = TcpStream::connect((hostname, port))?;
+                let options = TlsOptions {
+                    ca_file: ca_file.clone(),
+                    certificate_file: certificate_file.clone(),
+                    key_file: key_file.clone(),
+                    verify_peer: verify_peer,
+                    verify_hostname: verify_peer,
+                    alpn_protocols: Vec::new(),
+                };
+                connect_tls(&options, hostname, port, timeout)
+            }
+            #[cfg(feature = "ssl")]
+            StreamConnector::Tls(ref options) => connect_tls(options, hostname, port, timeout),
+            #[cfg(feature = "ssl")]
+            StreamConnector::SslContext(ref ssl_context) => {
+                let inner_stream = connect_tcp(hostname, port, timeout)?;
                 inner_stream.set_nodelay(true)?;
+                let tcp_time = start.elapsed();
+                let tls_start = Instant::now();
 
-                let mut ssl_context = SslContext::builder(SslMethod::tls())?;
-                ssl_context.set_cipher_list("ALL:!EXPORT:!eNULL:!aNULL:HIGH:@STRENGTH")?;
-                ssl_context.set_options(SslOptions::NO_SSLV2);
-                ssl_context.set_options(SslOptions::NO_SSLV3);
-                ssl_context.set_options(SslOptions::NO_COMPRESSION);
+                let mut ssl = Ssl::new(ssl_context)?;
+                ssl.set_hostname(hostname)?;
 
-                if let Some(ca_file) = ca_file {
-                    ssl_context.set_ca_file(ca_file)?;
+                match ssl.connect(inner_stream) {
+                    Ok(s) => Ok((Stream::Ssl(s), tcp_time, Some(tls_start.elapsed()))),
+                    Err(e) => Err(Error::new(ErrorKind::Other, e)),
                 }
+            }
+            #[cfg(feature = "tls-rustls")]
+            StreamConnector::Rustls(ref config) => {
+                let inner_stream = connect_tcp(hostname, port, timeout)?;
+                inner_stream.set_nodelay(true)?;
+                let tcp_time = start.elapsed();
+                let tls_start = Instant::now();
 
-                if let &Some(ref file) = certificate_file {
-                    ssl_context.set_certificate_file(file, SslFiletype::PEM)?;
-                }
-                if let &Some(ref file) = key_file {
-                    ssl_context.set_private_key_file(file, SslFiletype::PEM)?;
+                let server_name = ServerName::try_from(hostname.to_owned())
+                    .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+                let connection = ClientConnection::new(config.clone(), server_name)
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                // rustls handshakes lazily on first use, so drive it to completion here to time
+                // it and to report verification failures when connecting.
+                let mut stream = StreamOwned::new(connection, inner_stream);
+                while stream.conn.is_handshaking() {
+                    stream.conn.complete_io(&mut stream.sock)?;
                 }
 
-                let verify = if verify_peer {
-                    SslVerifyMode::PEER
-                } else {
-                    SslVerifyMode::NONE
-                };
-                ssl_context.set_verify(verify);
+                Ok((Stream::Rustls(stream), tcp_time, Some(tls_start.elapsed())))
+            }
+        }
+    }
+}
 
-                let mut ssl = Ssl::new(&ssl_context.build())?;
-                ssl.set_hostname(hostname)?;
+impl StreamConnector {
+    #[cfg(unix)]
+    /// Connects to a server listening on the Unix domain socket at `path`, returning the stream
+    /// along with the time spent connecting.
+    ///
+    /// Unix domain sockets are never encrypted, so this ignores any SSL configuration.
+    pub fn connect_unix_with_timing<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<(Stream, Duration, Option<Duration>)> {
+        let start = Instant::now();
+        let stream = UnixStream::connect(path)?;
+        let connect_time = start.elapsed();
 
-                match ssl.connect(inner_stream) {
-                    Ok(s) => Ok(Stream::Ssl(s)),
-                    Err(e) => Err(Error::new(ErrorKind::Other, e)),
-                }
-            }
+        let stream = Stream::Unix {
+            read_half: BufReader::new(stream.try_clone()?),
+            write_half: stream,
+        };
+        Ok((stream, connect_time, None))
+    }
+}
+
+// Opens a TCP connection to the first reachable address of the host, giving up on each address
+// after the timeout, if any.
+fn connect_tcp(hostname: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return TcpStream::connect((hostname, port)),
+    };
+
+    let mut last_err = None;
+    for addr in (hostname, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
         }
     }
+
+    Err(last_err.unwrap_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Could not resolve '{}:{}' to any address.", hostname, port),
+        )
+    }))
+}
+
+// Connects to the server with the given TLS settings, returning the stream along with the time
+// spent establishing the TCP connection and the time spent on the TLS handshake.
+#[cfg(feature = "ssl")]
+fn connect_tls(
+    options: &TlsOptions,
+    hostname: &str,
+    port: u16,
+    timeout: Option<Duration>,
+) -> Result<(Stream, Duration, Option<Duration>)> {
+    let start = Instant::now();
+    let inner_stream = connect_tcp(hostname, port, timeout)?;
+    inner_stream.set_nodelay(true)?;
+    let tcp_time = start.elapsed();
+    let tls_start = Instant::now();
+
+    let mut ssl_context = SslContext::builder(SslMethod::tls())?;
+    ssl_context.set_cipher_list("ALL:!EXPORT:!eNULL:!aNULL:HIGH:@STRENGTH")?;
+    ssl_context.set_options(SslOptions::NO_SSLV2);
+    ssl_context.set_options(SslOptions::NO_SSLV3);
+    ssl_context.set_options(SslOptions::NO_COMPRESSION);
+
+    if let Some(ref ca_file) = options.ca_file {
+        ssl_context.set_ca_file(ca_file)?;
+    }
+
+    if let Some(ref file) = options.certificate_file {
+        ssl_context.set_certificate_file(file, SslFiletype::PEM)?;
+    }
+    if let Some(ref file) = options.key_file {
+        ssl_context.set_private_key_file(file, SslFiletype::PEM)?;
+    }
+
+    let verify = if options.verify_peer {
+        SslVerifyMode::PEER
+    } else {
+        SslVerifyMode::NONE
+    };
+    ssl_context.set_verify(verify);
+
+    if !options.alpn_protocols.is_empty() {
+        ssl_context.set_alpn_protos(&encode_alpn_protocols(&options.alpn_protocols)?)?;
+    }
+
+    let mut ssl = Ssl::new(&ssl_context.build())?;
+    ssl.set_hostname(hostname)?;
+    if options.verify_hostname {
+        ssl.param_mut().set_host(hostname)?;
+    }
+
+    match ssl.connect(inner_stream) {
+        Ok(s) => Ok((Stream::Ssl(s), tcp_time, Some(tls_start.elapsed()))),
+        Err(e) => Err(Error::new(ErrorKind::Other, e)),
+    }
+}
+
+// Builds a rustls client configuration trusting the bundled Mozilla roots and any certificates
+// in `ca_file`, with an optional (certificate file, key file) pair for client authentication.
+#[cfg(feature = "tls-rustls")]
+fn rustls_config(
+    ca_file: Option<&str>,
+    client_certificate: Option<(&str, &str)>,
+) -> Result<ClientConfig> {
+    let invalid_pem = |e| Error::new(ErrorKind::InvalidData, e);
+
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(ca_file) = ca_file {
+        for cert in CertificateDer::pem_file_iter(ca_file).map_err(invalid_pem)? {
+            roots
+                .add(cert.map_err(invalid_pem)?)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        }
+    }
+
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    match client_certificate {
+        Some((certificate_file, key_file)) => {
+            let certs = CertificateDer::pem_file_iter(certificate_file)
+                .map_err(invalid_pem)?
+                .collect::<::std::result::Result<Vec<_>, _>>()
+                .map_err(invalid_pem)?;
+            let key = PrivateKeyDer::from_pem_file(key_file).map_err(invalid_pem)?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+        }
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+// Encodes protocol names in the length-prefixed wire format used by ALPN.
+#[cfg(feature = "ssl")]
+fn encode_alpn_protocols(protocols: &[String]) -> Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    for protocol in protocols {
+        if protocol.is_empty() || protocol.len() > 255 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid ALPN protocol name '{}'.", protocol),
+            ));
+        }
+        encoded.push(protocol.len() as u8);
+        encoded.extend_from_slice(protocol.as_bytes());
+    }
+    Ok(encoded)
 }
 
 pub enum Stream {
@@ -152,6 +517,13 @@ pub enum Stream {
     },
     #[cfg(feature = "ssl")]
     Ssl(SslStream<TcpStream>),
+    #[cfg(feature = "tls-rustls")]
+    Rustls(StreamOwned<ClientConnection, TcpStream>),
+    #[cfg(unix)]
+    Unix {
+        read_half: BufReader<UnixStream>,
+        write_half: UnixStream,
+    },
 }
 
 impl Read for Stream {
@@ -162,6 +534,12 @@ impl Read for Stream {
             } => read_half.read(buf),
             #[cfg(feature = "ssl")]
             Stream::Ssl(ref mut s) => s.read(buf),
+            #[cfg(feature = "tls-rustls")]
+            Stream::Rustls(ref mut s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix {
+                ref mut read_half, ..
+            } => read_half.read(buf),
         }
     }
 }
@@ -174,6 +552,12 @@ impl Write for Stream {
             } => write_half.write(buf),
             #[cfg(feature = "ssl")]
             Stream::Ssl(ref mut s) => s.write(buf),
+            #[cfg(feature = "tls-rustls")]
+            Stream::Rustls(ref mut s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix {
+                ref mut write_half, ..
+            } => write_half.write(buf),
         }
     }
 
@@ -184,16 +568,41 @@ impl Write for Stream {
             } => write_half.flush(),
             #[cfg(feature = "ssl")]
             Stream::Ssl(ref mut s) => s.flush(),
+            #[cfg(feature = "tls-rustls")]
+            Stream::Rustls(ref mut s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix {
+                ref mut write_half, ..
+            } => write_half.flush(),
         }
     }
 }
 
 impl Stream {
+    /// Returns whether the stream is encrypted with TLS.
+    pub fn is_tls(&self) -> bool {
+        match *self {
+            Stream::Tcp { .. } => false,
+            #[cfg(feature = "ssl")]
+            Stream::Ssl(_) => true,
+            #[cfg(feature = "tls-rustls")]
+            Stream::Rustls(_) => true,
+            #[cfg(unix)]
+            Stream::Unix { .. } => false,
+        }
+    }
+
+    /// Returns the address of the connected server. Unix domain sockets have no network
+    /// address, so they report the unspecified address with port 0.
     pub fn peer_addr(&self) -> Result<SocketAddr> {
         match *self {
             Stream::Tcp { ref write_half, .. } => write_half.peer_addr(),
             #[cfg(feature = "ssl")]
             Stream::Ssl(ref stream) => stream.get_ref().peer_addr(),
+            #[cfg(feature = "tls-rustls")]
+            Stream::Rustls(ref stream) => stream.get_ref().peer_addr(),
+            #[cfg(unix)]
+            Stream::Unix { .. } => Ok(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))),
         }
     }
 }
@@ -0,0 +1,123 @@
+//! Server selection errors.
+use common::ReadPreference;
+use connstring::Host;
+
+use std::{error, fmt};
+
+use super::TopologyType;
+use super::server::ServerType;
+
+/// The reason a known server was not selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectionReason {
+    /// The server has not been successfully checked, with the last monitoring error, if any.
+    Unknown(Option<String>),
+    /// The server type cannot satisfy the operation or read mode.
+    WrongType(ServerType),
+    /// The estimated replication lag of the secondary exceeds the max staleness.
+    Stale { staleness_ms: i64, max_staleness_ms: i64 },
+    /// The server tags match none of the read preference tag sets.
+    TagMismatch,
+    /// The server was eligible, but no connection could be acquired.
+    Unavailable,
+}
+
+/// The error returned when no suitable server is found within the server selection timeout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSelectionError {
+    /// The read preference being satisfied, or `None` when selecting a server for writes.
+    pub read_preference: Option<ReadPreference>,
+    /// The topology type at the time of the last selection attempt.
+    pub topology_type: TopologyType,
+    /// Each known server, with the reason it was not selected.
+    pub candidates: Vec<(Host, RejectionReason)>,
+    /// The error returned by the last selection attempt, if any.
+    pub last_error: Option<String>,
+    /// A summary of the above, listing each candidate and its rejection reason, which is also
+    /// the `Display` output of this error.
+    pub message: String,
+}
+
+impl ServerSelectionError {
+    /// Builds a selection error and its message from the rejected candidates.
+    pub fn new(
+        read_preference: Option<ReadPreference>,
+        topology_type: TopologyType,
+        timeout_ms: i64,
+        candidates: Vec<(Host, RejectionReason)>,
+        last_error: Option<String>,
+    ) -> ServerSelectionError {
+        let mut message = match read_preference {
+            Some(ref read_pref) => format!(
+                "Server selection timed out after {} ms for read preference {:?} in a {:?} \
+                 topology.",
+                timeout_ms,
+                read_pref.mode,
+                topology_type
+            ),
+            None => format!(
+                "Server selection timed out after {} ms for a write in a {:?} topology.",
+                timeout_ms,
+                topology_type
+            ),
+        };
+
+        if candidates.is_empty() {
+            message.push_str(" No servers are known.");
+        } else {
+            let servers: Vec<String> = candidates
+                .iter()
+                .map(|&(ref host, ref reason)| {
+                    format!("{}:{} ({})", host.host_name, host.port, reason)
+                })
+                .collect();
+            message.push_str(&format!(" Candidates: {}.", servers.join(", ")));
+        }
+
+        if let Some(ref err) = last_error {
+            message.push_str(&format!(" Last error: {}", err));
+        }
+
+        ServerSelectionError {
+            read_preference: read_preference,
+            topology_type: topology_type,
+            candidates: candidates,
+            last_error: last_error,
+            message: message,
+        }
+    }
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RejectionReason::Unknown(Some(ref err)) => write!(fmt, "unknown: {}", err),
+            RejectionReason::Unknown(None) => fmt.write_str("unknown: not yet checked"),
+            RejectionReason::WrongType(server_type) => {
+                write!(fmt, "wrong server type {:?}", server_type)
+            }
+            RejectionReason::Stale { staleness_ms, max_staleness_ms } => {
+                write!(
+                    fmt,
+                    "stale: {} ms behind, exceeding {} ms",
+                    staleness_ms,
+                    max_staleness_ms
+                )
+            }
+            RejectionReason::TagMismatch => fmt.write_str("tag mismatch"),
+            RejectionReason::Unavailable => fmt.write_str("unavailable"),
+        }
+    }
+}
+
+impl fmt::Display for ServerSelectionError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&self.message)
+    }
+}
+
+impl error::Error for ServerSelectionError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
@@ -1,4 +1,5 @@
 //! MongoDB server set topology and asynchronous monitoring.
+pub mod error;
 pub mod server;
 pub mod monitor;
 
@@ -18,16 +19,20 @@ use std::collections::HashMap;
 use std::fmt;
 use std::i64;
 use std::str::FromStr;
-use std::sync::{Arc, RwLock};
-use std::thread;
-use std::time::Duration;
-use time;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+use self::error::{RejectionReason, ServerSelectionError};
 use self::server::{Server, ServerDescription, ServerType};
 
 pub const DEFAULT_HEARTBEAT_FREQUENCY_MS: u32 = 10000;
 pub const DEFAULT_LOCAL_THRESHOLD_MS: i64 = 15;
 pub const DEFAULT_SERVER_SELECTION_TIMEOUT_MS: i64 = 30000;
+/// The minimum wire version that supports retryable writes (MongoDB 3.6).
+pub const RETRYABLE_WRITES_MIN_WIRE_VERSION: i64 = 6;
+
+/// The minimum wire version that supports maxStalenessSeconds (MongoDB 3.4).
+pub const MAX_STALENESS_MIN_WIRE_VERSION: i64 = 5;
 
 /// Describes the type of topology for a server set.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -39,6 +44,43 @@ pub enum TopologyType {
     Unknown,
 }
 
+// The longest server selection waits for a topology update before selecting again.
+const MAX_SELECTION_WAIT_MS: i64 = 500;
+
+// Wakes threads blocked in server selection whenever a monitor updates the topology. The
+// generation counts updates, so that an update between a failed selection attempt and the wait
+// that follows it is not missed.
+#[derive(Default)]
+struct UpdateSignal {
+    generation: Mutex<u64>,
+    condvar: Condvar,
+}
+
+impl UpdateSignal {
+    fn generation(&self) -> u64 {
+        self.generation.lock().map(|generation| *generation).unwrap_or(0)
+    }
+
+    fn notify(&self) {
+        if let Ok(mut generation) = self.generation.lock() {
+            *generation += 1;
+            self.condvar.notify_all();
+        }
+    }
+
+    // Blocks until the generation moves past `seen` or the timeout elapses.
+    fn wait_for_update(&self, seen: u64, timeout: Duration) {
+        let generation = match self.generation.lock() {
+            Ok(generation) => generation,
+            Err(_) => return,
+        };
+
+        if *generation == seen {
+            let _ = self.condvar.wait_timeout(generation, timeout);
+        }
+    }
+}
+
 /// Topology information gathered from server set monitoring.
 #[derive(Clone)]
 pub struct TopologyDescription {
@@ -66,6 +108,7 @@ pub struct TopologyDescription {
     max_set_version: Option<i64>,
     compat_error: String,
     stream_connector: StreamConnector,
+    update_signal: Arc<UpdateSignal>,
 }
 
 impl fmt::Debug for TopologyDescription {
@@ -123,10 +166,27 @@ impl Default for TopologyDescription {
             compat_error: String::new(),
             max_set_version: None,
             stream_connector: StreamConnector::Tcp,
+            update_signal: Arc::new(UpdateSignal::default()),
         }
     }
 }
 
+// Builds the error returned when no suitable server stream could be acquired, listing
+// the connection failure of each host that was attempted.
+fn no_servers_available(failures: Vec<(Host, Error)>) -> Error {
+    let mut message = String::from("No servers available for the provided ReadPreference.");
+
+    if !failures.is_empty() {
+        let hosts: Vec<String> = failures
+            .iter()
+            .map(|&(ref host, ref err)| format!("{}:{} ({})", host.host_name, host.port, err))
+            .collect();
+        message.push_str(&format!(" Failed to connect to: {}.", hosts.join(", ")));
+    }
+
+    OperationError(message)
+}
+
 impl TopologyDescription {
     /// Returns a default, unknown topology description.
     pub fn new(stream_connector: StreamConnector) -> TopologyDescription {
@@ -153,54 +213,60 @@ impl TopologyDescription {
         });
 
         // Iterate over each host until one's stream can be acquired.
+        let mut failures = Vec::new();
         for host in servers {
             if let Some(server) = self.servers.get(host) {
                 if let Ok(description) = server.description.read() {
                     if description.round_trip_time.is_none() {
                         break;
-                    } else if let Ok(stream) = server.acquire_stream(client.clone()) {
-                        return Ok((stream, description.server_type));
+                    }
+
+                    match server.acquire_stream(client.clone()) {
+                        Ok(stream) => return Ok((stream, description.server_type)),
+                        Err(err) => failures.push((host.clone(), err)),
                     }
                 }
             }
         }
-        Err(OperationError(String::from(
-            "No servers available for the provided ReadPreference.",
-        )))
+        Err(no_servers_available(failures))
     }
 
     /// Returns a random server stream from the vector.
     fn get_rand_from_vec(&self, client: Client, servers: &mut Vec<Host>) -> Result<(PooledStream, ServerType)> {
+        let mut failures = Vec::new();
         while !servers.is_empty() {
             let len = servers.len();
             let index = thread_rng().gen_range(0, len);
 
             if let Some(server) = self.servers.get(&servers[index]) {
-                if let Ok(stream) = server.acquire_stream(client.clone()) {
-                    if let Ok(description) = server.description.read() {
-                        return Ok((stream, description.server_type));
+                match server.acquire_stream(client.clone()) {
+                    Ok(stream) => {
+                        if let Ok(description) = server.description.read() {
+                            return Ok((stream, description.server_type));
+                        }
                     }
+                    Err(err) => failures.push((servers[index].clone(), err)),
                 }
             }
             servers.remove(index);
         }
-        Err(OperationError(String::from(
-            "No servers available for the provided ReadPreference.",
-        )))
+        Err(no_servers_available(failures))
     }
 
-    /// Returns a server stream for read operations.
-    pub fn acquire_stream(
-        &self,
-        client: Client,
-        read_preference: &ReadPreference,
-    ) -> Result<(PooledStream, bool, bool)> {
+    /// Returns the servers eligible for the read preference, before latency filtering,
+    /// and whether to take a random element.
+    fn suitable_hosts(&self, read_preference: &ReadPreference) -> Result<(Vec<Host>, bool)> {
         let (mut hosts, rand) = self.choose_hosts(read_preference)?;
 
-        // Filter hosts by tagsets
+        // Filter hosts by staleness and tagsets
         if self.topology_type != TopologyType::Sharded &&
             self.topology_type != TopologyType::Single
         {
+            if read_preference.max_staleness_seconds.is_some() {
+                self.check_max_staleness_support()?;
+            }
+
+            self.filter_stale_hosts(&mut hosts, read_preference);
             self.filter_hosts(&mut hosts, read_preference);
         }
 
@@ -211,13 +277,82 @@ impl TopologyDescription {
                 mode: ReadMode::PrimaryPreferred,
                 ..read_preference.clone()
             };
-            return self.acquire_stream(client, &read_pref);
+            return self.suitable_hosts(&read_pref);
+        }
+
+        Ok((hosts, rand))
+    }
+
+    /// Selects a server for read operations without acquiring a stream. The eligible server
+    /// with the lowest round trip time is chosen for mongos and nearest reads; otherwise,
+    /// one is picked at random from the latency window.
+    pub fn select_server(&self, read_preference: &ReadPreference) -> Result<Host> {
+        let (mut hosts, rand) = self.suitable_hosts(read_preference)?;
+
+        if !hosts.is_empty() {
+            self.filter_latency_hosts(&mut hosts);
+        }
+
+        self.pick_server(hosts, rand)
+    }
+
+    /// Selects a server for write operations without acquiring a stream. Only servers that
+    /// have completed a check are eligible, so the selected server's description is known.
+    pub fn select_write_server(&self) -> Result<Host> {
+        let (mut hosts, rand) = self.choose_write_hosts();
+        hosts.retain(|host| match self.servers.get(host) {
+            Some(server) => {
+                server.description.read().map_or(false, |description| {
+                    description.server_type != ServerType::Unknown
+                })
+            }
+            None => false,
+        });
+
+        self.pick_server(hosts, rand)
+    }
+
+    // Picks a server from the eligible hosts, either at random or by lowest round trip time.
+    fn pick_server(&self, mut hosts: Vec<Host>, rand: bool) -> Result<Host> {
+        // If no servers are available, request an update from all monitors.
+        if hosts.is_empty() {
+            for server in self.servers.values() {
+                server.request_check();
+            }
+            return Err(no_servers_available(Vec::new()));
         }
 
+        if rand {
+            let index = thread_rng().gen_range(0, hosts.len());
+            return Ok(hosts.swap_remove(index));
+        }
+
+        let round_trip_time = |host: &Host| {
+            self.servers
+                .get(host)
+                .and_then(|server| server.description.read().ok())
+                .and_then(|description| description.round_trip_time)
+                .unwrap_or(i64::MAX)
+        };
+
+        hosts
+            .into_iter()
+            .min_by_key(|host| round_trip_time(host))
+            .ok_or_else(|| no_servers_available(Vec::new()))
+    }
+
+    /// Returns a server stream for read operations.
+    pub fn acquire_stream(
+        &self,
+        client: Client,
+        read_preference: &ReadPreference,
+    ) -> Result<(PooledStream, bool, bool)> {
+        let (mut hosts, rand) = self.suitable_hosts(read_preference)?;
+
         // If no servers are available, request an update from all monitors.
         if hosts.is_empty() {
             for server in self.servers.values() {
-                server.request_update();
+                server.request_check();
             }
         }
 
@@ -278,7 +413,7 @@ impl TopologyDescription {
         // If no servers are available, request an update from all monitors.
         if hosts.is_empty() {
             for server in self.servers.values() {
-                server.request_update();
+                server.request_check();
             }
         }
 
@@ -289,6 +424,24 @@ impl TopologyDescription {
         }
     }
 
+    /// Returns whether writes to this topology can be retried, i.e. every known writable server
+    /// is a replica set primary or mongos on MongoDB 3.6 or newer.
+    pub fn supports_retryable_writes(&self) -> bool {
+        let wire_versions: Vec<_> = self.servers
+            .values()
+            .filter_map(|server| {
+                let description = server.description.read().ok()?;
+                match description.server_type {
+                    ServerType::RSPrimary | ServerType::Mongos => Some(description.max_wire_version),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        !wire_versions.is_empty() &&
+            wire_versions.iter().all(|&version| version >= RETRYABLE_WRITES_MIN_WIRE_VERSION)
+    }
+
     /// Filters a given set of hosts based on the provided read preference tag sets.
     pub fn filter_hosts(&self, hosts: &mut Vec<Host>, read_preference: &ReadPreference) {
         let mut tag_filter = None;
@@ -381,6 +534,159 @@ impl TopologyDescription {
         }
     }
 
+    /// Filter out secondaries whose estimated replication lag exceeds the max staleness of
+    /// the read preference, as described by the max staleness specification.
+    pub fn filter_stale_hosts(&self, hosts: &mut Vec<Host>, read_preference: &ReadPreference) {
+        let max_staleness_ms = match read_preference.max_staleness_seconds {
+            Some(seconds) => seconds * 1000,
+            None => return,
+        };
+
+        let staleness = self.staleness_estimates();
+        hosts.retain(|host| {
+            staleness.get(host).map_or(true, |&staleness_ms| staleness_ms <= max_staleness_ms)
+        });
+    }
+
+    // Returns an error if a known server is too old to report the last write date that
+    // staleness is estimated from.
+    fn check_max_staleness_support(&self) -> Result<()> {
+        for (host, server) in &self.servers {
+            let description = server.description.read()?;
+            if description.server_type != ServerType::Unknown &&
+                description.max_wire_version < MAX_STALENESS_MIN_WIRE_VERSION
+            {
+                return Err(ArgumentError(format!(
+                    "maxStalenessSeconds requires MongoDB 3.4 or newer, but {}:{} reports max \
+                     wire version {}.",
+                    host.host_name,
+                    host.port,
+                    description.max_wire_version
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Estimates the replication lag, in milliseconds, of each secondary that reported its
+    // most recent write.
+    fn staleness_estimates(&self) -> HashMap<Host, i64> {
+        let heartbeat_ms = i64::from(self.heartbeat_frequency_ms);
+
+        // The server type, last update time, and last write date of each server that
+        // reported its most recent write.
+        let times: HashMap<&Host, (ServerType, i64, i64)> = self.servers
+            .iter()
+            .filter_map(|(host, server)| {
+                let description = server.description.read().ok()?;
+                let update_ms = description.last_update_time?.timestamp_millis();
+                let write_ms = description.last_write_date?.timestamp_millis();
+                Some((host, (description.server_type, update_ms, write_ms)))
+            })
+            .collect();
+
+        let primary = times.values().find(|times| times.0 == ServerType::RSPrimary).cloned();
+        let max_write_ms = times
+            .values()
+            .filter(|times| times.0 == ServerType::RSSecondary)
+            .map(|times| times.2)
+            .max();
+
+        times
+            .iter()
+            .filter(|&(_, times)| times.0 == ServerType::RSSecondary)
+            .filter_map(|(&host, &(_, update_ms, write_ms))| {
+                let staleness_ms = match (primary, max_write_ms) {
+                    (Some((_, primary_update_ms, primary_write_ms)), _) => {
+                        (update_ms - write_ms) - (primary_update_ms - primary_write_ms) +
+                            heartbeat_ms
+                    }
+                    (None, Some(max_write_ms)) => max_write_ms - write_ms + heartbeat_ms,
+                    (None, None) => return None,
+                };
+                Some((host.clone(), staleness_ms))
+            })
+            .collect()
+    }
+
+    /// Describes why each known server is unsuitable for the read preference, or for writes
+    /// if no read preference is given.
+    pub fn selection_error(
+        &self,
+        read_preference: Option<&ReadPreference>,
+        last_error: Option<&Error>,
+    ) -> ServerSelectionError {
+        let chosen = match read_preference {
+            Some(read_pref) => {
+                self.choose_hosts(read_pref).map(|(hosts, _)| hosts).unwrap_or_default()
+            }
+            None => self.choose_write_hosts().0,
+        };
+
+        // Staleness and tag sets only apply to replica set members.
+        let filter = read_preference.filter(|_| {
+            self.topology_type != TopologyType::Sharded &&
+                self.topology_type != TopologyType::Single
+        });
+
+        let mut fresh = chosen.clone();
+        if let Some(read_pref) = filter {
+            self.filter_stale_hosts(&mut fresh, read_pref);
+        }
+
+        let mut matching = fresh.clone();
+        if let Some(read_pref) = filter {
+            self.filter_hosts(&mut matching, read_pref);
+        }
+
+        let staleness = self.staleness_estimates();
+        let max_staleness_ms = read_preference
+            .and_then(|read_pref| read_pref.max_staleness_seconds)
+            .map_or(i64::MAX, |seconds| seconds * 1000);
+
+        let mut candidates: Vec<(Host, RejectionReason)> = self.servers
+            .iter()
+            .map(|(host, server)| {
+                let description = server.description.read().ok();
+                let server_type = description.as_ref().map_or(
+                    ServerType::Unknown,
+                    |description| description.server_type,
+                );
+
+                let reason = if server_type == ServerType::Unknown {
+                    let err = description.as_ref().and_then(|description| {
+                        description.err.as_ref().as_ref().map(|err| err.to_string())
+                    });
+                    RejectionReason::Unknown(err)
+                } else if !chosen.contains(host) {
+                    RejectionReason::WrongType(server_type)
+                } else if !fresh.contains(host) {
+                    RejectionReason::Stale {
+                        staleness_ms: staleness.get(host).cloned().unwrap_or(0),
+                        max_staleness_ms: max_staleness_ms,
+                    }
+                } else if !matching.contains(host) {
+                    RejectionReason::TagMismatch
+                } else {
+                    RejectionReason::Unavailable
+                };
+
+                (host.clone(), reason)
+            })
+            .collect();
+
+        candidates.sort_by(|a, b| (&a.0.host_name, a.0.port).cmp(&(&b.0.host_name, b.0.port)));
+
+        ServerSelectionError::new(
+            read_preference.cloned(),
+            self.topology_type,
+            self.server_selection_timeout_ms,
+            candidates,
+            last_error.map(|err| err.to_string()),
+        )
+    }
+
     /// Filter out provided hosts by creating a latency window around
     /// the server with the lowest round-trip time.
     pub fn filter_latency_hosts(&self, hosts: &mut Vec<Host>) {
@@ -551,6 +857,11 @@ impl TopologyDescription {
         self.update_private(host, description, client, top_arc, false);
     }
 
+    // Wakes the threads waiting in server selection for a topology update.
+    fn notify_updated(&self) {
+        self.update_signal.notify();
+    }
+
     /// Updates the topology description based on an updated server description.
     pub fn update(
         &mut self,
@@ -653,6 +964,21 @@ impl TopologyDescription {
         self.topology_type = TopologyType::ReplicaSetNoPrimary;
     }
 
+    // Asks the monitor of the primary named by a replica set member to check it immediately,
+    // rather than waiting for its next heartbeat, unless it is already known to be the primary.
+    fn check_reported_primary(&self, description: Arc<RwLock<ServerDescription>>) {
+        let primary = match description.read().unwrap().primary.clone() {
+            Some(primary) => primary,
+            None => return,
+        };
+
+        if let Some(server) = self.servers.get(&primary) {
+            if server.description.read().unwrap().server_type != ServerType::RSPrimary {
+                server.request_update();
+            }
+        }
+    }
+
 
     // Updates an unknown topology with a new standalone server description.
     fn update_unknown_with_standalone(&mut self, host: Host) {
@@ -797,8 +1123,11 @@ impl TopologyDescription {
             if host != me {
                 self.servers.remove(&host);
                 self.check_if_has_primary();
+                return;
             }
         }
+
+        self.check_reported_primary(description);
     }
 
     // Updates a replica set topology with an updated member description.
@@ -879,11 +1208,9 @@ impl Topology {
             )));
         }
 
-        if let Some(ref config_opts) = config.options {
-            if let Some(name) = config_opts.options.get("replicaSet") {
-                options.set_name = name.to_owned();
-                options.topology_type = TopologyType::ReplicaSetNoPrimary;
-            }
+        if let Some(ref name) = config.replica_set {
+            options.set_name = name.to_owned();
+            options.topology_type = TopologyType::ReplicaSetNoPrimary;
         }
 
         if !options.set_name.is_empty() &&
@@ -902,48 +1229,85 @@ impl Topology {
         })
     }
 
-    // Private server stream acquisition helper.
-    fn acquire_stream_private(
+    // Retries a server selection attempt against the current topology description until it
+    // succeeds or the server selection timeout elapses, in which case the error describes why
+    // each known server was rejected for the read preference, or for writes if none is given.
+    // Errors that waiting cannot fix, such as an unsupported read preference, are returned
+    // immediately.
+    fn retry_selection<T, F>(
         &self,
-        client: Client,
-        read_preference: Option<ReadPreference>,
-        write: bool,
-    ) -> Result<(PooledStream, bool, bool)> {
+        read_preference: Option<&ReadPreference>,
+        attempt: F,
+    ) -> Result<T>
+    where
+        F: Fn(&TopologyDescription) -> Result<T>,
+    {
         // Note start of server selection.
-        let time = time::get_time();
-        let start_ms = time.sec * 1000 + (time.nsec as i64) / 1000000;
+        let start = Instant::now();
+        let signal = self.description.read()?.update_signal.clone();
 
         loop {
-            let result = if write {
-                match self.description.read()?.acquire_write_stream(client.clone()) {
-                    Ok(stream) => Ok((stream, false, false)),
-                    Err(err) => Err(err),
-                }
-            } else {
-                self.description.read()?.acquire_stream(
-                    client.clone(),
-                    read_preference.as_ref().unwrap(),
-                )
-            };
+            let seen = signal.generation();
+            let result = attempt(&*self.description.read()?);
 
             match result {
                 Ok(stream) => return Ok(stream),
+                Err(err @ ArgumentError(_)) |
+                Err(err @ Error::PoisonLockError) => return Err(err),
                 Err(err) => {
                     // Check duration of current server selection and return an error if
                     // overdue.
-                    let end_time = time::get_time();
-                    let end_ms = end_time.sec * 1000 + (end_time.nsec as i64) / 1000000;
-                    if end_ms - start_ms >= self.description.read()?.server_selection_timeout_ms {
-                        return Err(err);
+                    let description = self.description.read()?;
+                    let timeout = Duration::from_millis(
+                        description.server_selection_timeout_ms.max(0) as u64,
+                    );
+                    let elapsed = start.elapsed();
+                    if elapsed >= timeout {
+                        return Err(Error::ServerSelectionError(
+                            description.selection_error(read_preference, Some(&err)),
+                        ));
                     }
+
+                    // Otherwise, wait for a monitor to update the topology before trying again.
+                    let max_wait = Duration::from_millis(MAX_SELECTION_WAIT_MS as u64);
+                    let wait = (timeout - elapsed).min(max_wait);
+                    drop(description);
+                    signal.wait_for_update(seen, wait);
                 }
             };
-
-            // Otherwise, sleep for a little while.
-            thread::sleep(Duration::from_millis(500));
         }
     }
 
+    // Private server stream acquisition helper.
+    fn acquire_stream_private(
+        &self,
+        client: Client,
+        read_preference: Option<ReadPreference>,
+        write: bool,
+    ) -> Result<(PooledStream, bool, bool)> {
+        self.retry_selection(read_preference.as_ref(), |description| if write {
+            let stream = description.acquire_write_stream(client.clone())?;
+            Ok((stream, false, false))
+        } else {
+            description.acquire_stream(client.clone(), read_preference.as_ref().unwrap())
+        })
+    }
+
+    /// Selects a server matching the read preference, waiting up to the server selection
+    /// timeout for one to become available.
+    pub fn select_server(&self, read_preference: &ReadPreference) -> Result<Host> {
+        self.retry_selection(
+            Some(read_preference),
+            |description| description.select_server(read_preference),
+        )
+    }
+
+    /// Selects a server for write operations, waiting up to the server selection timeout for
+    /// one to become available.
+    pub fn select_write_server(&self) -> Result<Host> {
+        self.retry_selection(None, |description| description.select_write_server())
+    }
+
     /// Returns a server stream for read operations.
     pub fn acquire_stream(
         &self,
@@ -958,4 +1322,50 @@ impl Topology {
         let (stream, _, _) = self.acquire_stream_private(client, None, true)?;
         Ok(stream)
     }
+
+    /// Returns whether writes to this topology can be retried.
+    pub fn supports_retryable_writes(&self) -> bool {
+        self.description
+            .read()
+            .map(|description| description.supports_retryable_writes())
+            .unwrap_or(false)
+    }
+
+    /// Requests an immediate check of every server, and waits until a monitor updates the
+    /// topology or the longest selection wait elapses.
+    pub fn request_update(&self) {
+        let (signal, seen) = match self.description.read() {
+            Ok(description) => {
+                let seen = description.update_signal.generation();
+                for server in description.servers.values() {
+                    server.request_update();
+                }
+                (description.update_signal.clone(), seen)
+            }
+            Err(_) => return,
+        };
+
+        signal.wait_for_update(seen, Duration::from_millis(MAX_SELECTION_WAIT_MS as u64));
+    }
+
+    /// Returns a stream to the given server, bypassing server selection.
+    pub fn acquire_stream_to_host(&self, client: Client, host: &Host) -> Result<PooledStream> {
+        match self.description.read()?.servers.get(host) {
+            Some(server) => server.acquire_stream(client),
+            None => Err(ArgumentError(format!(
+                "{}:{} is not part of the current topology.",
+                host.host_name,
+                host.port
+            ))),
+        }
+    }
+
+    /// Returns the most recent description of a server in the topology, as reported by its
+    /// last isMaster response.
+    pub fn server_description(&self, host: &Host) -> Option<ServerDescription> {
+        let description = self.description.read().ok()?;
+        let server = description.servers.get(host)?;
+        let server_description = server.description.read().ok()?;
+        Some(server_description.clone())
+    }
 }
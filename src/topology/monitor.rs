@@ -17,7 +17,8 @@ use std::fmt;
 use std::collections::BTreeMap;
 use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use time;
 
@@ -26,6 +27,12 @@ use super::{DEFAULT_HEARTBEAT_FREQUENCY_MS, TopologyDescription};
 
 const DEFAULT_MAX_BSON_OBJECT_SIZE: i64 = 16 * 1024 * 1024;
 const DEFAULT_MAX_MESSAGE_SIZE_BYTES: i64 = 48000000;
+/// The max write batch size assumed when a server does not report one.
+pub const DEFAULT_MAX_WRITE_BATCH_SIZE: i64 = 1000;
+
+// The shortest time between two checks of a server requested by server selection, so that
+// operations waiting for a suitable server cannot flood it with isMaster commands.
+const MIN_HEARTBEAT_FREQUENCY_MS: u64 = 500;
 
 /// The result of an isMaster operation.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -34,7 +41,9 @@ pub struct IsMasterResult {
     pub is_master: bool,
     pub max_bson_object_size: i64,
     pub max_message_size_bytes: i64,
+    pub max_write_batch_size: i64,
     pub local_time: Option<DateTime<Utc>>,
+    pub last_write_date: Option<DateTime<Utc>>,
     pub min_wire_version: i64,
     pub max_wire_version: i64,
 
@@ -79,6 +88,9 @@ pub struct Monitor {
     // To allow servers to request an immediate update, this
     // condvar can be notified to wake up the monitor.
     condvar: Condvar,
+    // Whether an immediate update was requested since the previous check. Other wake-ups wait
+    // until the minimum heartbeat frequency has passed since the previous check.
+    immediate: AtomicBool,
     /// While true, the monitor will check server connection health
     /// at the topology's heartbeat frequency rate.
     pub running: Arc<AtomicBool>,
@@ -95,6 +107,15 @@ impl fmt::Debug for Monitor {
     }
 }
 
+// Reads an integer field that the server may encode as either a 32-bit or 64-bit value.
+fn get_integer(doc: &bson::Document, key: &str) -> Option<i64> {
+    match doc.get(key) {
+        Some(&Bson::I32(v)) => Some(i64::from(v)),
+        Some(&Bson::I64(v)) => Some(v),
+        _ => None,
+    }
+}
+
 impl IsMasterResult {
     /// Parses an isMaster response document from the server.
     pub fn new(doc: bson::Document) -> Result<IsMasterResult> {
@@ -110,7 +131,9 @@ impl IsMasterResult {
             is_master: false,
             max_bson_object_size: DEFAULT_MAX_BSON_OBJECT_SIZE,
             max_message_size_bytes: DEFAULT_MAX_MESSAGE_SIZE_BYTES,
+            max_write_batch_size: DEFAULT_MAX_WRITE_BATCH_SIZE,
             local_time: None,
+            last_write_date: None,
             min_wire_version: -1,
             max_wire_version: -1,
             msg: String::new(),
@@ -137,14 +160,32 @@ impl IsMasterResult {
             result.local_time = Some(datetime);
         }
 
-        if let Some(&Bson::I64(v)) = doc.get("minWireVersion") {
+        if let Some(&Bson::Document(ref last_write)) = doc.get("lastWrite") {
+            if let Some(&Bson::UtcDatetime(datetime)) = last_write.get("lastWriteDate") {
+                result.last_write_date = Some(datetime);
+            }
+        }
+
+        if let Some(v) = get_integer(&doc, "minWireVersion") {
             result.min_wire_version = v;
         }
 
-        if let Some(&Bson::I64(v)) = doc.get("maxWireVersion") {
+        if let Some(v) = get_integer(&doc, "maxWireVersion") {
             result.max_wire_version = v;
         }
 
+        if let Some(v) = get_integer(&doc, "maxBsonObjectSize") {
+            result.max_bson_object_size = v;
+        }
+
+        if let Some(v) = get_integer(&doc, "maxMessageSizeBytes") {
+            result.max_message_size_bytes = v;
+        }
+
+        if let Some(v) = get_integer(&doc, "maxWriteBatchSize") {
+            result.max_write_batch_size = v;
+        }
+
         if let Some(&Bson::String(ref s)) = doc.get("msg") {
             result.msg = s.to_owned();
         }
@@ -240,16 +281,21 @@ impl Monitor {
         server_description: Arc<RwLock<ServerDescription>>,
         connector: StreamConnector,
     ) -> Monitor {
+        let personal_pool = ConnectionPool::with_size(host.clone(), connector, 1);
+        let connect_timeout = client.topology.config.connect_timeout_ms;
+        let _ = personal_pool.set_connect_timeout(connect_timeout.map(Duration::from_millis));
+
         Monitor {
             client: client,
-            host: host.clone(),
+            host: host,
             server_pool: pool,
-            personal_pool: Arc::new(ConnectionPool::with_size(host, connector, 1)),
+            personal_pool: Arc::new(personal_pool),
             top_description: top_description,
             server_description: server_description,
             heartbeat_frequency_ms: AtomicUsize::new(DEFAULT_HEARTBEAT_FREQUENCY_MS as usize),
             dummy_lock: Mutex::new(()),
             condvar: Condvar::new(),
+            immediate: AtomicBool::new(false),
             running: Arc::new(AtomicBool::new(false)),
         }
     }
@@ -283,7 +329,6 @@ impl Monitor {
             options,
             CommandType::IsMaster,
             false,
-            None,
         )?;
         let time_end = time::get_time();
 
@@ -299,6 +344,13 @@ impl Monitor {
     }
 
     pub fn request_update(&self) {
+        self.immediate.store(true, Ordering::SeqCst);
+        self.condvar.notify_one();
+    }
+
+    /// Requests a check of the server once the minimum heartbeat frequency has passed since the
+    /// previous check, unless an immediate update is requested in the meantime.
+    pub fn request_check(&self) {
         self.condvar.notify_one();
     }
 
@@ -336,6 +388,7 @@ impl Monitor {
             self.client.clone(),
             self.top_description.clone(),
         );
+        top_description.notify_updated();
     }
 
     // Updates server and topology descriptions using a successful isMaster cursor result.
@@ -395,6 +448,7 @@ impl Monitor {
                 break;
             }
 
+            let last_check = Instant::now();
             self.execute_update();
 
             if let Ok(description) = self.top_description.read() {
@@ -409,6 +463,12 @@ impl Monitor {
                 .wait_timeout(guard, Duration::from_millis(frequency))
                 .unwrap()
                 .0;
+
+            let min_frequency = Duration::from_millis(MIN_HEARTBEAT_FREQUENCY_MS);
+            let elapsed = last_check.elapsed();
+            if !self.immediate.swap(false, Ordering::SeqCst) && elapsed < min_frequency {
+                thread::sleep(min_frequency - elapsed);
+            }
         }
     }
 }
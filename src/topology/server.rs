@@ -3,8 +3,10 @@ use {Client, Result};
 use Error::{self, OperationError};
 
 use bson::oid;
+use chrono::{DateTime, Utc};
 use connstring::Host;
-use pool::{ConnectionPool, PooledStream};
+use pool::{ConnectionMetrics, ConnectionPool, DEFAULT_MAINTENANCE_INTERVAL_MS, DEFAULT_POOL_SIZE,
+           PooledStream};
 use stream::StreamConnector;
 
 use std::collections::BTreeMap;
@@ -12,6 +14,7 @@ use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::Ordering;
 use std::thread;
+use std::time::Duration;
 
 use super::monitor::{IsMasterResult, Monitor};
 use super::TopologyDescription;
@@ -56,12 +59,18 @@ pub struct ServerDescription {
     pub server_type: ServerType,
     /// Any error encountered while monitoring this server.
     pub err: Arc<Option<Error>>,
-    /// The average round-trip time over the last 5 monitoring checks.
+    /// The exponentially-weighted moving average of isMaster round-trip times, in milliseconds.
     pub round_trip_time: Option<i64>,
     /// The minimum wire version supported by this server.
     pub min_wire_version: i64,
     /// The maximum wire version supported by this server.
     pub max_wire_version: i64,
+    /// The largest BSON document the server accepts, in bytes.
+    pub max_bson_object_size: i64,
+    /// The largest wire protocol message the server accepts, in bytes.
+    pub max_message_size_bytes: i64,
+    /// The largest number of statements the server accepts in a single write command.
+    pub max_write_batch_size: i64,
     /// The server's host information, if it is part of a replica set.
     pub me: Option<Host>,
     /// All hosts in the replica set known by this server.
@@ -80,6 +89,10 @@ pub struct ServerDescription {
     pub primary: Option<Host>,
     /// The current replica set version number.
     pub set_version: Option<i64>,
+    /// The time of the most recent write on the server, as reported by isMaster.
+    pub last_write_date: Option<DateTime<Utc>>,
+    /// The time at which this description was last updated from an isMaster response.
+    pub last_update_time: Option<DateTime<Utc>>,
 }
 
 /// Holds status and connection information about a single server.
@@ -129,6 +142,9 @@ impl ServerDescription {
 
         self.min_wire_version = ismaster.min_wire_version;
         self.max_wire_version = ismaster.max_wire_version;
+        self.max_bson_object_size = ismaster.max_bson_object_size;
+        self.max_message_size_bytes = ismaster.max_message_size_bytes;
+        self.max_write_batch_size = ismaster.max_write_batch_size;
         self.me = ismaster.me;
         self.hosts = ismaster.hosts;
         self.passives = ismaster.passives;
@@ -138,6 +154,8 @@ impl ServerDescription {
         self.election_id = ismaster.election_id;
         self.primary = ismaster.primary;
         self.set_version = ismaster.set_version;
+        self.last_write_date = ismaster.last_write_date;
+        self.last_update_time = Some(Utc::now());
         self.round_trip_time = match self.round_trip_time {
             Some(old_rtt) => {
                 // (rtt / div) + (old_rtt * (div-1)/div)
@@ -207,7 +225,30 @@ impl Server {
         let host_clone = host.clone();
         let desc_clone = description.clone();
 
-        let pool = Arc::new(ConnectionPool::new(host.clone(), connector.clone()));
+        let config = &client.topology.config;
+        let pool_size = config.max_pool_size.unwrap_or(DEFAULT_POOL_SIZE);
+        let pool = Arc::new(ConnectionPool::with_size(host.clone(), connector.clone(), pool_size));
+
+        // The connection string guarantees that minPoolSize does not exceed maxPoolSize.
+        let _ = pool.set_min_size(config.min_pool_size.unwrap_or(0));
+        let _ = pool.set_max_idle_time(config.max_idle_time_ms.map(Duration::from_millis));
+        let _ = pool.set_keep_alive_interval(
+            config.keep_alive_interval_ms.map(Duration::from_millis),
+        );
+        let _ = pool.set_handshake_cache_ttl(client.handshake_cache_ttl);
+        let _ = pool.set_connect_timeout(config.connect_timeout_ms.map(Duration::from_millis));
+
+        if run_monitor &&
+            (config.min_pool_size.is_some() || config.max_idle_time_ms.is_some() ||
+                 config.keep_alive_interval_ms.is_some())
+        {
+            // Check often enough that idle connections are pinged close to their interval.
+            let interval_ms = config.keep_alive_interval_ms.map_or(
+                DEFAULT_MAINTENANCE_INTERVAL_MS,
+                |keep_alive_ms| keep_alive_ms.min(DEFAULT_MAINTENANCE_INTERVAL_MS),
+            );
+            pool.start_maintenance(&client, Duration::from_millis(interval_ms));
+        }
 
         // Fails silently
         let monitor = Arc::new(Monitor::new(
@@ -237,8 +278,19 @@ impl Server {
         self.pool.acquire_stream(client)
     }
 
+    /// Returns the latency breakdown of the most recently established connection to this server.
+    pub fn connection_metrics(&self) -> Option<ConnectionMetrics> {
+        self.pool.last_connection_metrics()
+    }
+
     /// Request an update from the monitor on the server status.
     pub fn request_update(&self) {
         self.monitor.request_update();
     }
+
+    /// Request an update from the monitor on behalf of server selection, which is rate limited
+    /// so that waiting operations do not flood the server with checks.
+    pub fn request_check(&self) {
+        self.monitor.request_check();
+    }
 }
@@ -2,6 +2,7 @@
 
 use std::fmt;
 use std::io::{Read, Write};
+use std::mem;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use Result;
@@ -15,6 +16,8 @@ pub enum OpCode {
     Insert = 2002,
     Query = 2004,
     GetMore = 2005,
+    KillCursors = 2007,
+    Compressed = 2012,
 }
 
 impl OpCode {
@@ -35,6 +38,8 @@ impl OpCode {
             2002 => Some(OpCode::Insert),
             2004 => Some(OpCode::Query),
             2005 => Some(OpCode::GetMore),
+            2007 => Some(OpCode::KillCursors),
+            2012 => Some(OpCode::Compressed),
             _ => None,
         }
     }
@@ -48,6 +53,8 @@ impl fmt::Display for OpCode {
             OpCode::Insert => fmt.write_str("OP_INSERT"),
             OpCode::Query => fmt.write_str("OP_QUERY"),
             OpCode::GetMore => fmt.write_str("OP_GET_MORE"),
+            OpCode::KillCursors => fmt.write_str("OP_KILL_CURSORS"),
+            OpCode::Compressed => fmt.write_str("OP_COMPRESSED"),
         }
     }
 }
@@ -106,6 +113,18 @@ impl Header {
         Header::new_request(message_length, request_id, OpCode::GetMore)
     }
 
+    /// Constructs a new Header for an OP_KILL_CURSORS, with `response_to` set to 0 and
+    /// `op_code` set to `KillCursors`.
+    pub fn new_kill_cursors(message_length: i32, request_id: i32) -> Header {
+        Header::new_request(message_length, request_id, OpCode::KillCursors)
+    }
+
+    /// Constructs the Header of the message wrapped in an OP_COMPRESSED message with this header.
+    pub fn decompressed(&self, uncompressed_size: i32, op_code: OpCode) -> Header {
+        let message_length = mem::size_of::<Header>() as i32 + uncompressed_size;
+        Header::new(message_length, self.request_id, self.response_to, op_code)
+    }
+
     /// Writes the serialized Header to a buffer.
     ///
     /// # Arguments
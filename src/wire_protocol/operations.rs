@@ -1,5 +1,5 @@
 //! Wire protocol operational client-server communication logic.
-use bson;
+use bson::{self, Bson};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use Error::{ArgumentError, ResponseError};
 use Result;
@@ -10,6 +10,39 @@ use std::io::{Read, Write};
 use std::mem;
 use std::result::Result::{Ok, Err};
 
+// The identifier of the compressor that leaves messages unchanged.
+const NOOP_COMPRESSOR_ID: u8 = 0;
+
+/// The default maximum nesting depth of documents sent to the server, matching the server's limit.
+pub const DEFAULT_MAX_DOCUMENT_DEPTH: usize = 100;
+
+// Returns whether the document nests deeper than `max_depth` levels. The traversal is
+// iterative so that it cannot itself overflow the stack on pathological input.
+fn exceeds_depth(doc: &bson::Document, max_depth: usize) -> bool {
+    if max_depth < 1 {
+        return true;
+    }
+
+    let mut stack: Vec<(&Bson, usize)> = doc.values().map(|value| (value, 1)).collect();
+
+    while let Some((value, depth)) = stack.pop() {
+        let children: Vec<&Bson> = match *value {
+            Bson::Document(ref doc) |
+            Bson::JavaScriptCodeWithScope(_, ref doc) => doc.values().collect(),
+            Bson::Array(ref array) => array.iter().collect(),
+            _ => continue,
+        };
+
+        if depth + 1 > max_depth {
+            return true;
+        }
+
+        stack.extend(children.into_iter().map(|child| (child, depth + 1)));
+    }
+
+    false
+}
+
 trait ByteLength {
     /// Calculates the number of bytes in the serialized version of the struct.
     fn byte_length(&self) -> Result<i32>;
@@ -103,6 +136,13 @@ pub enum Message {
         /// Uniquely identifies the cursor being returned.
         cursor_id: i64,
     },
+    OpKillCursors {
+        /// The message header.
+        header: Header,
+        // The wire protocol specifies that a 32-bit 0 field goes here
+        /// The cursors to close on the server.
+        cursor_ids: Vec<i64>,
+    },
 }
 
 impl Message {
@@ -259,6 +299,49 @@ impl Message {
         }
     }
 
+    /// Constructs a new "kill cursors" request message.
+    pub fn new_kill_cursors(request_id: i32, cursor_ids: Vec<i64>) -> Message {
+        let header_length = mem::size_of::<Header>() as i32;
+
+        // There are two i32 fields: the reserved "ZERO" and the number of cursor ids.
+        let i32_length = 2 * mem::size_of::<i32>() as i32;
+
+        let i64_length = mem::size_of::<i64>() as i32 * cursor_ids.len() as i32;
+        let total_length = header_length + i32_length + i64_length;
+
+        let header = Header::new_kill_cursors(total_length, request_id);
+
+        Message::OpKillCursors {
+            header: header,
+            cursor_ids: cursor_ids,
+        }
+    }
+
+    /// Returns an error if any document in the message nests deeper than `max_depth` levels.
+    ///
+    /// The top-level document counts as one level, as does each nested document or array.
+    pub fn check_document_depth(&self, max_depth: usize) -> Result<()> {
+        let documents: Vec<&bson::Document> = match *self {
+            Message::OpUpdate { ref selector, ref update, .. } => vec![selector, update],
+            Message::OpInsert { ref documents, .. } => documents.iter().collect(),
+            Message::OpQuery { ref query, ref return_field_selector, .. } => {
+                let mut documents = vec![query];
+                documents.extend(return_field_selector.iter());
+                documents
+            }
+            _ => Vec::new(),
+        };
+
+        if documents.into_iter().any(|doc| exceeds_depth(doc, max_depth)) {
+            return Err(ArgumentError(format!(
+                "Document exceeds the maximum nesting depth of {}.",
+                max_depth
+            )));
+        }
+
+        Ok(())
+    }
+
     /// Writes a serialized BSON document to a given buffer.
     ///
     /// # Arguments
@@ -270,11 +353,7 @@ impl Message {
     ///
     /// Returns nothing on success, or an Error on failure.
     fn write_bson_document<W: Write>(buffer: &mut W, bson: &bson::Document) -> Result<()> {
-        let mut temp_buffer = Vec::new();
-
-        bson::encode_document(&mut temp_buffer, bson)?;
-        buffer.write_all(&temp_buffer)?;
-
+        bson::encode_document(buffer, bson)?;
         Ok(())
     }
 
@@ -459,6 +538,37 @@ impl Message {
         Ok(())
     }
 
+    /// Writes a serialized "kill cursors" request to a given buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buffer` - The buffer to write to.
+    /// `header` - The header for the given message.
+    /// `cursor_ids` - The cursors to close on the server.
+    ///
+    /// # Return value
+    ///
+    /// Returns nothing on success, or an Error on failure.
+    pub fn write_kill_cursors<W: Write>(
+        buffer: &mut W,
+        header: &Header,
+        cursor_ids: &[i64],
+    ) -> Result<()> {
+
+        header.write(buffer)?;
+
+        // Write ZERO field
+        buffer.write_i32::<LittleEndian>(0)?;
+
+        buffer.write_i32::<LittleEndian>(cursor_ids.len() as i32)?;
+        for cursor_id in cursor_ids {
+            buffer.write_i64::<LittleEndian>(*cursor_id)?;
+        }
+
+        let _ = buffer.flush();
+        Ok(())
+    }
+
     /// Attemps to write the serialized message to a buffer.
     ///
     /// # Arguments
@@ -515,9 +625,36 @@ impl Message {
                 number_to_return,
                 cursor_id,
             } => Message::write_get_more(buffer, header, namespace, number_to_return, cursor_id),
+            Message::OpKillCursors {
+                ref header,
+                ref cursor_ids,
+            } => Message::write_kill_cursors(buffer, header, cursor_ids),
         }
     }
 
+    /// Serializes the message into a reusable scratch buffer, then writes it to a buffer in a
+    /// single call.
+    ///
+    /// The scratch buffer is cleared before use, so its allocation can be shared across
+    /// messages written to the same connection.
+    ///
+    /// # Arguments
+    ///
+    /// `buffer` - The buffer to write to.
+    /// `scratch` - The buffer to serialize the message into.
+    ///
+    /// # Return value
+    ///
+    /// Returns nothing on success, or an Error on failure.
+    pub fn write_with_buffer<W: Write>(&self, buffer: &mut W, scratch: &mut Vec<u8>) -> Result<()> {
+        scratch.clear();
+        self.write(scratch)?;
+
+        buffer.write_all(scratch)?;
+        buffer.flush()?;
+        Ok(())
+    }
+
     /// Reads a serialized reply message from a buffer
     ///
     /// # Arguments
@@ -557,8 +694,68 @@ impl Message {
         Ok(Message::new_reply(header, flags, cid, sf, nr, v))
     }
 
+    /// Reads the reply wrapped in an OP_COMPRESSED message from a buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buffer` - The buffer to read from.
+    /// `header` - The header of the OP_COMPRESSED message, which has already been read.
+    ///
+    /// # Return value
+    ///
+    /// Returns the decompressed reply message on success, or an Error on failure.
+    fn read_compressed<R: Read>(buffer: &mut R, header: Header) -> Result<Message> {
+        let original_op_code = buffer.read_i32::<LittleEndian>()?;
+        let uncompressed_size = buffer.read_i32::<LittleEndian>()?;
+        let compressor_id = buffer.read_u8()?;
+
+        let compressed_length = header.message_length - mem::size_of::<Header>() as i32 -
+            2 * mem::size_of::<i32>() as i32 - mem::size_of::<u8>() as i32;
+        if compressed_length < 0 {
+            return Err(ResponseError(format!(
+                "Invalid OP_COMPRESSED message length {}.",
+                header.message_length
+            )));
+        }
+
+        let mut compressed = vec![0; compressed_length as usize];
+        buffer.read_exact(&mut compressed)?;
+
+        // The handshake advertises no compressors, so only the noop compressor is expected.
+        let uncompressed = match compressor_id {
+            NOOP_COMPRESSOR_ID => compressed,
+            id => {
+                return Err(ResponseError(
+                    format!("Unsupported compressor id {} in OP_COMPRESSED reply.", id),
+                ))
+            }
+        };
+
+        if uncompressed.len() as i32 != uncompressed_size {
+            return Err(ResponseError(format!(
+                "OP_COMPRESSED reply decompressed to {} bytes, but {} were expected.",
+                uncompressed.len(),
+                uncompressed_size
+            )));
+        }
+
+        match OpCode::from_i32(original_op_code) {
+            Some(OpCode::Reply) => {
+                let header = header.decompressed(uncompressed_size, OpCode::Reply);
+                Message::read_reply(&mut &uncompressed[..], header)
+            }
+            _ => Err(ResponseError(format!(
+                "Expected OP_COMPRESSED to wrap OP_REPLY but instead found opcode {}.",
+                original_op_code
+            ))),
+        }
+    }
+
     /// Attempts to read a serialized reply Message from a buffer.
     ///
+    /// The opcode of each message is inspected as it is read, so plain and compressed replies
+    /// may be interleaved on the same connection.
+    ///
     /// # Arguments
     ///
     /// `buffer` - The buffer to read from.
@@ -573,6 +770,7 @@ impl Message {
         let header = Header::read(buffer)?;
         match header.op_code {
             OpCode::Reply => Message::read_reply(buffer, header),
+            OpCode::Compressed => Message::read_compressed(buffer, header),
             opcode => {
                 Err(ResponseError(format!(
                     "Expected to read OpCode::Reply but instead found \
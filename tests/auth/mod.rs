@@ -3,6 +3,9 @@ use mongodb::{CommandType, Client, ThreadedClient};
 use mongodb::db::ThreadedDatabase;
 use mongodb::error::Error::OperationError;
 
+use client::mock::MockServer;
+use std::time::Duration;
+
 fn doc_vec_find(vec: &[Bson], key: &str, val: &str) -> Option<Bson> {
     vec.iter()
         .cloned()
@@ -180,3 +183,23 @@ fn successful_login() {
         _ => panic!("Invalid `db` field of auth'd user"),
     };
 }
+
+#[test]
+fn x509_auth_runs_against_external() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let db = client.db("test-auth-mod-x509");
+    db.auth_x509(Some("CN=client,OU=drivers,O=MongoDB")).unwrap();
+
+    let requests = server.received();
+    assert_eq!(1, requests.len());
+    assert_eq!("$external.$cmd", requests[0].namespace);
+
+    let command = requests[0].query.as_ref().unwrap();
+    assert_eq!(Some(&Bson::I32(1)), command.get("authenticate"));
+    assert_eq!(Ok("MONGODB-X509"), command.get_str("mechanism"));
+    assert_eq!(Ok("CN=client,OU=drivers,O=MongoDB"), command.get_str("user"));
+}
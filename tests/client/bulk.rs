@@ -1,8 +1,13 @@
 use bson::Bson;
+use mongodb::coll::error::BulkWriteException;
 use mongodb::coll::options::WriteModel;
-use mongodb::{Client, ThreadedClient};
+use mongodb::common::WriteConcern;
+use mongodb::{Client, Error, ThreadedClient};
 use mongodb::db::ThreadedDatabase;
 
+use client::mock::MockServer;
+use std::time::Duration;
+
 #[test]
 fn bulk_ordered_insert_only() {
     let client = Client::connect("localhost", 27017).unwrap();
@@ -115,16 +120,22 @@ fn bulk_ordered_mix() {
             filter: doc! { "_id": 3 },
             replacement: doc! { "x": 37 },
             upsert: Some(true),
+            collation: None,
+            hint: None,
         },
         WriteModel::UpdateMany {
             filter: doc! { "_id": { "$lt": 3 } },
             update: doc! { "$inc": { "x": 1 } },
             upsert: Some(false),
+            collation: None,
+            hint: None,
         },
         WriteModel::DeleteOne {
             filter: doc! {
             "_id": 4
         },
+            collation: None,
+            hint: None,
         },
         WriteModel::InsertOne {
             document: doc! {
@@ -136,6 +147,8 @@ fn bulk_ordered_mix() {
             filter: doc! { "_id": 6 },
             update: doc! { "$set":  { "x": 62 } },
             upsert: Some(true),
+            collation: None,
+            hint: None,
         },
         WriteModel::InsertOne {
             document: doc! {
@@ -165,6 +178,8 @@ fn bulk_ordered_mix() {
             filter: doc! {
             "_id": { "$gte": 103 }
         },
+            collation: None,
+            hint: None,
         },
     ];
 
@@ -204,3 +219,183 @@ fn bulk_ordered_mix() {
     check_value_in_tree!(result.inserted_ids, 12, 104);
     check_value_in_tree!(result.upserted_ids, 8, 6);
 }
+
+#[test]
+fn bulk_write_respects_max_write_batch_size() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("insert") => {
+            let n = query.get_array("documents").unwrap().len() as i32;
+            (0, doc! { "ok": 1, "n": n })
+        }
+        Some(ref query) if query.contains_key("update") => {
+            let n = query.get_array("updates").unwrap().len() as i32;
+            (0, doc! { "ok": 1, "n": n, "nModified": n })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true, "maxWriteBatchSize": 2 }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-bulk").collection("max_write_batch_size");
+
+    let mut models: Vec<_> = (0..5)
+        .map(|i| WriteModel::InsertOne { document: doc! { "_id": i } })
+        .collect();
+    models.extend((0..3).map(|i| {
+        WriteModel::UpdateOne {
+            filter: doc! { "_id": i },
+            update: doc! { "$set": { "x": i } },
+            upsert: None,
+            collation: None,
+            hint: None,
+        }
+    }));
+
+    let result = coll.bulk_write(models, true);
+    assert!(result.bulk_write_exception.is_none());
+    assert_eq!(5, result.inserted_count);
+    assert_eq!(3, result.matched_count);
+    assert_eq!(3, result.modified_count);
+
+    let sizes: Vec<_> = server
+        .received()
+        .into_iter()
+        .map(|req| {
+            let query = req.query.unwrap();
+            match query.get_array("documents") {
+                Ok(documents) => ("insert", documents.len()),
+                Err(_) => ("update", query.get_array("updates").unwrap().len()),
+            }
+        })
+        .collect();
+    assert_eq!(
+        vec![("insert", 2), ("insert", 2), ("insert", 1), ("update", 2), ("update", 1)],
+        sizes
+    );
+}
+
+#[test]
+fn parse_write_errors() {
+    let reply = doc! {
+        "ok": 1,
+        "n": 1,
+        "writeErrors": [
+            { "index": 0, "code": 11000, "codeName": "DuplicateKey", "errmsg": "E11000 dup" },
+            { "index": 2, "code": 121, "errmsg": "Document failed validation" },
+        ],
+    };
+
+    let errors = match BulkWriteException::validate_bulk_write_result(reply, WriteConcern::new()) {
+        Err(Error::BulkWriteError(exception)) => exception.write_errors,
+        other => panic!("expected a bulk write error, got {:?}", other),
+    };
+
+    assert_eq!(2, errors.len());
+    assert_eq!(0, errors[0].index);
+    assert_eq!(11000, errors[0].code);
+    assert_eq!(Some(String::from("DuplicateKey")), errors[0].code_name);
+    assert_eq!("E11000 dup", errors[0].message);
+    assert_eq!(2, errors[1].index);
+    assert_eq!(121, errors[1].code);
+    assert_eq!(None, errors[1].code_name);
+}
+
+#[test]
+fn write_error_indexes_are_rebased_across_batches() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("insert") => {
+            let documents = query.get_array("documents").unwrap();
+            match documents.iter().position(|doc| doc == &Bson::Document(doc! { "_id": 3 })) {
+                Some(index) => (0, doc! {
+                    "ok": 1,
+                    "n": documents.len() as i32 - 1,
+                    "writeErrors": [{
+                        "index": index as i32,
+                        "code": 11000,
+                        "codeName": "DuplicateKey",
+                        "errmsg": "E11000 duplicate key error",
+                    }],
+                }),
+                None => (0, doc! { "ok": 1, "n": documents.len() as i32 }),
+            }
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true, "maxWriteBatchSize": 2 }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-bulk").collection("rebased_write_errors");
+
+    let models = (0..5)
+        .map(|i| WriteModel::InsertOne { document: doc! { "_id": i } })
+        .collect();
+
+    let result = coll.bulk_write(models, false);
+    assert_eq!(4, result.inserted_count);
+    assert!(!result.inserted_ids.contains_key(&3));
+
+    let exception = result.bulk_write_exception.unwrap();
+    assert_eq!(1, exception.write_errors.len());
+
+    let error = &exception.write_errors[0];
+    assert_eq!(3, error.index);
+    assert_eq!(11000, error.code);
+    assert_eq!(Some(String::from("DuplicateKey")), error.code_name);
+    assert_eq!(
+        Some(WriteModel::InsertOne { document: doc! { "_id": 3 } }),
+        error.request
+    );
+}
+
+#[test]
+fn unordered_mixed_writes_keep_original_indexes() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("insert") => {
+            let documents = query.get_array("documents").unwrap();
+            match documents.iter().position(|doc| doc == &Bson::Document(doc! { "_id": 1 })) {
+                Some(index) => (0, doc! {
+                    "ok": 1,
+                    "n": documents.len() as i32 - 1,
+                    "writeErrors": [{
+                        "index": index as i32,
+                        "code": 11000,
+                        "errmsg": "E11000 duplicate key error",
+                    }],
+                }),
+                None => (0, doc! { "ok": 1, "n": documents.len() as i32 }),
+            }
+        }
+        Some(ref query) if query.contains_key("update") => {
+            (0, doc! { "ok": 1, "n": 1, "nModified": 0, "upserted": [{ "index": 0, "_id": 7 }] })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-bulk").collection("unordered_indexes");
+
+    let models = vec![
+        WriteModel::InsertOne { document: doc! { "_id": 0 } },
+        WriteModel::UpdateOne {
+            filter: doc! { "_id": 7 },
+            update: doc! { "$set": { "x": 1 } },
+            upsert: Some(true),
+            collation: None,
+            hint: None,
+        },
+        WriteModel::InsertOne { document: doc! { "_id": 1 } },
+    ];
+
+    let result = coll.bulk_write(models, false);
+    assert_eq!(1, result.inserted_count);
+    assert!(result.inserted_ids.contains_key(&0));
+    assert_eq!(Some(&Bson::I32(7)), result.upserted_ids.get(&1));
+
+    let exception = result.bulk_write_exception.unwrap();
+    assert_eq!(1, exception.write_errors.len());
+
+    let error = &exception.write_errors[0];
+    assert_eq!(2, error.index);
+    assert_eq!(
+        Some(WriteModel::InsertOne { document: doc! { "_id": 1 } }),
+        error.request
+    );
+}
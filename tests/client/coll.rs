@@ -1,9 +1,22 @@
-use bson::Bson;
+use bson::{Bson, Document};
 
-use mongodb::{Client, ThreadedClient};
+use mongodb::{Client, ClientOptions, Error, ThreadedClient};
+use mongodb::common::{ReadConcern, ReadConcernLevel};
 use mongodb::db::ThreadedDatabase;
-use mongodb::coll::options::{FindOptions, FindOneAndUpdateOptions, IndexModel, IndexOptions,
-                             ReturnDocument};
+use mongodb::coll::{build_find_command, Collection};
+use mongodb::coll::options::{AggregateOptions, CountOptions, CursorType, DeleteOptions,
+                             DistinctOptions, EstimatedDocumentCountOptions, FindOptions,
+                             FindOneAndUpdateOptions, IndexModel, IndexOptions, ReturnDocument,
+                             UpdateOptions, WriteModel};
+use mongodb::connstring;
+use mongodb::topology::server::ServerType;
+
+use client::mock::{MockRequest, MockServer, OP_INSERT};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[test]
 fn find_sorted() {
@@ -910,3 +923,884 @@ fn drop_all_indexes() {
 
     assert_eq!(1, results.len());
 }
+
+// Starts a mock primary that answers `cmd` commands with `reply`, and returns it along with a
+// collection named `name` connected to it.
+fn mock_command_server(cmd: &'static str, reply: Document, name: &str) -> (MockServer, Collection) {
+    let server = MockServer::start(Duration::from_millis(0), move |req| match req.query {
+        Some(ref query) if query.contains_key(cmd) => (0, reply.clone()),
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-coll").collection(name);
+    (server, coll)
+}
+
+// Returns the commands a mock server received, excluding handshakes and heartbeats.
+fn received_commands(server: &MockServer) -> Vec<Document> {
+    server
+        .received()
+        .into_iter()
+        .filter_map(|req| req.query)
+        .collect()
+}
+
+// Returns a mock server that acknowledges every write, along with a collection connected to it.
+fn mock_write_server() -> (MockServer, Collection) {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("isMaster") => {
+            (0, doc! { "ok": 1, "ismaster": true, "maxWireVersion": 9 })
+        }
+        _ => (0, doc! { "ok": 1, "n": 1, "nModified": 1 }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-coll").collection("mock_writes");
+    (server, coll)
+}
+
+#[test]
+fn update_statement_collation_and_hint() {
+    let (server, coll) = mock_write_server();
+
+    let mut options = UpdateOptions::new();
+    options.collation = Some(doc! { "locale": "fr", "strength": 1 });
+    options.hint = Some(String::from("title_1"));
+    coll.update_one(doc! { "title": "Jaws" }, doc! { "$set": { "seen": true } }, Some(options))
+        .unwrap();
+
+    let mut options = UpdateOptions::new();
+    options.hint_doc = Some(doc! { "year": 1 });
+    coll.update_many(doc! {}, doc! { "$set": { "seen": false } }, Some(options))
+        .unwrap();
+
+    let requests = server.received();
+    assert_eq!(2, requests.len());
+
+    for request in &requests {
+        let command = request.query.as_ref().unwrap();
+        assert!(!command.contains_key("collation"));
+        assert!(!command.contains_key("hint"));
+    }
+
+    let statement = update_statement(&requests[0]);
+    assert_eq!(
+        Some(&Bson::Document(doc! { "locale": "fr", "strength": 1 })),
+        statement.get("collation")
+    );
+    assert_eq!(
+        Some(&Bson::String(String::from("title_1"))),
+        statement.get("hint")
+    );
+
+    let statement = update_statement(&requests[1]);
+    assert_eq!(None, statement.get("collation"));
+    assert_eq!(Some(&Bson::Document(doc! { "year": 1 })), statement.get("hint"));
+}
+
+fn update_statement(request: &MockRequest) -> Document {
+    let command = request.query.as_ref().unwrap();
+    match command.get_array("updates").unwrap()[0] {
+        Bson::Document(ref statement) => statement.clone(),
+        _ => panic!("update statement was not a document"),
+    }
+}
+
+#[test]
+fn delete_statement_hint() {
+    let (server, coll) = mock_write_server();
+
+    let mut options = DeleteOptions::new();
+    options.hint = Some(String::from("title_1"));
+    coll.delete_one_with_options(doc! { "title": "Jaws" }, Some(options))
+        .unwrap();
+
+    let mut options = DeleteOptions::new();
+    options.hint_doc = Some(doc! { "year": 1 });
+    coll.delete_many_with_options(doc! {}, Some(options)).unwrap();
+
+    let requests = server.received();
+    assert_eq!(2, requests.len());
+
+    let statement = delete_statement(&requests[0]);
+    assert_eq!(
+        Some(&Bson::String(String::from("title_1"))),
+        statement.get("hint")
+    );
+    assert_eq!(Some(&Bson::I64(1)), statement.get("limit"));
+
+    let statement = delete_statement(&requests[1]);
+    assert_eq!(Some(&Bson::Document(doc! { "year": 1 })), statement.get("hint"));
+    assert_eq!(Some(&Bson::I64(0)), statement.get("limit"));
+}
+
+#[test]
+fn delete_statement_hint_requires_wire_version_9() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("isMaster") => {
+            (0, doc! { "ok": 1, "ismaster": true, "maxWireVersion": 8 })
+        }
+        _ => (0, doc! { "ok": 1, "n": 1 }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-coll").collection("mock_writes");
+
+    let mut options = DeleteOptions::new();
+    options.hint = Some(String::from("title_1"));
+    match coll.delete_one_with_options(doc! { "title": "Jaws" }, Some(options)) {
+        Err(Error::ArgumentError(_)) => (),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    assert!(server.received().is_empty());
+
+    // Deletes without a hint are unaffected.
+    coll.delete_one(doc! { "title": "Jaws" }, None).unwrap();
+    assert_eq!(1, server.received().len());
+}
+
+fn delete_statement(request: &MockRequest) -> Document {
+    let command = request.query.as_ref().unwrap();
+    match command.get_array("deletes").unwrap()[0] {
+        Bson::Document(ref statement) => statement.clone(),
+        _ => panic!("delete statement was not a document"),
+    }
+}
+
+#[test]
+fn bulk_write_statement_collation_and_hint() {
+    let (server, coll) = mock_write_server();
+
+    let models = vec![
+        WriteModel::UpdateOne {
+            filter: doc! { "title": "Jaws" },
+            update: doc! { "$set": { "seen": true } },
+            upsert: None,
+            collation: Some(doc! { "locale": "fr" }),
+            hint: Some(Bson::String(String::from("title_1"))),
+        },
+        WriteModel::DeleteMany {
+            filter: doc! { "year": { "$lt": 1970 } },
+            collation: Some(doc! { "locale": "en" }),
+            hint: Some(Bson::Document(doc! { "year": 1 })),
+        },
+    ];
+
+    let result = coll.bulk_write(models, true);
+    assert!(result.bulk_write_exception.is_none());
+
+    let requests = server.received();
+    assert_eq!(2, requests.len());
+
+    let statement = update_statement(&requests[0]);
+    assert_eq!(Some(&Bson::Document(doc! { "locale": "fr" })), statement.get("collation"));
+    assert_eq!(Some(&Bson::String(String::from("title_1"))), statement.get("hint"));
+
+    let statement = delete_statement(&requests[1]);
+    assert_eq!(Some(&Bson::Document(doc! { "locale": "en" })), statement.get("collation"));
+    assert_eq!(Some(&Bson::Document(doc! { "year": 1 })), statement.get("hint"));
+}
+
+#[test]
+fn insert_one_unacknowledged_does_not_wait_for_reply() {
+    let (server, coll) = mock_write_server();
+
+    // The mock never answers OP_INSERT, so reading a reply would block indefinitely.
+    let start = Instant::now();
+    coll.insert_one_unacknowledged(doc! { "level": "info", "msg": "started" })
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let mut inserts = Vec::new();
+    for _ in 0..100 {
+        inserts = server
+            .received()
+            .into_iter()
+            .filter(|req| req.op_code == OP_INSERT)
+            .collect::<Vec<_>>();
+        if !inserts.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(1, inserts.len());
+    assert_eq!("test-client-coll.mock_writes", inserts[0].namespace);
+    assert_eq!(1, inserts[0].documents.len());
+    assert_eq!(Some(&Bson::String(String::from("started"))), inserts[0].documents[0].get("msg"));
+    assert!(inserts[0].documents[0].contains_key("_id"));
+
+    // The stream is still in sync for acknowledged operations.
+    coll.insert_one(doc! { "level": "info", "msg": "acknowledged" }, None)
+        .unwrap();
+}
+
+#[test]
+fn index_hint_injected_for_mapped_equality_filter() {
+    let (server, mut coll) = mock_write_server();
+
+    let mut hints = BTreeMap::new();
+    hints.insert(String::from("title"), String::from("title_1"));
+    coll.set_index_hints(hints);
+
+    coll.find_one(Some(doc! { "title": "Jaws" }), None).unwrap();
+    coll.find_one(Some(doc! { "year": 1975 }), None).unwrap();
+    coll.find_one(Some(doc! { "title": { "$gt": "J" } }), None).unwrap();
+    coll.find_one(Some(doc! { "title": "Jaws", "year": 1975 }), None).unwrap();
+
+    let queries: Vec<_> = server
+        .received()
+        .into_iter()
+        .filter(|req| req.namespace == "test-client-coll.mock_writes")
+        .map(|req| req.query.unwrap())
+        .collect();
+    assert_eq!(4, queries.len());
+
+    assert_eq!(Some(&Bson::String(String::from("title_1"))), queries[0].get("$hint"));
+    assert_eq!(
+        Some(&Bson::Document(doc! { "title": "Jaws" })),
+        queries[0].get("$query")
+    );
+
+    for query in &queries[1..] {
+        assert!(!query.contains_key("$hint"));
+    }
+}
+
+#[test]
+fn count_runs_aggregate_pipeline() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("aggregate") => {
+            let batch = match query.get_array("pipeline").unwrap().len() {
+                // Filter-only pipelines count three documents; anything with $skip/$limit, none.
+                2 => vec![Bson::Document(doc! { "_id": 1, "n": 3 })],
+                _ => Vec::new(),
+            };
+            (0, doc! {
+                "ok": 1,
+                "cursor": { "id": 0i64, "ns": "test-client-coll.count", "firstBatch": batch },
+            })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-coll").collection("count");
+
+    let count: Result<i64, Error> = coll.count(Some(doc! { "title": "Jaws" }), None);
+    assert_eq!(3, count.unwrap());
+
+    let mut options = CountOptions::new();
+    options.skip = Some(5);
+    options.limit = Some(10);
+    options.hint = Some(String::from("title_1"));
+    assert_eq!(0, coll.count(None, Some(options)).unwrap());
+
+    let commands = received_commands(&server);
+    assert_eq!(2, commands.len());
+
+    assert_eq!(
+        &vec![
+            Bson::Document(doc! { "$match": { "title": "Jaws" } }),
+            Bson::Document(doc! { "$group": { "_id": 1, "n": { "$sum": 1 } } }),
+        ],
+        commands[0].get_array("pipeline").unwrap()
+    );
+    assert!(!commands[0].contains_key("hint"));
+
+    assert_eq!(
+        &vec![
+            Bson::Document(doc! { "$match": {} }),
+            Bson::Document(doc! { "$skip": 5i64 }),
+            Bson::Document(doc! { "$limit": 10i64 }),
+            Bson::Document(doc! { "$group": { "_id": 1, "n": { "$sum": 1 } } }),
+        ],
+        commands[1].get_array("pipeline").unwrap()
+    );
+    assert_eq!(Some(&Bson::String(String::from("title_1"))), commands[1].get("hint"));
+}
+
+#[test]
+fn count_limit_follows_legacy_count_semantics() {
+    let reply = doc! {
+        "ok": 1,
+        "cursor": {
+            "id": 0i64,
+            "ns": "test-client-coll.count_limit",
+            "firstBatch": [{ "_id": 1, "n": 3 }],
+        },
+    };
+    let (server, coll) = mock_command_server("aggregate", reply, "count_limit");
+
+    let count_with_limit = |limit| {
+        let options = CountOptions { limit: Some(limit), ..CountOptions::new() };
+        coll.count(None, Some(options)).unwrap()
+    };
+    assert_eq!(3, count_with_limit(0));
+    assert_eq!(3, count_with_limit(-5));
+
+    let pipelines: Vec<Vec<Bson>> = received_commands(&server)
+        .iter()
+        .map(|command| command.get_array("pipeline").unwrap().clone())
+        .collect();
+    let group = Bson::Document(doc! { "$group": { "_id": 1, "n": { "$sum": 1 } } });
+
+    // A limit of 0 means no limit, and a negative limit is treated as its absolute value.
+    assert_eq!(
+        vec![
+            vec![Bson::Document(doc! { "$match": {} }), group.clone()],
+            vec![
+                Bson::Document(doc! { "$match": {} }),
+                Bson::Document(doc! { "$limit": 5i64 }),
+                group,
+            ],
+        ],
+        pipelines
+    );
+}
+
+// Starts a mock replica set primary whose first `failures` insert commands fail with a
+// NotMaster error, and returns a client with the given retry setting that has discovered it.
+fn stepping_down_primary(failures: usize, retry_writes: bool) -> (MockServer, Collection) {
+    let inserts = Arc::new(AtomicUsize::new(0));
+    let server = MockServer::start(Duration::from_millis(0), move |req| match req.query {
+        Some(ref query) if query.contains_key("insert") => {
+            if inserts.fetch_add(1, Ordering::SeqCst) < failures {
+                (0, doc! { "ok": 0, "code": 10107, "errmsg": "not master" })
+            } else {
+                (0, doc! { "ok": 1, "n": 1 })
+            }
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true, "setName": "rs", "maxWireVersion": 8 }),
+    });
+
+    let mut options = ClientOptions::new();
+    options.retry_writes = retry_writes;
+    let client = Client::connect_with_options("127.0.0.1", server.port, options).unwrap();
+
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    for _ in 0..100 {
+        match client.server_description(&host) {
+            Some(ref desc) if desc.server_type == ServerType::RSPrimary => break,
+            _ => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+
+    let coll = client.db("test-client-coll").collection("retry_writes");
+    (server, coll)
+}
+
+fn insert_commands(server: &MockServer) -> Vec<Document> {
+    server
+        .received()
+        .into_iter()
+        .filter_map(|req| req.query)
+        .filter(|query| query.contains_key("insert"))
+        .collect()
+}
+
+#[test]
+fn retryable_insert_survives_step_down() {
+    let (server, coll) = stepping_down_primary(1, true);
+
+    coll.insert_one(doc! { "title": "Jaws" }, None).unwrap();
+    coll.insert_one(doc! { "title": "Back to the Future" }, None).unwrap();
+
+    let inserts = insert_commands(&server);
+    assert_eq!(3, inserts.len());
+
+    // The retry reuses the original session and transaction number.
+    assert!(inserts[0].get_document("lsid").is_ok());
+    assert_eq!(inserts[0].get("lsid"), inserts[1].get("lsid"));
+    assert_eq!(Some(&Bson::I64(1)), inserts[0].get("txnNumber"));
+    assert_eq!(Some(&Bson::I64(1)), inserts[1].get("txnNumber"));
+
+    // The next write gets a new transaction number in the same session.
+    assert_eq!(inserts[0].get("lsid"), inserts[2].get("lsid"));
+    assert_eq!(Some(&Bson::I64(2)), inserts[2].get("txnNumber"));
+}
+
+#[test]
+fn writes_not_retried_unless_enabled() {
+    let (server, coll) = stepping_down_primary(1, false);
+
+    let _ = coll.insert_one(doc! { "title": "Jaws" }, None);
+
+    let inserts = insert_commands(&server);
+    assert_eq!(1, inserts.len());
+    assert!(!inserts[0].contains_key("lsid"));
+    assert!(!inserts[0].contains_key("txnNumber"));
+}
+
+#[test]
+fn search_pipeline_sent_verbatim() {
+    let reply = doc! {
+        "ok": 1,
+        "cursor": { "id": 0i64, "ns": "test-client-coll.search", "firstBatch": [] },
+    };
+    let (server, coll) = mock_command_server("aggregate", reply, "search");
+
+    let pipeline = vec![
+        doc! {
+            "$search": {
+                "index": "default",
+                "compound": {
+                    "must": [{ "text": { "query": "shark", "path": "title" } }],
+                    "should": [{ "range": { "path": "year", "gte": 1970 } }],
+                },
+            },
+        },
+        doc! { "$limit": 5 },
+        doc! { "$project": { "title": 1, "score": { "$meta": "searchScore" } } },
+    ];
+
+    coll.aggregate(pipeline.clone(), None).unwrap();
+
+    let commands = received_commands(&server);
+    assert_eq!(1, commands.len());
+
+    let expected: Vec<_> = pipeline.into_iter().map(Bson::Document).collect();
+    assert_eq!(&expected, commands[0].get_array("pipeline").unwrap());
+    assert!(!commands[0].contains_key("readConcern"));
+}
+
+#[test]
+fn find_one_and_update_returns_post_image_in_one_round_trip() {
+    let reply = doc! { "ok": 1, "value": { "_id": "hits", "n": 8 } };
+    let (server, coll) = mock_command_server("findAndModify", reply, "counters");
+
+    let mut options = FindOneAndUpdateOptions::new();
+    options.return_document = Some(ReturnDocument::After);
+    options.upsert = Some(true);
+    options.max_time_ms = Some(100);
+    options.projection = Some(doc! { "n": 1 });
+    options.collation = Some(doc! { "locale": "en" });
+
+    let counter = coll.find_one_and_update(
+        doc! { "_id": "hits" },
+        doc! { "$inc": { "n": 1 } },
+        Some(options),
+    ).unwrap();
+    assert_eq!(Some(doc! { "_id": "hits", "n": 8 }), counter);
+
+    let commands = received_commands(&server);
+    assert_eq!(1, commands.len());
+
+    let expected = doc! {
+        "findAndModify": "counters",
+        "query": { "_id": "hits" },
+        "update": { "$inc": { "n": 1 } },
+        "new": true,
+        "maxTimeMS": 100i64,
+        "fields": { "n": 1 },
+        "upsert": true,
+        "collation": { "locale": "en" },
+    };
+    assert_eq!(&expected, &commands[0]);
+}
+
+#[test]
+fn distinct_sends_collation_and_read_concern() {
+    let reply = doc! { "ok": 1, "values": ["rust", "mongodb"] };
+    let (server, coll) = mock_command_server("distinct", reply, "distinct_tags");
+
+    let options = DistinctOptions {
+        max_time_ms: Some(300),
+        collation: Some(doc! { "locale": "en", "strength": 1 }),
+        read_concern: Some(ReadConcern::with_level(ReadConcernLevel::Majority)),
+        ..DistinctOptions::default()
+    };
+    let tags = coll.distinct("tag", Some(doc! { "published": true }), Some(options)).unwrap();
+    assert_eq!(
+        vec![Bson::String(String::from("rust")), Bson::String(String::from("mongodb"))],
+        tags
+    );
+
+    let commands = received_commands(&server);
+    assert_eq!(1, commands.len());
+
+    let expected = doc! {
+        "distinct": "distinct_tags",
+        "key": "tag",
+        "query": { "published": true },
+        "maxTimeMS": 300i64,
+        "collation": { "locale": "en", "strength": 1 },
+        "readConcern": { "level": "majority" },
+    };
+    assert_eq!(&expected, &commands[0]);
+}
+
+#[test]
+fn count_documents_and_estimated_document_count() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("aggregate") => {
+            (0, doc! {
+                "ok": 1,
+                "cursor": {
+                    "id": 0i64,
+                    "ns": "test-client-coll.counts",
+                    "firstBatch": [{ "_id": 1, "n": 2 }],
+                },
+            })
+        }
+        Some(ref query) if query.contains_key("count") => (0, doc! { "ok": 1, "n": 7 }),
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-coll").collection("counts");
+
+    let options = CountOptions { max_time_ms: Some(100), ..CountOptions::new() };
+    assert_eq!(2, coll.count_documents(Some(doc! { "x": 1 }), Some(options)).unwrap());
+
+    let options = EstimatedDocumentCountOptions {
+        max_time_ms: Some(200),
+        ..EstimatedDocumentCountOptions::new()
+    };
+    assert_eq!(7, coll.estimated_document_count(Some(options)).unwrap());
+
+    let commands = received_commands(&server);
+    assert_eq!(2, commands.len());
+
+    assert_eq!("counts", commands[0].get_str("aggregate").unwrap());
+    assert_eq!(
+        &vec![
+            Bson::Document(doc! { "$match": { "x": 1 } }),
+            Bson::Document(doc! { "$group": { "_id": 1, "n": { "$sum": 1 } } }),
+        ],
+        commands[0].get_array("pipeline").unwrap()
+    );
+    assert_eq!(Some(&Bson::I64(100)), commands[0].get("maxTimeMS"));
+
+    assert_eq!(&doc! { "count": "counts", "maxTimeMS": 200i64 }, &commands[1]);
+}
+
+#[test]
+fn aggregate_sends_options_and_iterates_results() {
+    let reply = doc! {
+        "ok": 1,
+        "cursor": {
+            "id": 0i64,
+            "ns": "test-client-coll.aggregate_options",
+            "firstBatch": [{ "_id": "a", "n": 2 }, { "_id": "b", "n": 1 }],
+        },
+    };
+    let (server, coll) = mock_command_server("aggregate", reply, "aggregate_options");
+
+    let options = AggregateOptions {
+        allow_disk_use: Some(true),
+        batch_size: 10,
+        max_time_ms: Some(500),
+        collation: Some(doc! { "locale": "en", "strength": 2 }),
+        ..AggregateOptions::new()
+    };
+    let pipeline = vec![doc! { "$group": { "_id": "$tag", "n": { "$sum": 1 } } }];
+    let cursor = coll.aggregate(pipeline, Some(options)).unwrap();
+
+    let ids: Vec<_> = cursor
+        .map(|doc| String::from(doc.unwrap().get_str("_id").unwrap()))
+        .collect();
+    assert_eq!(vec!["a", "b"], ids);
+
+    let commands = received_commands(&server);
+    assert_eq!(1, commands.len());
+
+    let command = &commands[0];
+    assert_eq!("aggregate_options", command.get_str("aggregate").unwrap());
+    assert_eq!(Some(&Bson::Boolean(true)), command.get("allowDiskUse"));
+    assert_eq!(Some(&Bson::I64(500)), command.get("maxTimeMS"));
+    assert_eq!(&doc! { "batchSize": 10 }, command.get_document("cursor").unwrap());
+    assert_eq!(
+        &doc! { "locale": "en", "strength": 2 },
+        command.get_document("collation").unwrap()
+    );
+}
+
+#[test]
+fn find_command_maps_every_find_option() {
+    let options = FindOptions {
+        allow_partial_results: true,
+        no_cursor_timeout: true,
+        oplog_replay: true,
+        skip: Some(5),
+        limit: Some(10),
+        cursor_type: CursorType::TailableAwait,
+        batch_size: Some(3),
+        comment: Some(String::from("tagged")),
+        max_time_ms: Some(250),
+        modifiers: Some(doc! { "$hint": "x_1", "$showDiskLoc": true }),
+        projection: Some(doc! { "x": 1 }),
+        sort: Some(doc! { "x": -1 }),
+        collation: Some(doc! { "locale": "en", "strength": 2 }),
+        read_concern: Some(ReadConcern::with_level(ReadConcernLevel::Majority)),
+        read_preference: None,
+    };
+
+    let command = build_find_command(
+        "test-client-coll.find_command",
+        Some(doc! { "x": { "$gt": 1 } }),
+        &options,
+    ).unwrap();
+
+    let expected = doc! {
+        "find": "find_command",
+        "filter": { "x": { "$gt": 1 } },
+        "hint": "x_1",
+        "showRecordId": true,
+        "projection": { "x": 1 },
+        "sort": { "x": -1 },
+        "skip": 5i64,
+        "limit": 10i64,
+        "batchSize": 3,
+        "comment": "tagged",
+        "maxTimeMS": 250i64,
+        "collation": { "locale": "en", "strength": 2 },
+        "readConcern": { "level": "majority" },
+        "tailable": true,
+        "awaitData": true,
+        "oplogReplay": true,
+        "noCursorTimeout": true,
+        "allowPartialResults": true,
+    };
+    assert_eq!(expected, command);
+}
+
+#[test]
+fn find_command_omits_unset_options() {
+    let command = build_find_command("test-client-coll.find_command", None, &FindOptions::new())
+        .unwrap();
+    assert_eq!(doc! { "find": "find_command", "filter": {} }, command);
+
+    let options = FindOptions { limit: Some(-4), ..FindOptions::new() };
+    let command = build_find_command("test-client-coll.find_command", None, &options).unwrap();
+    assert_eq!(Some(&Bson::I64(4)), command.get("limit"));
+    assert_eq!(Some(&Bson::Boolean(true)), command.get("singleBatch"));
+
+    let options = FindOptions {
+        comment: Some(String::from("explicit")),
+        modifiers: Some(doc! { "$comment": "modifier", "$maxScan": 100 }),
+        ..FindOptions::new()
+    };
+    let command = build_find_command("test-client-coll.find_command", None, &options).unwrap();
+    assert_eq!(Some(&Bson::String(String::from("explicit"))), command.get("comment"));
+    assert_eq!(Some(&Bson::I32(100)), command.get("maxScan"));
+}
+
+#[test]
+fn find_command_rejects_modifiers_without_command_fields() {
+    let options = FindOptions {
+        modifiers: Some(doc! { "$min": { "x": 1 }, "$returnKey": true, "$snapshot": true }),
+        ..FindOptions::new()
+    };
+    let command = build_find_command("test-client-coll.find_command", None, &options).unwrap();
+    assert_eq!(Some(&Bson::Document(doc! { "x": 1 })), command.get("min"));
+    assert_eq!(Some(&Bson::Boolean(true)), command.get("returnKey"));
+    assert_eq!(Some(&Bson::Boolean(true)), command.get("snapshot"));
+
+    let unsupported = vec![
+        doc! { "$explain": true },
+        doc! { "$query": { "x": 1 } },
+        doc! { "x": 1 },
+    ];
+
+    for modifiers in unsupported {
+        let options = FindOptions { modifiers: Some(modifiers), ..FindOptions::new() };
+        match build_find_command("test-client-coll.find_command", None, &options) {
+            Err(Error::ArgumentError(_)) => (),
+            result => panic!("Expected an argument error, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn create_unique_ttl_index_returns_generated_name() {
+    let (server, coll) = mock_command_server("createIndexes", doc! { "ok": 1 }, "ttl_index");
+
+    let mut options = IndexOptions::new();
+    options.unique = Some(true);
+    options.expire_after_seconds = Some(3600);
+    options.partial_filter_expression = Some(doc! { "session": { "$exists": true } });
+    options.collation = Some(doc! { "locale": "en", "strength": 2 });
+
+    let name = coll.create_index(doc! { "created_at": 1 }, Some(options)).unwrap();
+    assert_eq!("created_at_1", name);
+
+    let commands = received_commands(&server);
+    assert_eq!(1, commands.len());
+    assert_eq!(
+        doc! {
+            "createIndexes": "ttl_index",
+            "indexes": [{
+                "key": { "created_at": 1 },
+                "expireAfterSeconds": 3600,
+                "name": "created_at_1",
+                "unique": true,
+                "partialFilterExpression": { "session": { "$exists": true } },
+                "collation": { "locale": "en", "strength": 2 },
+            }],
+        },
+        commands[0]
+    );
+}
+
+#[test]
+fn collated_find_runs_find_command() {
+    let reply = doc! {
+        "ok": 1,
+        "cursor": {
+            "id": 0i64,
+            "ns": "test-client-coll.collated_find",
+            "firstBatch": [{ "_id": 1, "name": "Élodie" }, { "_id": 2, "name": "elodie" }],
+        },
+    };
+    let (server, coll) = mock_command_server("find", reply, "collated_find");
+
+    let options = FindOptions {
+        collation: Some(doc! { "locale": "en", "strength": 2 }),
+        ..FindOptions::new()
+    };
+    let results: Vec<_> = coll.find(Some(doc! { "name": "elodie" }), Some(options))
+        .unwrap()
+        .map(|doc| doc.unwrap())
+        .collect();
+    assert_eq!(2, results.len());
+
+    let mut delete_options = DeleteOptions::new();
+    delete_options.collation = Some(doc! { "locale": "fr" });
+    let _ = coll.delete_many_with_options(doc! { "name": "elodie" }, Some(delete_options));
+
+    let requests = server.received();
+    assert_eq!(2, requests.len());
+    assert_eq!("test-client-coll.$cmd", requests[0].namespace);
+    assert_eq!(
+        doc! {
+            "find": "collated_find",
+            "filter": { "name": "elodie" },
+            "collation": { "locale": "en", "strength": 2 },
+        },
+        requests[0].query.clone().unwrap()
+    );
+
+    let deletes = requests[1].query.as_ref().unwrap().get_array("deletes").unwrap();
+    assert_eq!(
+        &Bson::Document(doc! {
+            "q": { "name": "elodie" },
+            "limit": 0i64,
+            "collation": { "locale": "fr" },
+        }),
+        &deletes[0]
+    );
+}
+
+#[test]
+fn majority_read_concern_is_sent_with_reads() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("find") || query.contains_key("aggregate") => {
+            (0, doc! {
+                "ok": 1,
+                "cursor": {
+                    "id": 0i64,
+                    "ns": "test-client-coll.read_concern",
+                    "firstBatch": [{ "_id": 1, "n": 1 }],
+                },
+            })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-coll").collection("read_concern");
+    let majority = ReadConcern::with_level(ReadConcernLevel::Majority);
+
+    let options = FindOptions {
+        read_concern: Some(majority.clone()),
+        ..FindOptions::new()
+    };
+    assert_eq!(1, coll.find(None, Some(options)).unwrap().count());
+
+    let options = CountOptions {
+        read_concern: Some(majority.clone()),
+        ..CountOptions::new()
+    };
+    assert_eq!(1, coll.count_documents(None, Some(options)).unwrap());
+
+    let options = AggregateOptions {
+        read_concern: Some(majority),
+        ..AggregateOptions::new()
+    };
+    assert_eq!(1, coll.aggregate(Vec::new(), Some(options)).unwrap().count());
+
+    let commands = received_commands(&server);
+    assert_eq!(3, commands.len());
+    assert_eq!(
+        doc! { "find": "read_concern", "filter": {}, "readConcern": { "level": "majority" } },
+        commands[0]
+    );
+    for command in &commands[1..] {
+        assert_eq!(
+            &doc! { "level": "majority" },
+            command.get_document("readConcern").unwrap()
+        );
+    }
+}
+
+#[test]
+fn key_validation_rejects_invalid_inserts_only() {
+    let reply = doc! { "ok": 1, "n": 1, "nModified": 1 };
+    let (server, mut coll) = mock_command_server("update", reply, "key_validation");
+    coll.set_key_validation(true);
+
+    match coll.insert_one(doc! { "$where": "x" }, None) {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("'$where'")),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    match coll.update_one(doc! {}, doc! { "$set": { "a\0b": 1 } }, None) {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("null byte")),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    coll.update_one(doc! {}, doc! { "$set": { "x": 1 } }, None).unwrap();
+    assert_eq!(1, server.received().len());
+}
+
+#[test]
+fn no_op_update_reports_match_without_modification() {
+    let reply = doc! { "ok": 1, "n": 1, "nModified": 0 };
+    let (_server, coll) = mock_command_server("update", reply, "no_op_update");
+
+    let result = coll.update_one(doc! { "_id": 1 }, doc! { "$set": { "x": 1 } }, None).unwrap();
+    assert_eq!(1, result.matched_count);
+    assert_eq!(0, result.modified_count);
+    assert!(result.upserted_id.is_none());
+
+    let result = coll.update_many(doc! {}, doc! { "$set": { "x": 1 } }, None).unwrap();
+    assert_eq!(1, result.matched_count);
+    assert_eq!(0, result.modified_count);
+}
+
+#[test]
+fn replace_and_update_documents_are_validated() {
+    let reply = doc! { "ok": 1, "n": 1, "nModified": 1 };
+    let (server, coll) = mock_command_server("update", reply, "replace_and_update_validation");
+
+    match coll.replace_one(doc! {}, doc! { "x": 1, "$set": { "y": 1 } }, None) {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("'$set'")),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    match coll.update_one(doc! {}, doc! { "$set": { "y": 1 }, "x": 1 }, None) {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("'x'")),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    match coll.update_one(doc! {}, doc! {}, None) {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("at least one")),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    assert!(coll.update_many(doc! {}, doc! {}, None).is_err());
+    assert!(server.received().is_empty());
+}
@@ -0,0 +1,141 @@
+use bson::Bson;
+use mongodb::{Client, CommandType, Error, ThreadedClient};
+use mongodb::common::{validate_document_keys, validate_namespace, ReadConcern, ReadConcernLevel,
+                      ReadMode, ReadPreference, Timestamp};
+use mongodb::db::ThreadedDatabase;
+
+#[test]
+fn read_preference_from_secondary_ok() {
+    let read_pref = ReadPreference::from_secondary_ok(true);
+    assert_eq!(ReadMode::SecondaryPreferred, read_pref.mode);
+    assert!(read_pref.tag_sets.is_empty());
+
+    let read_pref = ReadPreference::from_secondary_ok(false);
+    assert_eq!(ReadMode::Primary, read_pref.mode);
+    assert!(read_pref.tag_sets.is_empty());
+}
+
+#[test]
+fn timestamp_round_trips_through_bson() {
+    let timestamp = Timestamp::new(1_500_000_000, 7);
+    let bson = timestamp.to_bson();
+    assert_eq!(Bson::TimeStamp((1_500_000_000 << 32) | 7), bson);
+    assert_eq!(Some(timestamp), Timestamp::from_bson(&bson));
+
+    let max = Timestamp::new(u32::max_value(), u32::max_value());
+    assert_eq!(max, Timestamp::from_raw(max.to_raw()));
+    assert_eq!(None, Timestamp::from_bson(&Bson::I64(7)));
+}
+
+#[test]
+fn timestamp_orders_by_seconds_then_increment() {
+    assert!(Timestamp::new(10, 1) < Timestamp::new(10, 2));
+    assert!(Timestamp::new(10, 9) < Timestamp::new(11, 0));
+    assert!(Timestamp::new(11, 0) >= Timestamp::new(11, 0));
+    assert!(Timestamp::new(2, 0) > Timestamp::new(1, u32::max_value()));
+}
+
+#[test]
+fn read_concern_emits_only_the_fields_set() {
+    let level_only = ReadConcern::with_level(ReadConcernLevel::Majority);
+    assert_eq!(doc! { "level": "majority" }, level_only.to_document());
+
+    let cluster_time = Timestamp::new(1_500_000_000, 3);
+    let after_only = ReadConcern::after_cluster_time(cluster_time);
+    assert_eq!(
+        doc! { "afterClusterTime": cluster_time.to_bson() },
+        after_only.to_document()
+    );
+
+    let both = ReadConcern {
+        level: Some(ReadConcernLevel::Local),
+        after_cluster_time: Some(cluster_time),
+    };
+    assert_eq!(
+        doc! { "level": "local", "afterClusterTime": cluster_time.to_bson() },
+        both.to_document()
+    );
+
+    assert!(ReadConcern::new().to_document().is_empty());
+}
+
+#[test]
+fn validate_namespace_accepts_collections_and_commands() {
+    assert!(validate_namespace("test-client-common.coll").is_ok());
+    assert!(validate_namespace("test-client-common.fs.files").is_ok());
+    assert!(validate_namespace("admin.$cmd").is_ok());
+    assert!(validate_namespace("$external.$cmd").is_ok());
+    assert!(validate_namespace("local.oplog.$main").is_ok());
+}
+
+#[test]
+fn validate_namespace_rejects_empty_names() {
+    for namespace in &[".coll", "test-client-common.", ".$cmd", "no-collection"] {
+        match validate_namespace(namespace) {
+            Err(Error::ArgumentError(_)) => (),
+            other => panic!("expected an argument error for '{}', got {:?}", namespace, other),
+        }
+    }
+}
+
+#[test]
+fn validate_namespace_rejects_illegal_characters() {
+    let namespaces = [
+        "te$t.coll",
+        "test\0db.coll",
+        "test.co$ll",
+        "test.co\0ll",
+        "test.$cmdx",
+        "$externalx.coll",
+        "local.oplog.$mainx",
+    ];
+
+    for namespace in &namespaces {
+        match validate_namespace(namespace) {
+            Err(Error::ArgumentError(_)) => (),
+            other => panic!("expected an argument error for '{:?}', got {:?}", namespace, other),
+        }
+    }
+}
+
+#[test]
+fn command_on_empty_database_name_fails_before_server_selection() {
+    let client = Client::connect("i-dont-exist", 27017).unwrap();
+    let result = client.db("").command(doc! { "ping": 1 }, CommandType::Suppressed, None);
+
+    match result {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("database")),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+}
+
+#[test]
+fn validate_document_keys_rejects_null_bytes_at_any_depth() {
+    let docs = vec![
+        doc! { "na\0me": 1 },
+        doc! { "outer": { "in\0ner": 1 } },
+        doc! { "list": [{ "ke\0y": 1 }] },
+    ];
+
+    for doc in &docs {
+        for &allow_operators in &[false, true] {
+            match validate_document_keys(doc, allow_operators) {
+                Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("null byte")),
+                other => panic!("expected an argument error for {:?}, got {:?}", doc, other),
+            }
+        }
+    }
+}
+
+#[test]
+fn validate_document_keys_rejects_operators_unless_allowed() {
+    let doc = doc! { "$set": { "x": 1 } };
+
+    match validate_document_keys(&doc, false) {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("'$set'")),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    assert!(validate_document_keys(&doc, true).is_ok());
+    assert!(validate_document_keys(&doc! { "x": { "$y": 1 } }, false).is_ok());
+}
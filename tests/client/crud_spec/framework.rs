@@ -170,7 +170,11 @@ macro_rules! run_insert_one_test {
 macro_rules! run_replace_one_test {
     ( $db:expr, $coll:expr, $filter:expr, $replacement:expr, $upsert:expr,
         $outcome:expr ) => {{
-            let options = ReplaceOptions { upsert: $upsert, write_concern: None };
+            let options = ReplaceOptions {
+                upsert: $upsert,
+                write_concern: None,
+                ..Default::default()
+            };
             let actual = $coll.replace_one($filter, $replacement, Some(options)).unwrap();
 
             let (matched, modified, upserted) = match $outcome.result {
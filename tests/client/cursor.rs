@@ -1,12 +1,21 @@
 use bson::{Bson, Document};
 
-use mongodb::{Client, CommandType, ThreadedClient};
+use mongodb::{Client, ClientOptions, CommandType, Error, ThreadedClient};
 use mongodb::common::{ReadMode, ReadPreference};
 use mongodb::coll::options::FindOptions;
+use mongodb::connstring;
 use mongodb::db::ThreadedDatabase;
 use mongodb::cursor::Cursor;
+use mongodb::error::Result;
+use mongodb::topology::server::ServerType;
 use mongodb::wire_protocol::flags::OpQueryFlags;
 
+use client::mock::{MockRequest, MockServer, OP_GET_MORE, OP_KILL_CURSORS};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
 #[test]
 fn cursor_features() {
     let client = Client::connect("localhost", 27017).unwrap();
@@ -82,3 +91,476 @@ fn cursor_features() {
         };
     }
 }
+
+#[test]
+fn command_cursor_uses_reply_namespace_for_get_more() {
+    let server = MockServer::start(Duration::from_millis(0), |req| {
+        match req.query {
+            Some(ref query) if query.contains_key("aggregate") => {
+                (0, doc! {
+                    "cursor": {
+                        "id": 42i64,
+                        "ns": "test-client-cursor.out_coll",
+                        "firstBatch": [],
+                    },
+                    "ok": 1,
+                })
+            }
+            Some(_) => (0, doc! { "ok": 1, "ismaster": true }),
+            None => (0, doc! { "foo": 1 }),
+        }
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let db = client.db("test-client-cursor");
+    let spec = doc! {
+        "aggregate": "in_coll",
+        "pipeline": [{ "$out": "out_coll" }],
+        "cursor": {},
+    };
+
+    let mut cursor = db.command_cursor(
+        spec,
+        CommandType::Aggregate,
+        ReadPreference::new(ReadMode::Primary, None),
+    ).unwrap();
+
+    let doc = cursor.next().expect("Expected a document.").unwrap();
+    assert_eq!(Some(&Bson::I32(1)), doc.get("foo"));
+    assert!(cursor.next().is_none());
+
+    let get_mores: Vec<MockRequest> = server
+        .received()
+        .into_iter()
+        .filter(|req| req.op_code == OP_GET_MORE)
+        .collect();
+
+    assert_eq!(1, get_mores.len());
+    assert_eq!("test-client-cursor.out_coll", get_mores[0].namespace);
+    assert_eq!(Some(42), get_mores[0].cursor_id);
+}
+
+// Starts a mock server whose aggregate cursor returns two documents in its first batch and
+// one per getMore, ending with the reply produced by `last_reply` for the second getMore.
+fn mock_batched_cursor_server(last_reply: Document) -> MockServer {
+    MockServer::start(Duration::from_millis(0), move |req| match req.query {
+        Some(ref query) if query.contains_key("aggregate") => {
+            (0, doc! {
+                "cursor": {
+                    "id": 42i64,
+                    "ns": "test-client-cursor.batched",
+                    "firstBatch": [{ "x": 1 }, { "x": 2 }],
+                },
+                "ok": 1,
+            })
+        }
+        Some(_) => (0, doc! { "ok": 1, "ismaster": true }),
+        None if req.cursor_id == Some(42) => (43, doc! { "x": 3 }),
+        None => (0, last_reply.clone()),
+    })
+}
+
+fn batched_cursor(server: &MockServer) -> Cursor {
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let db = client.db("test-client-cursor");
+    let spec = doc! {
+        "aggregate": "batched",
+        "pipeline": [],
+        "cursor": {},
+    };
+
+    db.command_cursor(
+        spec,
+        CommandType::Aggregate,
+        ReadPreference::new(ReadMode::Primary, None),
+    ).unwrap()
+}
+
+// Waits for the mock server to record the cursors killed so far, since OP_KILL_CURSORS has no
+// reply to wait on.
+fn killed_cursors(server: &MockServer, expected: usize) -> Vec<i64> {
+    for _ in 0..100 {
+        let killed: Vec<i64> = server
+            .received()
+            .into_iter()
+            .filter(|req| req.op_code == OP_KILL_CURSORS)
+            .filter_map(|req| req.cursor_id)
+            .collect();
+
+        if killed.len() >= expected {
+            return killed;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    Vec::new()
+}
+
+#[test]
+fn limit_kills_server_cursor() {
+    let server = mock_batched_cursor_server(doc! { "x": 4 });
+    let cursor = batched_cursor(&server).limit(3);
+
+    let values: Vec<_> = cursor.map(|doc| doc.unwrap().get_i32("x").unwrap()).collect();
+    assert_eq!(vec![1, 2, 3], values);
+
+    // Only the one remaining document is requested, and the next cursor is killed instead of
+    // being fetched from.
+    let get_mores: Vec<MockRequest> = server
+        .received()
+        .into_iter()
+        .filter(|req| req.op_code == OP_GET_MORE)
+        .collect();
+    assert_eq!(1, get_mores.len());
+    assert_eq!(Some(1), get_mores[0].number_to_return);
+
+    assert_eq!(vec![43], killed_cursors(&server, 1));
+}
+
+#[test]
+fn dropping_partially_consumed_cursor_kills_it() {
+    let server = mock_batched_cursor_server(doc! { "x": 4 });
+    let mut cursor = batched_cursor(&server);
+
+    assert_eq!(1, cursor.next().unwrap().unwrap().get_i32("x").unwrap());
+    drop(cursor);
+
+    assert_eq!(vec![42], killed_cursors(&server, 1));
+}
+
+#[test]
+fn exhausted_cursor_is_not_killed() {
+    let server = mock_batched_cursor_server(doc! { "x": 4 });
+    assert_eq!(4, batched_cursor(&server).count());
+
+    thread::sleep(Duration::from_millis(100));
+    assert!(killed_cursors(&server, 0).is_empty());
+}
+
+#[test]
+fn close_kills_cursor_once() {
+    let server = mock_batched_cursor_server(doc! { "x": 4 });
+    let mut cursor = batched_cursor(&server);
+
+    assert_eq!(1, cursor.next().unwrap().unwrap().get_i32("x").unwrap());
+    cursor.close().unwrap();
+    assert_eq!(vec![42], killed_cursors(&server, 1));
+
+    // The cursor is not killed again when it is dropped by close.
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(vec![42], killed_cursors(&server, 1));
+}
+
+#[test]
+fn close_sends_kill_cursors_command_on_3_2() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("aggregate") => {
+            (0, doc! {
+                "cursor": {
+                    "id": 42i64,
+                    "ns": "test-client-cursor.batched",
+                    "firstBatch": [{ "x": 1 }, { "x": 2 }],
+                },
+                "ok": 1,
+            })
+        }
+        Some(ref query) if query.contains_key("killCursors") => {
+            (0, doc! { "cursorsKilled": [42i64], "ok": 1 })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true, "maxWireVersion": 4 }),
+    });
+
+    let cursor = batched_cursor(&server);
+    cursor.close().unwrap();
+
+    let requests = server.received();
+    assert!(requests.iter().all(|req| req.op_code != OP_KILL_CURSORS));
+
+    let kill = requests
+        .iter()
+        .filter_map(|req| req.query.as_ref())
+        .find(|query| query.contains_key("killCursors"))
+        .expect("expected a killCursors command");
+    assert_eq!(Some(&Bson::String(String::from("batched"))), kill.get("killCursors"));
+    assert_eq!(Some(&Bson::Array(vec![Bson::I64(42)])), kill.get("cursors"));
+}
+
+#[test]
+fn failed_kill_does_not_fail_a_fully_read_cursor() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("aggregate") => {
+            (0, doc! {
+                "cursor": {
+                    "id": 42i64,
+                    "ns": "test-client-cursor.batched",
+                    "firstBatch": [{ "x": 1 }, { "x": 2 }],
+                },
+                "ok": 1,
+            })
+        }
+        Some(ref query) if query.contains_key("killCursors") => {
+            (0, doc! { "ok": 0, "errmsg": "cursor kill failed" })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true, "maxWireVersion": 4 }),
+    });
+
+    let cursor = batched_cursor(&server).limit(2);
+    let docs = cursor.collect::<Result<Vec<_>>>().expect("Expected every document.");
+    assert_eq!(2, docs.len());
+
+    let kills = server
+        .received()
+        .into_iter()
+        .filter_map(|req| req.query)
+        .filter(|query| query.contains_key("killCursors"))
+        .count();
+    assert_eq!(1, kills);
+}
+
+// Opens a batched cursor on the primary of a single-member mock replica set, then waits for
+// the member to step down, so that selecting a primary would block for the selection timeout.
+fn stepped_down_cursor() -> (MockServer, Cursor) {
+    let primary = Arc::new(AtomicBool::new(true));
+    let is_primary = primary.clone();
+    let members = Arc::new(Mutex::new(Vec::new()));
+    let hosts = members.clone();
+    let server = MockServer::start(Duration::from_millis(0), move |req| match req.query {
+        Some(ref query) if query.contains_key("aggregate") => {
+            (0, doc! {
+                "cursor": {
+                    "id": 42i64,
+                    "ns": "test-client-cursor.batched",
+                    "firstBatch": [{ "x": 1 }, { "x": 2 }],
+                },
+                "ok": 1,
+            })
+        }
+        None if req.cursor_id == Some(42) => (0, doc! { "x": 3 }),
+        _ => {
+            let primary = is_primary.load(Ordering::SeqCst);
+            let hosts: Vec<Bson> =
+                hosts.lock().unwrap().iter().cloned().map(Bson::String).collect();
+            (0, doc! {
+                "ok": 1,
+                "ismaster": primary,
+                "secondary": !primary,
+                "setName": "rs",
+                "hosts": hosts,
+            })
+        }
+    });
+    members.lock().unwrap().push(format!("127.0.0.1:{}", server.port));
+
+    let uri = format!("mongodb://127.0.0.1:{}/?replicaSet=rs", server.port);
+    let options = ClientOptions::builder().heartbeat_frequency_ms(500).build();
+    let client = Client::with_uri_and_options(&uri, options).unwrap();
+    let spec = doc! { "aggregate": "batched", "pipeline": [], "cursor": {} };
+    let cursor = client
+        .db("test-client-cursor")
+        .command_cursor(spec, CommandType::Aggregate, ReadPreference::new(ReadMode::Primary, None))
+        .unwrap();
+
+    primary.store(false, Ordering::SeqCst);
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while client.server_description(&host).map(|desc| desc.server_type) !=
+        Some(ServerType::RSSecondary)
+    {
+        assert!(Instant::now() < deadline, "Expected the server to step down.");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    (server, cursor)
+}
+
+#[test]
+fn dropped_cursor_is_killed_on_its_server_without_selection() {
+    let (server, cursor) = stepped_down_cursor();
+
+    let start = Instant::now();
+    drop(cursor);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(vec![42], killed_cursors(&server, 1));
+}
+
+#[test]
+fn get_more_is_sent_to_the_cursor_server_without_selection() {
+    let (server, cursor) = stepped_down_cursor();
+
+    let start = Instant::now();
+    let docs = cursor.collect::<Result<Vec<_>>>().expect("Expected every document.");
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(3, docs.len());
+
+    let get_mores = server
+        .received()
+        .into_iter()
+        .filter(|req| req.op_code == OP_GET_MORE)
+        .count();
+    assert_eq!(1, get_mores);
+}
+
+#[test]
+fn batch_size_applies_to_get_mores() {
+    let server = mock_batched_cursor_server(doc! { "x": 4 });
+    let cursor = batched_cursor(&server).batch_size(1000);
+
+    assert_eq!(4, cursor.count());
+
+    let get_mores: Vec<MockRequest> = server
+        .received()
+        .into_iter()
+        .filter(|req| req.op_code == OP_GET_MORE)
+        .collect();
+    assert_eq!(2, get_mores.len());
+    assert!(get_mores.iter().all(|req| req.number_to_return == Some(1000)));
+}
+
+#[test]
+fn max_time_ms_sends_get_more_commands() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("aggregate") => {
+            (0, doc! {
+                "cursor": {
+                    "id": 42i64,
+                    "ns": "test-client-cursor.fs.timed",
+                    "firstBatch": [{ "x": 1 }],
+                },
+                "ok": 1,
+            })
+        }
+        Some(ref query) if query.contains_key("getMore") => {
+            (0, doc! {
+                "cursor": {
+                    "id": 0i64,
+                    "ns": "test-client-cursor.fs.timed",
+                    "nextBatch": [{ "x": 2 }, { "x": 3 }],
+                },
+                "ok": 1,
+            })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let spec = doc! { "aggregate": "fs.timed", "pipeline": [], "cursor": {} };
+    let cursor = client
+        .db("test-client-cursor")
+        .command_cursor(spec, CommandType::Aggregate, ReadPreference::new(ReadMode::Primary, None))
+        .unwrap()
+        .batch_size(10)
+        .max_time_ms(500);
+
+    let values: Vec<_> = cursor.map(|doc| doc.unwrap().get_i32("x").unwrap()).collect();
+    assert_eq!(vec![1, 2, 3], values);
+
+    let requests = server.received();
+    assert!(requests.iter().all(|req| req.op_code != OP_GET_MORE));
+
+    let commands: Vec<&Document> = requests
+        .iter()
+        .filter_map(|req| req.query.as_ref())
+        .filter(|query| query.contains_key("getMore"))
+        .collect();
+    assert_eq!(1, commands.len());
+    assert_eq!(Some(&Bson::I64(42)), commands[0].get("getMore"));
+    assert_eq!(Some(&Bson::String(String::from("fs.timed"))), commands[0].get("collection"));
+    assert_eq!(Some(&Bson::I64(500)), commands[0].get("maxTimeMS"));
+    assert_eq!(Some(&Bson::I32(10)), commands[0].get("batchSize"));
+}
+
+#[test]
+fn collect_documents_across_batches() {
+    let server = mock_batched_cursor_server(doc! { "x": 4 });
+    let mut cursor = batched_cursor(&server);
+
+    let documents = cursor.collect_documents().unwrap();
+    let values: Vec<_> = documents.iter().map(|doc| doc.get_i32("x").unwrap()).collect();
+    assert_eq!(vec![1, 2, 3, 4], values);
+    assert!(cursor.next().is_none());
+
+    let get_mores = server
+        .received()
+        .into_iter()
+        .filter(|req| req.op_code == OP_GET_MORE)
+        .count();
+    assert_eq!(2, get_mores);
+}
+
+#[test]
+fn collect_documents_surfaces_get_more_error() {
+    let server = mock_batched_cursor_server(doc! {
+        "ok": 0,
+        "code": 43,
+        "errmsg": "cursor killed",
+    });
+    let mut cursor = batched_cursor(&server);
+
+    match cursor.collect_documents() {
+        Err(Error::OperationError(ref msg)) => assert_eq!("cursor killed", msg),
+        other => panic!("expected an operation error, got {:?}", other),
+    }
+}
+
+#[test]
+fn tail_waits_through_empty_batches() {
+    let get_mores = Arc::new(AtomicUsize::new(0));
+    let counter = get_mores.clone();
+
+    // The first batch holds one document, and every other getMore comes back empty before a
+    // newly appended document arrives.
+    let server = MockServer::start_with_batches(Duration::from_millis(0), move |req| {
+        match req.query {
+            Some(ref query) if query.contains_key("isMaster") => {
+                (0, vec![doc! { "ok": 1, "ismaster": true }])
+            }
+            Some(_) => (7, vec![doc! { "x": 1 }]),
+            None => {
+                let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                if n % 2 == 0 {
+                    (7, vec![doc! { "x": (n / 2 + 1) as i32 }])
+                } else {
+                    (7, Vec::new())
+                }
+            }
+        }
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-cursor").collection("capped");
+    let tail = coll.tail(None, None).unwrap();
+
+    let values: Vec<_> = tail.take(3)
+        .map(|doc| doc.unwrap().get_i32("x").unwrap())
+        .collect();
+    assert_eq!(vec![1, 2, 3], values);
+    assert_eq!(4, get_mores.load(Ordering::SeqCst));
+
+    // Dropping the tailable cursor kills the still-open server cursor.
+    assert_eq!(vec![7], killed_cursors(&server, 1));
+
+    let requests = server.received();
+    assert_eq!("test-client-cursor.capped", requests[0].namespace);
+    assert!(requests[1..].iter().filter(|req| req.op_code != OP_KILL_CURSORS).all(|req| {
+        req.op_code == OP_GET_MORE && req.cursor_id == Some(7)
+    }));
+}
+
+#[test]
+fn tail_ends_when_server_closes_cursor() {
+    let server = MockServer::start_with_batches(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("isMaster") => {
+            (0, vec![doc! { "ok": 1, "ismaster": true }])
+        }
+        Some(_) => (7, Vec::new()),
+        None => (0, vec![doc! { "x": 1 }]),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-cursor").collection("capped");
+    let mut tail = coll.tail(None, None).unwrap();
+
+    assert_eq!(1, tail.next().unwrap().unwrap().get_i32("x").unwrap());
+    assert!(tail.next().is_none());
+}
@@ -1,9 +1,14 @@
 use bson::{self, Bson};
-use mongodb::{Client, ThreadedClient};
+use mongodb::{Client, Error, ThreadedClient};
+use mongodb::coll::build_aggregate_command;
+use mongodb::coll::options::AggregateOptions;
 use mongodb::db::ThreadedDatabase;
 use mongodb::db::options::CreateUserOptions;
 use mongodb::db::roles::{AllDatabaseRole, SingleDatabaseRole, Role};
 
+use client::mock::MockServer;
+use std::time::Duration;
+
 #[test]
 fn create_collection() {
     let client = Client::connect("localhost", 27017).unwrap();
@@ -193,3 +198,93 @@ fn get_version() {
     let db = client.db("test-client-db-get_version");
     let _ = db.version().unwrap();
 }
+
+#[test]
+fn build_database_aggregate_command() {
+    let options = AggregateOptions {
+        max_time_ms: Some(1000),
+        explain: Some(true),
+        comment: Some(String::from("current ops")),
+        ..AggregateOptions::new()
+    };
+
+    let command = build_aggregate_command(
+        Bson::I32(1),
+        vec![doc! { "$currentOp": { "allUsers": true } }],
+        Some(options),
+    );
+
+    assert_eq!(
+        doc! {
+            "aggregate": 1,
+            "pipeline": [{ "$currentOp": { "allUsers": true } }],
+            "explain": true,
+            "maxTimeMS": 1000i64,
+            "comment": "current ops",
+        },
+        command
+    );
+
+    let command = build_aggregate_command(Bson::I32(1), Vec::new(), None);
+    assert_eq!(doc! { "aggregate": 1, "pipeline": [], "cursor": {} }, command);
+}
+
+#[test]
+fn database_aggregate_runs_against_database() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.get_bool("explain").unwrap_or(false) => {
+            (0, doc! { "ok": 1, "stages": [{ "$listLocalSessions": {} }] })
+        }
+        Some(ref query) if query.contains_key("aggregate") => {
+            (0, doc! {
+                "ok": 1,
+                "cursor": {
+                    "id": 0i64,
+                    "ns": "admin.$cmd.aggregate",
+                    "firstBatch": [{ "_id": { "id": 1 } }],
+                },
+            })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let db = client.db("admin");
+    let pipeline = vec![doc! { "$listLocalSessions": {} }];
+
+    let sessions: Vec<_> = db.aggregate(pipeline.clone(), None).unwrap().collect();
+    assert_eq!(1, sessions.len());
+    assert_eq!(doc! { "_id": { "id": 1 } }, sessions[0].as_ref().unwrap().clone());
+
+    let options = AggregateOptions {
+        explain: Some(true),
+        ..AggregateOptions::new()
+    };
+    let mut plan = db.aggregate(pipeline, Some(options)).unwrap();
+    let plan = plan.next().unwrap().unwrap();
+    assert!(plan.contains_key("stages"));
+
+    let requests = server.received();
+    assert_eq!(2, requests.len());
+    for req in &requests {
+        assert_eq!("admin.$cmd", req.namespace);
+        assert_eq!(Some(&Bson::I32(1)), req.query.as_ref().unwrap().get("aggregate"));
+    }
+}
+
+#[test]
+fn current_op_aggregate_requires_admin_database() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let pipeline = vec![doc! { "$currentOp": { "allUsers": true } }];
+
+    match client.db("test-db").aggregate(pipeline, None) {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("admin")),
+        other => panic!("Expected an argument error, got {:?}", other.map(|_| ())),
+    }
+
+    assert!(server.received().is_empty());
+}
@@ -1,6 +1,10 @@
 use mongodb::common::WriteConcern;
 use mongodb::coll::error::{BulkWriteException, WriteConcernError, WriteError};
-use mongodb::Error;
+use mongodb::{Client, ClientOptions, Error, ThreadedClient};
+use mongodb::db::ThreadedDatabase;
+
+use client::mock::MockServer;
+use std::time::Duration;
 
 #[test]
 fn validate_write_result() {
@@ -129,3 +133,62 @@ fn parse_invalid_write_error() {
     let result = WriteError::parse(doc);
     assert!(result.is_err());
 }
+
+fn write_concern_error_server() -> MockServer {
+    MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("insert") => {
+            (0, doc! {
+                "ok": 1,
+                "n": 1,
+                "writeConcernError": {
+                    "code": 64,
+                    "errmsg": "waiting for replication timed out",
+                },
+            })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    })
+}
+
+#[test]
+fn write_concern_error_returned_as_error() {
+    let server = write_concern_error_server();
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let coll = client.db("test-client-error").collection("write_concern");
+
+    match coll.insert_one(doc! { "x": 1 }, None) {
+        Err(Error::WriteConcernError(ref wc_err)) => {
+            assert_eq!(64, wc_err.code);
+            assert!(wc_err.is_timeout());
+        }
+        other => panic!("Expected a write concern error, but got {:?}", other),
+    }
+
+    match coll.insert_many(vec![doc! { "x": 1 }], None) {
+        Err(err) => {
+            let wc_err = err.write_concern_error().expect("Expected a write concern error.");
+            assert_eq!(64, wc_err.code);
+            match err {
+                Error::BulkWriteError(exc) => assert!(exc.write_concern_error.is_some()),
+                other => panic!("Expected a bulk write error, but got {:?}", other),
+            }
+        }
+        other => panic!("Expected a bulk write error, but got {:?}", other),
+    }
+}
+
+#[test]
+fn write_concern_error_attached_to_result() {
+    let server = write_concern_error_server();
+    let mut options = ClientOptions::new();
+    options.attach_write_concern_errors = true;
+
+    let client = Client::connect_with_options("127.0.0.1", server.port, options).unwrap();
+    let coll = client.db("test-client-error").collection("write_concern");
+
+    let result = coll.insert_one(doc! { "x": 1 }, None).expect("Expected the insert to succeed.");
+    assert!(result.inserted_id.is_some());
+
+    let exc = result.write_exception.expect("Expected a write exception.");
+    assert_eq!(64, exc.write_concern_error.expect("Expected a write concern error.").code);
+}
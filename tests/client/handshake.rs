@@ -1,7 +1,14 @@
 use bson::{self, Bson};
-use mongodb::{DRIVER_NAME, Client, ThreadedClient};
+use mongodb::{DRIVER_NAME, Client, ClientOptions, ThreadedClient};
 use mongodb::db::ThreadedDatabase;
 use mongodb::CommandType;
+use mongodb::connstring;
+use mongodb::pool::ConnectionPool;
+use mongodb::stream::StreamConnector;
+
+use client::mock::MockServer;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize)]
 struct Metadata {
@@ -44,3 +51,137 @@ fn metadata_sent_in_handshake() {
     assert_eq!(metadata.client.driver.name, DRIVER_NAME);
 }
 
+#[test]
+fn app_name_sent_in_handshake() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let config = connstring::parse("mongodb://i-dont-exist/?appName=myapp").unwrap();
+    let client = Client::with_config(config, None, None).unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = ConnectionPool::new(host, StreamConnector::Tcp);
+    let _stream = pool.acquire_stream(client).expect("Failed to acquire stream");
+
+    let requests = server.requests.lock().unwrap();
+    let handshake = requests[0].query.as_ref().expect("Handshake was not a query");
+    let metadata = match handshake.get("client") {
+        Some(&Bson::Document(ref metadata)) => metadata,
+        _ => panic!("no client metadata sent in handshake"),
+    };
+
+    let app_name = match metadata.get("application") {
+        Some(&Bson::Document(ref application)) => application.get_str("name").unwrap(),
+        _ => panic!("no application metadata sent in handshake"),
+    };
+    assert_eq!("myapp", app_name);
+}
+
+#[test]
+fn metadata_sent_on_each_new_socket() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let config = connstring::parse("mongodb://i-dont-exist/?appName=myapp").unwrap();
+    let client = Client::with_config(config, None, None).unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = ConnectionPool::with_size(host, StreamConnector::Tcp, 2);
+
+    let first = pool.acquire_stream(client.clone()).expect("Failed to acquire stream");
+    let second = pool.acquire_stream(client.clone()).expect("Failed to acquire stream");
+    drop(first);
+    drop(second);
+
+    // Reusing a pooled socket should not repeat the handshake.
+    let _third = pool.acquire_stream(client).expect("Failed to acquire stream");
+
+    let requests = server.requests.lock().unwrap();
+    assert_eq!(2, requests.len());
+
+    for request in requests.iter() {
+        let handshake = request.query.as_ref().expect("Handshake was not a query");
+        let metadata: ClientMetadata =
+            bson::from_bson(handshake.get("client").unwrap().clone()).unwrap();
+        assert_eq!(DRIVER_NAME, metadata.driver.name);
+        assert_eq!(env!("CARGO_PKG_VERSION"), metadata.driver.version);
+        assert_eq!(::std::env::consts::OS, metadata.os.os_type);
+        assert_eq!(::std::env::consts::ARCH, metadata.os.architecture);
+    }
+}
+
+#[test]
+fn handshake_reused_within_cache_ttl() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true, "maxWireVersion": 6 })
+    });
+
+    let config = connstring::parse("mongodb://i-dont-exist").unwrap();
+    let client = Client::with_config(config, None, None).unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = ConnectionPool::with_size(host, StreamConnector::Tcp, 3);
+    pool.set_handshake_cache_ttl(Some(Duration::from_secs(60))).unwrap();
+
+    let first = pool.acquire_stream(client.clone()).expect("Failed to acquire stream");
+    let second = pool.acquire_stream(client.clone()).expect("Failed to acquire stream");
+    assert_eq!(1, server.requests.lock().unwrap().len());
+    drop(first);
+    drop(second);
+
+    // Clearing the pool discards the cached handshake along with the connections.
+    pool.clear();
+    let _third = pool.acquire_stream(client).expect("Failed to acquire stream");
+    assert_eq!(2, server.requests.lock().unwrap().len());
+}
+
+#[test]
+fn handshake_repeated_after_cache_ttl() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let config = connstring::parse("mongodb://i-dont-exist").unwrap();
+    let client = Client::with_config(config, None, None).unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = ConnectionPool::with_size(host, StreamConnector::Tcp, 2);
+    pool.set_handshake_cache_ttl(Some(Duration::from_millis(50))).unwrap();
+
+    let _first = pool.acquire_stream(client.clone()).expect("Failed to acquire stream");
+    thread::sleep(Duration::from_millis(100));
+    let _second = pool.acquire_stream(client).expect("Failed to acquire stream");
+
+    assert_eq!(2, server.requests.lock().unwrap().len());
+}
+
+#[test]
+fn handshake_cache_ttl_is_a_client_option() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let uri = format!("mongodb://127.0.0.1:{}", server.port);
+    let options = ClientOptions::builder()
+        .handshake_cache_ttl(Duration::from_secs(60))
+        .build();
+    let client = Client::with_uri_and_options(&uri, options).unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+
+    // Wait for the monitor's own connection to complete its handshake.
+    client.db("test").command(doc! { "ping": 1 }, CommandType::Suppressed, None).unwrap();
+
+    let first = client.acquire_stream_to_host(&host).expect("Failed to acquire stream");
+    let second = client.acquire_stream_to_host(&host).expect("Failed to acquire stream");
+    drop(first);
+    drop(second);
+
+    // The monitor and the first pooled connection each handshake; the second connection
+    // reuses the pool's handshake.
+    let handshakes = server
+        .requests
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|req| req.query.as_ref().map_or(false, |query| query.contains_key("client")))
+        .count();
+    assert_eq!(2, handshakes);
+}
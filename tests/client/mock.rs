@@ -0,0 +1,348 @@
+//! A minimal in-process server used to exercise wire-level behavior without a live mongod.
+use bson::{self, Document};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "ssl")]
+use openssl::ssl::SslAcceptor;
+#[cfg(feature = "tls-rustls")]
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+#[cfg(any(feature = "ssl", feature = "tls-rustls"))]
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const OP_REPLY: i32 = 1;
+pub const OP_INSERT: i32 = 2002;
+pub const OP_QUERY: i32 = 2004;
+pub const OP_GET_MORE: i32 = 2005;
+pub const OP_KILL_CURSORS: i32 = 2007;
+
+/// A request received by the mock server.
+#[derive(Clone, Debug)]
+pub struct MockRequest {
+    pub op_code: i32,
+    pub namespace: String,
+    /// The query document for OP_QUERY requests.
+    pub query: Option<Document>,
+    /// The cursor id for OP_GET_MORE requests, or the first one killed by OP_KILL_CURSORS.
+    pub cursor_id: Option<i64>,
+    /// The number of documents requested by OP_QUERY and OP_GET_MORE requests.
+    pub number_to_return: Option<i32>,
+    /// The documents sent in OP_INSERT requests.
+    pub documents: Vec<Document>,
+}
+
+pub struct MockServer {
+    pub port: u16,
+    pub requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    /// Starts a server on an ephemeral port that waits `delay` before answering each request
+    /// with the reply document produced by `respond`.
+    pub fn start<F>(delay: Duration, respond: F) -> MockServer
+    where
+        F: Fn(&MockRequest) -> (i64, Document) + Send + Sync + 'static,
+    {
+        MockServer::start_with_batches(delay, move |req| {
+            let (cursor_id, reply) = respond(req);
+            (cursor_id, vec![reply])
+        })
+    }
+
+    /// Starts a server like `start`, but answers each request with a reply containing every
+    /// document produced by `respond`, which may be none.
+    pub fn start_with_batches<F>(delay: Duration, respond: F) -> MockServer
+    where
+        F: Fn(&MockRequest) -> (i64, Vec<Document>) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond = Arc::new(respond);
+
+        let thread_requests = requests.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+
+            let requests = thread_requests.clone();
+            let respond = respond.clone();
+            thread::spawn(move || serve(stream, delay, requests, respond));
+        });
+
+        MockServer {
+            port: port,
+            requests: requests,
+        }
+    }
+
+    #[cfg(feature = "ssl")]
+    /// Starts a server like `start`, but encrypting each connection with `acceptor` after
+    /// waiting `tls_delay` to answer the client's TLS handshake.
+    pub fn start_tls<F>(acceptor: SslAcceptor, tls_delay: Duration, respond: F) -> MockServer
+    where
+        F: Fn(&MockRequest) -> (i64, Document) + Send + Sync + 'static,
+    {
+        MockServer::start_encrypted(
+            move |stream| {
+                thread::sleep(tls_delay);
+                acceptor.accept(stream).ok()
+            },
+            respond,
+        )
+    }
+
+    #[cfg(feature = "tls-rustls")]
+    /// Starts a server like `start`, but encrypting each connection with rustls.
+    pub fn start_rustls<F>(config: Arc<ServerConfig>, respond: F) -> MockServer
+    where
+        F: Fn(&MockRequest) -> (i64, Document) + Send + Sync + 'static,
+    {
+        MockServer::start_encrypted(
+            move |stream| {
+                let connection = ServerConnection::new(config.clone()).ok()?;
+                let mut stream = StreamOwned::new(connection, stream);
+                while stream.conn.is_handshaking() {
+                    stream.conn.complete_io(&mut stream.sock).ok()?;
+                }
+                Some(stream)
+            },
+            respond,
+        )
+    }
+
+    // Starts a server like `start`, serving each connection over the stream returned by
+    // `accept`, or closing it if `accept` fails.
+    #[cfg(any(feature = "ssl", feature = "tls-rustls"))]
+    fn start_encrypted<A, S, F>(accept: A, respond: F) -> MockServer
+    where
+        A: Fn(TcpStream) -> Option<S> + Send + Sync + 'static,
+        S: Read + Write,
+        F: Fn(&MockRequest) -> (i64, Document) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let accept = Arc::new(accept);
+        let respond = Arc::new(move |req: &MockRequest| {
+            let (cursor_id, reply) = respond(req);
+            (cursor_id, vec![reply])
+        });
+
+        let thread_requests = requests.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+
+            let requests = thread_requests.clone();
+            let respond = respond.clone();
+            let accept = accept.clone();
+            thread::spawn(move || if let Some(stream) = accept(stream) {
+                serve(stream, Duration::from_millis(0), requests, respond);
+            });
+        });
+
+        MockServer {
+            port: port,
+            requests: requests,
+        }
+    }
+
+    #[cfg(unix)]
+    /// Starts a server like `start`, but listening on a Unix domain socket at `path` instead of
+    /// a TCP port. The returned server's `port` is 0.
+    pub fn start_unix<P, F>(path: P, delay: Duration, respond: F) -> MockServer
+    where
+        P: AsRef<Path>,
+        F: Fn(&MockRequest) -> (i64, Document) + Send + Sync + 'static,
+    {
+        let listener = UnixListener::bind(path).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond = Arc::new(move |req: &MockRequest| {
+            let (cursor_id, reply) = respond(req);
+            (cursor_id, vec![reply])
+        });
+
+        let thread_requests = requests.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+
+            let requests = thread_requests.clone();
+            let respond = respond.clone();
+            thread::spawn(move || serve(stream, delay, requests, respond));
+        });
+
+        MockServer {
+            port: 0,
+            requests: requests,
+        }
+    }
+
+    /// Returns the requests received so far, excluding server monitoring heartbeats.
+    pub fn received(&self) -> Vec<MockRequest> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|req| match req.query {
+                Some(ref query) => !query.contains_key("isMaster"),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns every request received so far, including server monitoring heartbeats.
+    pub fn received_all(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn serve<S, F>(
+    stream: S,
+    delay: Duration,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    respond: Arc<F>,
+) where
+    S: Read + Write,
+    F: Fn(&MockRequest) -> (i64, Vec<Document>),
+{
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let length = match reader.read_i32::<LittleEndian>() {
+            Ok(length) => length,
+            Err(_) => return,
+        };
+        let request_id = reader.read_i32::<LittleEndian>().unwrap();
+        let _ = reader.read_i32::<LittleEndian>().unwrap();
+        let op_code = reader.read_i32::<LittleEndian>().unwrap();
+
+        let mut body = vec![0u8; length as usize - 16];
+        reader.read_exact(&mut body).unwrap();
+        let request = parse_request(op_code, &body);
+
+        requests.lock().unwrap().push(request.clone());
+
+        // Only queries and getMores are answered; the server never replies to legacy writes.
+        if op_code != OP_QUERY && op_code != OP_GET_MORE {
+            continue;
+        }
+
+        thread::sleep(delay);
+
+        let (cursor_id, replies) = respond(&request);
+        let mut doc_bytes = Vec::new();
+        for reply in &replies {
+            bson::encode_document(&mut doc_bytes, reply).unwrap();
+        }
+
+        let mut message = Vec::new();
+        message
+            .write_i32::<LittleEndian>(36 + doc_bytes.len() as i32)
+            .unwrap();
+        message.write_i32::<LittleEndian>(0).unwrap();
+        message.write_i32::<LittleEndian>(request_id).unwrap();
+        message.write_i32::<LittleEndian>(OP_REPLY).unwrap();
+        message.write_i32::<LittleEndian>(0).unwrap();
+        message.write_i64::<LittleEndian>(cursor_id).unwrap();
+        message.write_i32::<LittleEndian>(0).unwrap();
+        message
+            .write_i32::<LittleEndian>(replies.len() as i32)
+            .unwrap();
+        message.extend(doc_bytes);
+
+        if reader.get_mut().write_all(&message).is_err() {
+            return;
+        }
+    }
+}
+
+fn parse_request(op_code: i32, body: &[u8]) -> MockRequest {
+    let mut cursor = body;
+
+    // Unlike the other ops, OP_KILL_CURSORS has no namespace.
+    if op_code == OP_KILL_CURSORS {
+        let _ = cursor.read_i32::<LittleEndian>().unwrap();
+        let _ = cursor.read_i32::<LittleEndian>().unwrap();
+        return MockRequest {
+            op_code: op_code,
+            namespace: String::new(),
+            query: None,
+            cursor_id: Some(cursor.read_i64::<LittleEndian>().unwrap()),
+            number_to_return: None,
+            documents: Vec::new(),
+        };
+    }
+
+    let mut namespace = Vec::new();
+
+    let _ = cursor.read_i32::<LittleEndian>().unwrap();
+    cursor.read_until(0, &mut namespace).unwrap();
+    namespace.pop();
+    let namespace = String::from_utf8(namespace).unwrap();
+
+    match op_code {
+        OP_QUERY => {
+            let _ = cursor.read_i32::<LittleEndian>().unwrap();
+            let number_to_return = cursor.read_i32::<LittleEndian>().unwrap();
+            MockRequest {
+                op_code: op_code,
+                namespace: namespace,
+                query: Some(bson::decode_document(&mut cursor).unwrap()),
+                cursor_id: None,
+                number_to_return: Some(number_to_return),
+                documents: Vec::new(),
+            }
+        }
+        OP_GET_MORE => {
+            let number_to_return = cursor.read_i32::<LittleEndian>().unwrap();
+            MockRequest {
+                op_code: op_code,
+                namespace: namespace,
+                query: None,
+                cursor_id: Some(cursor.read_i64::<LittleEndian>().unwrap()),
+                number_to_return: Some(number_to_return),
+                documents: Vec::new(),
+            }
+        }
+        OP_INSERT => {
+            let mut documents = Vec::new();
+            while !cursor.is_empty() {
+                documents.push(bson::decode_document(&mut cursor).unwrap());
+            }
+
+            MockRequest {
+                op_code: op_code,
+                namespace: namespace,
+                query: None,
+                cursor_id: None,
+                number_to_return: None,
+                documents: documents,
+            }
+        }
+        _ => MockRequest {
+            op_code: op_code,
+            namespace: namespace,
+            query: None,
+            cursor_id: None,
+            number_to_return: None,
+            documents: Vec::new(),
+        },
+    }
+}
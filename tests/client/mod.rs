@@ -1,6 +1,7 @@
 mod batch_size;
 mod bulk;
 mod coll;
+mod common;
 mod connstring;
 mod crud_spec;
 mod db;
@@ -8,6 +9,10 @@ mod cursor;
 mod error;
 mod gridfs;
 mod handshake;
+pub mod mock;
+mod pool;
+mod session;
+mod topology;
 mod wire_protocol;
 
 use bson;
@@ -0,0 +1,241 @@
+use mongodb::{Client, ThreadedClient};
+use mongodb::connstring::{self, ConnectionString};
+use mongodb::pool::ConnectionPool;
+use mongodb::stream::StreamConnector;
+
+use client::mock::{MockRequest, MockServer};
+use std::env;
+use std::fs;
+use std::process;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn connection_metrics_reflect_handshake_latency() {
+    let server = MockServer::start(Duration::from_millis(200), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = ConnectionPool::new(host, StreamConnector::Tcp);
+
+    assert!(pool.last_connection_metrics().is_none());
+    let _stream = pool.acquire_stream(client).expect("Failed to acquire stream");
+
+    let metrics = pool.last_connection_metrics().expect("No connection metrics recorded");
+    assert!(metrics.handshake >= Duration::from_millis(200));
+    assert!(metrics.tcp_connect < Duration::from_millis(200));
+    assert_eq!(None, metrics.tls_handshake);
+    assert!(metrics.total() >= metrics.handshake);
+}
+
+#[test]
+fn connect_timeout_bounds_new_connections() {
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+
+    // A non-routable address, so the TCP handshake is never answered.
+    let host = connstring::parse_host("10.255.255.1:27017").unwrap();
+    let pool = ConnectionPool::new(host, StreamConnector::Tcp);
+    pool.set_connect_timeout(Some(Duration::from_millis(100))).unwrap();
+
+    let start = Instant::now();
+    assert!(pool.acquire_stream(client).is_err());
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn maintenance_reaps_idle_connections_and_restores_min_size() {
+    let handshakes = Arc::new(AtomicUsize::new(0));
+    let counter = handshakes.clone();
+    let server = MockServer::start(Duration::from_millis(0), move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = ConnectionPool::new(host, StreamConnector::Tcp);
+    pool.set_min_size(1).unwrap();
+    pool.set_max_idle_time(Some(Duration::from_millis(100))).unwrap();
+    pool.start_maintenance(&client, Duration::from_millis(20));
+
+    // The minimum connection is established, and once idle for too long, it is reaped and
+    // replaced.
+    assert!(wait_until(|| handshakes.load(Ordering::SeqCst) >= 1));
+    assert!(wait_until(|| handshakes.load(Ordering::SeqCst) >= 2));
+
+    // Without a minimum size, idle connections are only reaped, so handshakes stop once the
+    // last connection has been idle for longer than the max idle time.
+    pool.set_min_size(0).unwrap();
+    let mut settled = handshakes.load(Ordering::SeqCst);
+    assert!(wait_until(|| {
+        thread::sleep(Duration::from_millis(250));
+        let current = handshakes.load(Ordering::SeqCst);
+        let unchanged = current == settled;
+        settled = current;
+        unchanged
+    }));
+    assert_eq!(0, pool.reap_idle());
+}
+
+// Polls the condition until it holds, returning false if it still fails after five seconds.
+fn wait_until<F: FnMut() -> bool>(mut condition: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+#[test]
+fn slow_connections_do_not_block_the_pool() {
+    let server = MockServer::start(Duration::from_millis(300), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = Arc::new(ConnectionPool::with_size(host, StreamConnector::Tcp, 2));
+    let stream = pool.acquire_stream(client.clone()).expect("Failed to acquire stream");
+
+    // Another thread opens a second connection, whose handshake takes a while.
+    let opener = {
+        let pool = pool.clone();
+        let client = client.clone();
+        thread::spawn(move || pool.acquire_stream(client).map(|_| ()))
+    };
+    assert!(wait_until(|| server.requests.lock().unwrap().len() >= 2));
+
+    // Meanwhile, the idle connection can be returned and taken again right away.
+    let start = Instant::now();
+    drop(stream);
+    let _stream = pool.acquire_stream(client).expect("Failed to acquire stream");
+    assert!(start.elapsed() < Duration::from_millis(200));
+
+    opener.join().unwrap().expect("Failed to open the second connection");
+}
+
+#[test]
+fn maintenance_pings_idle_connections() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = ConnectionPool::new(host, StreamConnector::Tcp);
+
+    // Without a keep-alive interval, idle connections are left alone.
+    drop(pool.acquire_stream(client.clone()).expect("Failed to acquire stream"));
+    let idle_since = Instant::now();
+    assert_eq!(0, pool.keep_alive_idle(client.clone()));
+
+    pool.set_keep_alive_interval(Some(Duration::from_millis(100))).unwrap();
+    pool.start_maintenance(&client, Duration::from_millis(20));
+
+    // The connection is only pinged once it has been idle for the keep-alive interval.
+    assert!(wait_until(|| !keep_alive_pings(&server).is_empty()));
+    assert!(idle_since.elapsed() >= Duration::from_millis(100));
+
+    for req in keep_alive_pings(&server) {
+        assert_eq!(Some(doc! { "isMaster": 1 }), req.query);
+    }
+    assert!(server.received().is_empty());
+}
+
+// Returns the keep-alive pings a mock server received. Unlike handshakes and heartbeats, which
+// run isMaster against the local database, pings are sent to the admin database.
+fn keep_alive_pings(server: &MockServer) -> Vec<MockRequest> {
+    server
+        .received_all()
+        .into_iter()
+        .filter(|req| req.namespace == "admin.$cmd")
+        .collect()
+}
+
+#[test]
+fn failed_ping_wakes_threads_waiting_for_a_connection() {
+    // Keep-alive pings are answered slowly and without a reply document, so they fail.
+    let server = MockServer::start_with_batches(Duration::from_millis(0), |req| {
+        match req.query {
+            Some(_) if req.namespace == "admin.$cmd" => {
+                thread::sleep(Duration::from_millis(200));
+                (0, Vec::new())
+            }
+            _ => (0, vec![doc! { "ok": 1, "ismaster": true }]),
+        }
+    });
+
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = Arc::new(ConnectionPool::with_size(host, StreamConnector::Tcp, 1));
+    drop(pool.acquire_stream(client.clone()).expect("Failed to acquire stream"));
+
+    pool.set_keep_alive_interval(Some(Duration::from_millis(1))).unwrap();
+    thread::sleep(Duration::from_millis(5));
+    let pinger = {
+        let pool = pool.clone();
+        let client = client.clone();
+        thread::spawn(move || pool.keep_alive_idle(client))
+    };
+    assert!(wait_until(|| !keep_alive_pings(&server).is_empty()));
+
+    // The only connection is being pinged, so this waits until the failed ping frees its slot.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(pool.acquire_stream(client).is_ok());
+    });
+
+    assert_eq!(Ok(true), receiver.recv_timeout(Duration::from_secs(5)));
+    assert_eq!(1, pinger.join().unwrap());
+}
+
+#[test]
+fn tcp_stream_is_not_tls() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = ConnectionPool::new(host, StreamConnector::Tcp);
+
+    let mut stream = pool.acquire_stream(client).expect("Failed to acquire stream");
+    assert!(!stream.is_tls());
+    assert!(!stream.get_socket().get_ref().is_tls());
+}
+
+#[cfg(unix)]
+#[test]
+fn connect_over_unix_domain_socket() {
+    let path = env::temp_dir().join(format!("mongodb-rust-{}.sock", process::id()));
+    let _ = fs::remove_file(&path);
+
+    let server = MockServer::start_unix(&path, Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let encoded = path.to_str().unwrap().replace("/", "%2F");
+    let client = Client::with_uri(&format!("mongodb://{}", encoded)).unwrap();
+    assert!(client.is_master().unwrap());
+
+    let mut stream = client.acquire_write_stream().unwrap();
+    assert!(!stream.is_tls());
+    assert!(stream.get_socket().get_ref().peer_addr().is_ok());
+
+    drop(server);
+    let _ = fs::remove_file(&path);
+}
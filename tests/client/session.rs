@@ -0,0 +1,191 @@
+use bson::{Bson, Document};
+use mongodb::{Client, CommandType, ThreadedClient};
+use mongodb::common::Timestamp;
+use mongodb::db::ThreadedDatabase;
+
+use client::mock::MockServer;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+fn cluster_time(seconds: u32) -> Document {
+    doc! {
+        "clusterTime": Bson::TimeStamp(Timestamp::new(seconds, 1).to_raw()),
+        "signature": { "keyId": 0i64 },
+    }
+}
+
+#[test]
+fn session_keeps_greatest_cluster_time() {
+    let client = Client::connect("localhost", 27017).unwrap();
+    let mut session = client.start_session();
+    assert!(session.cluster_time().is_none());
+    assert!(session.operation_time().is_none());
+
+    session.update_from_reply(&doc! {
+        "ok": 1,
+        "$clusterTime": cluster_time(5),
+        "operationTime": Bson::TimeStamp(Timestamp::new(5, 1).to_raw()),
+    });
+    session.update_from_reply(&doc! { "ok": 1, "$clusterTime": cluster_time(3) });
+
+    assert_eq!(Some(&cluster_time(5)), session.cluster_time());
+    assert_eq!(Some(Timestamp::new(5, 1)), session.operation_time());
+
+    let mut cmd = doc! { "ping": 1 };
+    session.apply_to(&mut cmd);
+    assert_eq!(Some(&Bson::Document(session.id().clone())), cmd.get("lsid"));
+    assert_eq!(Some(&Bson::Document(cluster_time(5))), cmd.get("$clusterTime"));
+
+    let other = client.start_session();
+    assert!(session.id() != other.id());
+}
+
+#[test]
+fn session_gossips_cluster_time_from_replies() {
+    let server = MockServer::start(Duration::from_millis(0), |req| match req.query {
+        Some(ref query) if query.contains_key("ping") => {
+            (0, doc! { "ok": 1, "$clusterTime": cluster_time(5) })
+        }
+        Some(ref query) if query.contains_key("buildInfo") => {
+            (0, doc! { "ok": 1, "$clusterTime": cluster_time(3) })
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let db = client.db("admin");
+    let mut session = client.start_session();
+
+    db.command_with_session(doc! { "ping": 1 }, CommandType::Suppressed, None, &mut session)
+        .unwrap();
+    db.command_with_session(doc! { "buildInfo": 1 }, CommandType::Suppressed, None, &mut session)
+        .unwrap();
+    db.command_with_session(doc! { "ping": 1 }, CommandType::Suppressed, None, &mut session)
+        .unwrap();
+
+    let requests = server.received();
+    assert_eq!(3, requests.len());
+    let queries: Vec<_> = requests.iter().map(|req| req.query.clone().unwrap()).collect();
+    for query in &queries {
+        assert_eq!(Some(&Bson::Document(session.id().clone())), query.get("lsid"));
+    }
+    assert!(!queries[0].contains_key("$clusterTime"));
+    assert_eq!(Some(&Bson::Document(cluster_time(5))), queries[1].get("$clusterTime"));
+    assert_eq!(Some(&Bson::Document(cluster_time(5))), queries[2].get("$clusterTime"));
+}
+
+#[test]
+fn transaction_statements_carry_transaction_fields() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true, "n": 1 })
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let db = client.db("bank");
+    let mut session = client.start_session();
+
+    session.start_transaction(None).unwrap();
+    assert!(session.in_transaction());
+    assert!(session.start_transaction(None).is_err());
+
+    for coll in &["accounts", "ledger"] {
+        let cmd = doc! { "insert": *coll, "documents": [{ "amount": 10 }] };
+        db.command_with_session(cmd, CommandType::InsertOne, None, &mut session).unwrap();
+    }
+    session.commit_transaction().unwrap();
+    assert!(!session.in_transaction());
+    assert!(session.abort_transaction().is_err());
+
+    let requests = server.received();
+    assert_eq!(3, requests.len());
+    let queries: Vec<_> = requests.iter().map(|req| req.query.clone().unwrap()).collect();
+
+    assert_eq!("bank.$cmd", requests[0].namespace);
+    assert_eq!(Some(&Bson::I64(1)), queries[0].get("txnNumber"));
+    assert_eq!(Some(&Bson::Boolean(true)), queries[0].get("startTransaction"));
+    assert_eq!(Some(&Bson::Boolean(false)), queries[0].get("autocommit"));
+
+    assert_eq!(Some(&Bson::I64(1)), queries[1].get("txnNumber"));
+    assert!(!queries[1].contains_key("startTransaction"));
+    assert_eq!(Some(&Bson::Boolean(false)), queries[1].get("autocommit"));
+
+    assert_eq!("admin.$cmd", requests[2].namespace);
+    assert!(queries[2].contains_key("commitTransaction"));
+    assert_eq!(Some(&Bson::I64(1)), queries[2].get("txnNumber"));
+    assert_eq!(Some(&Bson::Document(session.id().clone())), queries[2].get("lsid"));
+
+    // The next transaction uses a new transaction number.
+    session.start_transaction(None).unwrap();
+    let mut cmd = doc! { "insert": "accounts" };
+    session.apply_to(&mut cmd);
+    assert_eq!(Some(&Bson::I64(2)), cmd.get("txnNumber"));
+}
+
+#[test]
+fn abort_transaction_rolls_back_started_transactions() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let db = client.db("bank");
+    let mut session = client.start_session();
+
+    // A transaction without statements is aborted without contacting the server.
+    session.start_transaction(None).unwrap();
+    session.abort_transaction().unwrap();
+    assert!(server.received().is_empty());
+    assert!(session.commit_transaction().is_err());
+
+    session.start_transaction(None).unwrap();
+    let cmd = doc! { "insert": "accounts" };
+    db.command_with_session(cmd, CommandType::InsertOne, None, &mut session).unwrap();
+    session.abort_transaction().unwrap();
+    assert!(session.abort_transaction().is_err());
+
+    let requests = server.received();
+    assert_eq!(2, requests.len());
+    let abort = requests[1].query.clone().unwrap();
+    assert_eq!("admin.$cmd", requests[1].namespace);
+    assert!(abort.contains_key("abortTransaction"));
+    assert_eq!(Some(&Bson::I64(2)), abort.get("txnNumber"));
+}
+
+#[test]
+fn commit_transaction_retries_unknown_commit_result() {
+    let commits = Arc::new(AtomicUsize::new(0));
+    let counter = commits.clone();
+    let server = MockServer::start(Duration::from_millis(0), move |req| match req.query {
+        Some(ref query) if query.contains_key("commitTransaction") => {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                (0, doc! {
+                    "ok": 1,
+                    "writeConcernError": { "code": 64, "errmsg": "waiting for replication" },
+                    "errorLabels": ["UnknownTransactionCommitResult"],
+                })
+            } else {
+                (0, doc! { "ok": 1 })
+            }
+        }
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let db = client.db("bank");
+    let mut session = client.start_session();
+
+    session.start_transaction(None).unwrap();
+    let cmd = doc! { "insert": "accounts" };
+    db.command_with_session(cmd, CommandType::InsertOne, None, &mut session).unwrap();
+    session.commit_transaction().unwrap();
+    assert_eq!(2, commits.load(Ordering::SeqCst));
+
+    let requests = server.received();
+    let retry = requests.last().unwrap().query.clone().unwrap();
+    assert_eq!(
+        Some(&Bson::Document(doc! { "w": "majority", "wtimeout": 10000 })),
+        retry.get("writeConcern")
+    );
+    assert_eq!(Some(&Bson::I64(1)), retry.get("txnNumber"));
+}
@@ -0,0 +1,749 @@
+use bson::Bson;
+use chrono::{Duration as ChronoDuration, Utc};
+
+use mongodb::{Client, ClientOptions, CommandType, Error, ThreadedClient};
+use mongodb::db::ThreadedDatabase;
+use mongodb::common::{ReadMode, ReadPreference, WriteConcern};
+use mongodb::connstring::{self, ConnectionString, Host};
+use mongodb::stream::StreamConnector;
+use mongodb::topology::{Topology, TopologyDescription, TopologyType};
+use mongodb::topology::error::RejectionReason;
+use mongodb::topology::monitor::IsMasterResult;
+use mongodb::topology::server::{Server, ServerType};
+
+use client::mock::MockServer;
+use std::collections::BTreeMap;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Returns a local host that refuses connections.
+fn unreachable_host() -> Host {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    connstring::parse_host(&format!("127.0.0.1:{}", port)).unwrap()
+}
+
+fn mongos_topology(hosts: &[(Host, i64)]) -> TopologyDescription {
+    let dummy_client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let dummy_top_arc = Arc::new(RwLock::new(
+        TopologyDescription::new(StreamConnector::default()),
+    ));
+
+    let mut topology_description = TopologyDescription::new(StreamConnector::default());
+    topology_description.topology_type = TopologyType::Sharded;
+    topology_description.local_threshold_ms = 1000;
+
+    for &(ref host, rtt) in hosts {
+        let server = Server::new(
+            dummy_client.clone(),
+            host.clone(),
+            dummy_top_arc.clone(),
+            false,
+            StreamConnector::default(),
+        );
+
+        {
+            let mut description = server.description.write().unwrap();
+            description.round_trip_time = Some(rtt);
+            description.server_type = ServerType::Mongos;
+        }
+
+        topology_description.servers.insert(host.clone(), server);
+    }
+
+    topology_description
+}
+
+#[test]
+fn unreachable_seed_is_skipped() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true, "msg": "isdbgrid" })
+    });
+    let reachable = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let unreachable = unreachable_host();
+
+    let topology_description = mongos_topology(&[(unreachable, 1), (reachable, 10)]);
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+
+    let read_pref = ReadPreference::new(ReadMode::Primary, None);
+    assert!(topology_description.acquire_stream(client, &read_pref).is_ok());
+}
+
+#[test]
+fn all_failures_reported_when_no_seed_is_reachable() {
+    let first = unreachable_host();
+    let second = unreachable_host();
+
+    let topology_description = mongos_topology(&[(first.clone(), 1), (second.clone(), 10)]);
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+
+    let read_pref = ReadPreference::new(ReadMode::Primary, None);
+    let err = match topology_description.acquire_stream(client, &read_pref) {
+        Ok(_) => panic!("Expected server selection to fail."),
+        Err(err) => err.to_string(),
+    };
+
+    assert!(err.contains(&format!("127.0.0.1:{}", first.port)));
+    assert!(err.contains(&format!("127.0.0.1:{}", second.port)));
+}
+
+// Builds a replica set description from (host, server type, rtt, replication lag in seconds,
+// data center tag) entries. Each member is populated from an isMaster reply reporting a last
+// write `lag` seconds ago.
+fn replica_set_topology(members: &[(Host, ServerType, i64, i64, &str)]) -> TopologyDescription {
+    let dummy_client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let dummy_top_arc = Arc::new(RwLock::new(
+        TopologyDescription::new(StreamConnector::default()),
+    ));
+
+    let mut topology_description = TopologyDescription::new(StreamConnector::default());
+    let has_primary = members.iter().any(|member| member.1 == ServerType::RSPrimary);
+    topology_description.topology_type = if has_primary {
+        TopologyType::ReplicaSetWithPrimary
+    } else {
+        TopologyType::ReplicaSetNoPrimary
+    };
+    let now = Utc::now();
+
+    for &(ref host, server_type, rtt, lag, dc) in members {
+        let server = Server::new(
+            dummy_client.clone(),
+            host.clone(),
+            dummy_top_arc.clone(),
+            false,
+            StreamConnector::default(),
+        );
+
+        let ismaster = IsMasterResult::new(doc! {
+            "ok": 1,
+            "ismaster": server_type == ServerType::RSPrimary,
+            "secondary": server_type == ServerType::RSSecondary,
+            "setName": "rs",
+            "maxWireVersion": 6,
+            "tags": { "dc": dc },
+            "lastWrite": { "lastWriteDate": now - ChronoDuration::seconds(lag) },
+        }).unwrap();
+
+        {
+            let mut description = server.description.write().unwrap();
+            description.update(ismaster, rtt);
+            description.server_type = server_type;
+        }
+
+        topology_description.servers.insert(host.clone(), server);
+    }
+
+    topology_description
+}
+
+#[test]
+fn select_server_by_read_preference() {
+    let primary = connstring::parse_host("a:27017").unwrap();
+    let fresh = connstring::parse_host("b:27017").unwrap();
+    let stale = connstring::parse_host("c:27017").unwrap();
+    let remote = connstring::parse_host("d:27017").unwrap();
+
+    let topology_description = replica_set_topology(&[
+        (primary.clone(), ServerType::RSPrimary, 20, 0, "ny"),
+        (fresh.clone(), ServerType::RSSecondary, 10, 0, "ny"),
+        (stale.clone(), ServerType::RSSecondary, 5, 300, "ny"),
+        (remote.clone(), ServerType::RSSecondary, 12, 0, "sf"),
+    ]);
+
+    let select = |mode, dc: Option<&str>, max_staleness_seconds| {
+        let tag_sets = dc.map(|dc| {
+            let mut tag_set = BTreeMap::new();
+            tag_set.insert(String::from("dc"), String::from(dc));
+            vec![tag_set]
+        });
+        let mut read_pref = ReadPreference::new(mode, tag_sets);
+        read_pref.max_staleness_seconds = max_staleness_seconds;
+        topology_description.select_server(&read_pref).unwrap()
+    };
+
+    assert_eq!(primary, select(ReadMode::Primary, None, None));
+    assert_eq!(stale, select(ReadMode::Nearest, None, None));
+    assert_eq!(fresh, select(ReadMode::Nearest, None, Some(90)));
+    assert_eq!(fresh, select(ReadMode::Secondary, Some("ny"), Some(90)));
+    assert_eq!(remote, select(ReadMode::SecondaryPreferred, Some("sf"), None));
+    assert_eq!(primary, select(ReadMode::SecondaryPreferred, Some("la"), None));
+}
+
+#[test]
+fn max_staleness_requires_wire_version_5() {
+    let primary = connstring::parse_host("a:27017").unwrap();
+    let secondary = connstring::parse_host("b:27017").unwrap();
+
+    let topology_description = replica_set_topology(&[
+        (primary.clone(), ServerType::RSPrimary, 20, 0, "ny"),
+        (secondary.clone(), ServerType::RSSecondary, 10, 0, "ny"),
+    ]);
+    topology_description.servers[&secondary].description.write().unwrap().max_wire_version = 4;
+
+    // Read preferences without a max staleness are unaffected.
+    let read_pref = ReadPreference::new(ReadMode::Nearest, None);
+    assert!(topology_description.select_server(&read_pref).is_ok());
+
+    let mut read_pref = ReadPreference::new(ReadMode::Nearest, None);
+    read_pref.max_staleness_seconds = Some(90);
+    match topology_description.select_server(&read_pref) {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("b:27017")),
+        other => panic!("Expected an argument error, got {:?}", other),
+    }
+}
+
+#[test]
+fn unsupported_max_staleness_fails_selection_immediately() {
+    let primary = connstring::parse_host("a:27017").unwrap();
+    let secondary = connstring::parse_host("b:27017").unwrap();
+
+    let mut topology_description = replica_set_topology(&[
+        (primary.clone(), ServerType::RSPrimary, 20, 0, "ny"),
+        (secondary.clone(), ServerType::RSSecondary, 10, 0, "ny"),
+    ]);
+    topology_description.servers[&secondary].description.write().unwrap().max_wire_version = 4;
+    topology_description.server_selection_timeout_ms = 5000;
+
+    let topology = Topology::new(
+        ConnectionString::new("a", 27017),
+        Some(topology_description),
+        StreamConnector::default(),
+    ).unwrap();
+
+    let mut read_pref = ReadPreference::new(ReadMode::Nearest, None);
+    read_pref.max_staleness_seconds = Some(90);
+
+    // Waiting for the topology to change cannot make the read preference valid, so the
+    // error is returned without retrying until the selection timeout.
+    let start = Instant::now();
+    match topology.select_server(&read_pref) {
+        Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("b:27017")),
+        other => panic!("Expected an argument error, got {:?}", other),
+    }
+    assert!(start.elapsed() < Duration::from_millis(1000));
+}
+
+#[test]
+fn staleness_estimated_from_last_write() {
+    let primary = connstring::parse_host("a:27017").unwrap();
+    let near = connstring::parse_host("b:27017").unwrap();
+    let far = connstring::parse_host("c:27017").unwrap();
+
+    let filter = |description: &TopologyDescription, max_staleness_seconds| {
+        let mut read_pref = ReadPreference::new(ReadMode::Nearest, None);
+        read_pref.max_staleness_seconds = Some(max_staleness_seconds);
+
+        let mut hosts: Vec<Host> = description.servers.keys().cloned().collect();
+        description.filter_stale_hosts(&mut hosts, &read_pref);
+        hosts.sort_by_key(|host| host.host_name.clone());
+        hosts
+    };
+
+    // With a primary, lag is measured against the primary's last write, plus the
+    // heartbeat frequency (10 seconds by default).
+    let with_primary = replica_set_topology(&[
+        (primary.clone(), ServerType::RSPrimary, 10, 0, "ny"),
+        (near.clone(), ServerType::RSSecondary, 10, 50, "ny"),
+        (far.clone(), ServerType::RSSecondary, 10, 200, "ny"),
+    ]);
+    assert_eq!(vec![primary.clone(), near.clone()], filter(&with_primary, 90));
+    assert_eq!(
+        vec![primary.clone(), near.clone(), far.clone()],
+        filter(&with_primary, 250)
+    );
+
+    // Without a primary, lag is measured against the most recent secondary write.
+    let without_primary = replica_set_topology(&[
+        (primary.clone(), ServerType::RSSecondary, 10, 0, "ny"),
+        (near.clone(), ServerType::RSSecondary, 10, 70, "ny"),
+        (far.clone(), ServerType::RSSecondary, 10, 120, "ny"),
+    ]);
+    assert_eq!(vec![primary, near], filter(&without_primary, 90));
+}
+
+#[test]
+fn selection_timeout_lists_rejected_candidates() {
+    let primary = connstring::parse_host("a:27017").unwrap();
+    let stale = connstring::parse_host("b:27017").unwrap();
+    let remote = connstring::parse_host("c:27017").unwrap();
+    let unknown = connstring::parse_host("d:27017").unwrap();
+
+    let mut topology_description = replica_set_topology(&[
+        (primary.clone(), ServerType::RSPrimary, 10, 0, "ny"),
+        (stale.clone(), ServerType::RSSecondary, 10, 300, "ny"),
+        (remote.clone(), ServerType::RSSecondary, 10, 0, "sf"),
+        (unknown.clone(), ServerType::Unknown, 10, 0, "ny"),
+    ]);
+    topology_description.server_selection_timeout_ms = 0;
+
+    let topology = Topology::new(
+        ConnectionString::new("a", 27017),
+        Some(topology_description),
+        StreamConnector::default(),
+    ).unwrap();
+
+    let mut tag_set = BTreeMap::new();
+    tag_set.insert(String::from("dc"), String::from("ny"));
+    let mut read_pref = ReadPreference::new(ReadMode::Secondary, Some(vec![tag_set]));
+    read_pref.max_staleness_seconds = Some(90);
+
+    let err = match topology.select_server(&read_pref) {
+        Err(Error::ServerSelectionError(err)) => err,
+        other => panic!("Expected a server selection error, got {:?}", other),
+    };
+
+    assert_eq!(Some(read_pref), err.read_preference);
+    assert_eq!(4, err.candidates.len());
+    assert_eq!((primary, RejectionReason::WrongType(ServerType::RSPrimary)), err.candidates[0]);
+    match err.candidates[1] {
+        (ref host, RejectionReason::Stale { staleness_ms, max_staleness_ms }) => {
+            assert_eq!(&stale, host);
+            assert!(staleness_ms > max_staleness_ms);
+            assert_eq!(90000, max_staleness_ms);
+        }
+        ref other => panic!("Expected a stale candidate, got {:?}", other),
+    }
+    assert_eq!((remote, RejectionReason::TagMismatch), err.candidates[2]);
+    assert_eq!((unknown, RejectionReason::Unknown(None)), err.candidates[3]);
+
+    let message = err.to_string();
+    assert!(message.contains("a:27017 (wrong server type RSPrimary)"));
+    assert!(message.contains("b:27017 (stale"));
+    assert!(message.contains("c:27017 (tag mismatch)"));
+    assert!(message.contains("d:27017 (unknown"));
+}
+
+// Starts a mock replica set member that waits until the member list is known before
+// answering isMaster, and acknowledges all other commands.
+fn replica_set_member(primary: bool, members: Arc<Mutex<Vec<String>>>) -> MockServer {
+    delayed_replica_set_member(primary, Duration::from_millis(0), members)
+}
+
+// Starts a mock replica set member that waits `delay` before answering each request.
+fn delayed_replica_set_member(
+    primary: bool,
+    delay: Duration,
+    members: Arc<Mutex<Vec<String>>>,
+) -> MockServer {
+    MockServer::start(delay, move |req| match req.query {
+        Some(ref query) if query.contains_key("isMaster") => {
+            while members.lock().unwrap().is_empty() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            let hosts: Vec<Bson> = members
+                .lock()
+                .unwrap()
+                .iter()
+                .cloned()
+                .map(Bson::String)
+                .collect();
+
+            (0, doc! {
+                "ok": 1,
+                "ismaster": primary,
+                "secondary": !primary,
+                "setName": "rs",
+                "hosts": hosts,
+            })
+        }
+        _ => (0, doc! { "ok": 1 }),
+    })
+}
+
+fn replica_set_client(servers: &[&MockServer], members: &Arc<Mutex<Vec<String>>>) -> Client {
+    let hosts: Vec<_> = servers
+        .iter()
+        .map(|server| format!("127.0.0.1:{}", server.port))
+        .collect();
+    members.lock().unwrap().extend(hosts.iter().cloned());
+
+    let uri = format!("mongodb://{}/?replicaSet=rs", hosts.join(","));
+    Client::with_uri(&uri).unwrap()
+}
+
+fn db_stats_commands(server: &MockServer) -> usize {
+    server
+        .received()
+        .iter()
+        .filter(|req| req.query.as_ref().map_or(false, |q| q.contains_key("dbStats")))
+        .count()
+}
+
+#[test]
+fn nearest_avoids_high_latency_member() {
+    let members = Arc::new(Mutex::new(Vec::new()));
+    let primary = replica_set_member(true, members.clone());
+    let slow = delayed_replica_set_member(false, Duration::from_millis(200), members.clone());
+    let client = replica_set_client(&[&primary, &slow], &members);
+
+    let primary_host = connstring::parse_host(&format!("127.0.0.1:{}", primary.port)).unwrap();
+    let slow_host = connstring::parse_host(&format!("127.0.0.1:{}", slow.port)).unwrap();
+    let rtt = |host: &Host| client.server_description(host).and_then(|desc| desc.round_trip_time);
+
+    // Wait for both members to be checked by their monitors.
+    for _ in 0..200 {
+        if rtt(&primary_host).is_some() && rtt(&slow_host).is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(rtt(&slow_host).expect("Expected the slow member to be checked.") >= 200);
+
+    let read_pref = ReadPreference::new(ReadMode::Nearest, None);
+    for _ in 0..10 {
+        assert_eq!(primary_host, client.select_server(&read_pref).unwrap());
+    }
+}
+
+#[test]
+fn analytics_command_prefers_secondary() {
+    let members = Arc::new(Mutex::new(Vec::new()));
+    let primary = replica_set_member(true, members.clone());
+    let secondary = replica_set_member(false, members.clone());
+    let client = replica_set_client(&[&primary, &secondary], &members);
+
+    // Wait for the secondary to be discovered before relying on SecondaryPreferred.
+    let read_pref = ReadPreference::new(ReadMode::Secondary, None);
+    client
+        .db("admin")
+        .command(doc! { "ping": 1 }, CommandType::Suppressed, Some(read_pref))
+        .unwrap();
+
+    client
+        .run_analytics_command("test", doc! { "dbStats": 1 }, CommandType::Suppressed)
+        .unwrap();
+
+    assert_eq!(1, db_stats_commands(&secondary));
+    assert_eq!(0, db_stats_commands(&primary));
+}
+
+#[test]
+fn analytics_command_falls_back_to_primary() {
+    let members = Arc::new(Mutex::new(Vec::new()));
+    let primary = replica_set_member(true, members.clone());
+    let client = replica_set_client(&[&primary], &members);
+
+    client
+        .run_analytics_command("test", doc! { "dbStats": 1 }, CommandType::Suppressed)
+        .unwrap();
+
+    assert_eq!(1, db_stats_commands(&primary));
+}
+
+#[test]
+fn command_on_host_targets_the_given_server() {
+    let members = Arc::new(Mutex::new(Vec::new()));
+    let primary = replica_set_member(true, members.clone());
+    let secondary = replica_set_member(false, members.clone());
+    let client = replica_set_client(&[&primary, &secondary], &members);
+
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", secondary.port)).unwrap();
+    client
+        .db("test")
+        .command_on_host(doc! { "dbStats": 1 }, CommandType::Suppressed, &host)
+        .unwrap();
+
+    assert_eq!(1, db_stats_commands(&secondary));
+    assert_eq!(0, db_stats_commands(&primary));
+}
+
+#[test]
+fn command_on_host_rejects_unknown_host() {
+    let members = Arc::new(Mutex::new(Vec::new()));
+    let primary = replica_set_member(true, members.clone());
+    let client = replica_set_client(&[&primary], &members);
+
+    let host = unreachable_host();
+    let result = client
+        .db("test")
+        .command_on_host(doc! { "dbStats": 1 }, CommandType::Suppressed, &host);
+
+    match result {
+        Err(Error::ArgumentError(ref msg)) => {
+            assert!(msg.contains(&format!("127.0.0.1:{}", host.port)))
+        }
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    assert_eq!(0, db_stats_commands(&primary));
+}
+
+#[test]
+fn secondary_reply_adds_named_primary() {
+    let dummy_client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let top_arc = Arc::new(RwLock::new(
+        TopologyDescription::new(StreamConnector::default()),
+    ));
+
+    let secondary = connstring::parse_host("a:27017").unwrap();
+    let primary = connstring::parse_host("b:27017").unwrap();
+    let server = Server::new(
+        dummy_client.clone(),
+        secondary.clone(),
+        top_arc.clone(),
+        false,
+        StreamConnector::default(),
+    );
+
+    {
+        let ismaster = IsMasterResult::new(doc! {
+            "ok": 1,
+            "ismaster": false,
+            "secondary": true,
+            "setName": "rs",
+            "hosts": ["a:27017", "b:27017"],
+            "primary": "b:27017",
+        }).unwrap();
+        server.description.write().unwrap().update(ismaster, 0);
+    }
+
+    let mut topology_description = top_arc.write().unwrap();
+    topology_description.servers.insert(secondary.clone(), server.clone());
+    topology_description.update_without_monitor(
+        secondary,
+        server.description.clone(),
+        dummy_client,
+        top_arc.clone(),
+    );
+
+    assert_eq!(TopologyType::ReplicaSetNoPrimary, topology_description.topology_type);
+    assert!(topology_description.servers.contains_key(&primary));
+}
+
+#[test]
+fn secondary_reply_triggers_immediate_check_of_named_primary() {
+    let primary_port = Arc::new(Mutex::new(0));
+    let secondary_port = Arc::new(Mutex::new(0));
+    let members = {
+        let primary_port = primary_port.clone();
+        let secondary_port = secondary_port.clone();
+        move || {
+            vec![
+                Bson::String(format!("127.0.0.1:{}", *secondary_port.lock().unwrap())),
+                Bson::String(format!("127.0.0.1:{}", *primary_port.lock().unwrap())),
+            ]
+        }
+    };
+    let primary_members = members.clone();
+
+    // The named primary answers as a non-primary member until the secondary has reported it,
+    // so only an immediate recheck can discover it before the next heartbeat.
+    let reported = Arc::new(AtomicBool::new(false));
+    let primary_reported = reported.clone();
+    let primary = MockServer::start(Duration::from_millis(0), move |_| {
+        let ismaster = primary_reported.load(Ordering::SeqCst);
+        (0, doc! {
+            "ok": 1,
+            "ismaster": ismaster,
+            "secondary": false,
+            "setName": "rs",
+            "hosts": primary_members(),
+        })
+    });
+    *primary_port.lock().unwrap() = primary.port;
+
+    let primary_host = format!("127.0.0.1:{}", primary.port);
+    let named_primary = primary_host.clone();
+    let secondary = MockServer::start(Duration::from_millis(200), move |_| {
+        reported.store(true, Ordering::SeqCst);
+        (0, doc! {
+            "ok": 1,
+            "ismaster": false,
+            "secondary": true,
+            "setName": "rs",
+            "hosts": members(),
+            "primary": named_primary.clone(),
+        })
+    });
+    *secondary_port.lock().unwrap() = secondary.port;
+
+    let uri = format!(
+        "mongodb://127.0.0.1:{},{}/?replicaSet=rs",
+        secondary.port,
+        primary_host
+    );
+    let options = ClientOptions::builder().heartbeat_frequency_ms(60000).build();
+    let client = Client::with_uri_and_options(&uri, options).unwrap();
+
+    // Poll the description directly, since server selection would itself request checks.
+    let host = connstring::parse_host(&primary_host).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let server_type = client.server_description(&host).map(|desc| desc.server_type);
+        if server_type == Some(ServerType::RSPrimary) {
+            break;
+        }
+
+        assert!(Instant::now() < deadline, "The named primary was not checked");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn server_description_reflects_ismaster_reply() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! {
+            "ok": 1,
+            "ismaster": true,
+            "maxBsonObjectSize": 16777216,
+            "maxMessageSizeBytes": 48000000,
+            "maxWriteBatchSize": 100000,
+            "minWireVersion": 0,
+            "maxWireVersion": 8,
+        })
+    });
+
+    let client = Client::connect("127.0.0.1", server.port).unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+
+    let mut description = None;
+    for _ in 0..100 {
+        description = client.server_description(&host);
+        match description {
+            Some(ref desc) if desc.server_type != ServerType::Unknown => break,
+            _ => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+
+    let description = description.expect("Expected a description for the seed.");
+    assert_eq!(ServerType::Standalone, description.server_type);
+    assert_eq!(16777216, description.max_bson_object_size);
+    assert_eq!(48000000, description.max_message_size_bytes);
+    assert_eq!(100000, description.max_write_batch_size);
+    assert_eq!(8, description.max_wire_version);
+
+    let other = connstring::parse_host("127.0.0.1:1").unwrap();
+    assert!(client.server_description(&other).is_none());
+}
+
+#[test]
+fn clients_with_new_defaults_share_topology() {
+    let client = Client::connect("i-dont-exist", 27017).unwrap();
+
+    let secondary = ReadPreference::new(ReadMode::Secondary, None);
+    let reader = client.with_read_preference(secondary.clone());
+    assert!(reader.shares_topology_with(&client));
+    assert_eq!(secondary, reader.read_preference);
+    assert_eq!(client.write_concern, reader.write_concern);
+    assert_eq!(ReadMode::Primary, client.read_preference.mode);
+
+    let mut journaled = WriteConcern::new();
+    journaled.j = true;
+    let writer = reader.with_write_concern(journaled);
+    assert!(writer.shares_topology_with(&client));
+    assert_eq!(journaled, writer.write_concern);
+    assert_eq!(secondary, writer.read_preference);
+    assert!(!client.write_concern.j);
+}
+
+#[test]
+fn client_options_builder_configures_client() {
+    let secondary = ReadPreference::new(ReadMode::SecondaryPreferred, None);
+    let mut journaled = WriteConcern::new();
+    journaled.j = true;
+
+    let options = ClientOptions::builder()
+        .max_pool_size(2)
+        .read_preference(secondary.clone())
+        .write_concern(journaled)
+        .server_selection_timeout_ms(500)
+        .local_threshold_ms(5)
+        .retry_writes(true)
+        .build();
+    assert_eq!(Some(2), options.max_pool_size);
+    assert_eq!(500, options.server_selection_timeout_ms);
+    assert_eq!(5, options.local_threshold_ms);
+    assert!(options.retry_writes);
+    assert!(!options.attach_write_concern_errors);
+
+    let client = Client::connect_with_options("i-dont-exist", 27017, options).unwrap();
+    assert_eq!(secondary, client.read_preference);
+    assert_eq!(journaled, client.write_concern);
+    assert!(client.retry_writes);
+
+    let options = ClientOptions::builder().max_pool_size(0).build();
+    assert!(Client::connect_with_options("i-dont-exist", 27017, options).is_err());
+
+    let options = ClientOptions::builder().max_pool_size(2).build();
+    let uri = "mongodb://i-dont-exist/?minPoolSize=5";
+    assert!(Client::with_uri_and_options(uri, options).is_err());
+}
+
+// Starts a mock replica set member that reports itself as a secondary until `elected` is set.
+fn electable_replica_set_member(elected: Arc<AtomicBool>) -> MockServer {
+    let members = Arc::new(Mutex::new(Vec::new()));
+    let hosts = members.clone();
+    let server = MockServer::start(Duration::from_millis(0), move |req| match req.query {
+        Some(ref query) if query.contains_key("isMaster") => {
+            let primary = elected.load(Ordering::SeqCst);
+            let hosts: Vec<Bson> =
+                hosts.lock().unwrap().iter().cloned().map(Bson::String).collect();
+            (0, doc! {
+                "ok": 1,
+                "ismaster": primary,
+                "secondary": !primary,
+                "setName": "rs",
+                "hosts": hosts,
+            })
+        }
+        _ => (0, doc! { "ok": 1 }),
+    });
+    members.lock().unwrap().push(format!("127.0.0.1:{}", server.port));
+    server
+}
+
+#[test]
+fn server_selection_waits_for_an_election() {
+    let elected = Arc::new(AtomicBool::new(false));
+    let server = electable_replica_set_member(elected.clone());
+    let uri = format!("mongodb://127.0.0.1:{}/?replicaSet=rs", server.port);
+    let client = Client::with_uri(&uri).unwrap();
+
+    let start = Instant::now();
+    let election = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        elected.store(true, Ordering::SeqCst);
+    });
+
+    client
+        .db("admin")
+        .command(doc! { "ping": 1 }, CommandType::Suppressed, None)
+        .expect("Expected the primary to be selected once elected.");
+    election.join().unwrap();
+
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(300));
+    assert!(elapsed < Duration::from_secs(5));
+}
+
+#[test]
+fn server_selection_times_out_without_a_primary() {
+    let server = electable_replica_set_member(Arc::new(AtomicBool::new(false)));
+    let uri = format!("mongodb://127.0.0.1:{}/?replicaSet=rs", server.port);
+    let options = ClientOptions::builder().server_selection_timeout_ms(200).build();
+    let client = Client::with_uri_and_options(&uri, options).unwrap();
+
+    let start = Instant::now();
+    let read_pref = ReadPreference::new(ReadMode::Primary, None);
+    match client.select_server(&read_pref) {
+        Err(Error::ServerSelectionError(ref err)) => {
+            assert!(err.message.contains("200 ms"), "unexpected message: {}", err.message);
+        }
+        other => panic!("Expected a server selection error, got {:?}", other),
+    }
+
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(200));
+    assert!(elapsed < Duration::from_secs(2));
+}
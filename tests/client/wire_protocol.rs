@@ -1,9 +1,14 @@
-use bson::{Bson, Document};
-use mongodb::{Client, ThreadedClient};
+use bson::{self, Bson, Document};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use mongodb::{Client, ClientOptions, Error, ThreadedClient};
 use mongodb::db::ThreadedDatabase;
 use mongodb::wire_protocol::flags::{OpInsertFlags, OpQueryFlags, OpUpdateFlags};
-use mongodb::wire_protocol::operations::Message;
+use mongodb::wire_protocol::operations::{DEFAULT_MAX_DOCUMENT_DEPTH, Message};
+
+use client::mock::MockServer;
+use std::io::Cursor;
 use std::net::TcpStream;
+use std::time::Duration;
 
 #[test]
 fn insert_single_key_doc() {
@@ -296,3 +301,179 @@ fn insert_update_then_query() {
         Err(_) => panic!("Could not connect to server"),
     }
 }
+
+// Builds a document with the given number of nesting levels, counting the top-level document.
+fn nested_doc(levels: usize) -> Document {
+    let mut doc = doc! { "leaf": 1 };
+    for _ in 1..levels {
+        doc = doc! { "nested": doc };
+    }
+    doc
+}
+
+#[test]
+fn document_depth_limit_allows_max_depth() {
+    let flags = OpQueryFlags::empty();
+    let name = "test-client-wire_protocol-depth.nested".to_owned();
+    let message = Message::new_query(1, flags, name, 0, 0, nested_doc(100), None).unwrap();
+    assert!(message.check_document_depth(DEFAULT_MAX_DOCUMENT_DEPTH).is_ok());
+
+    let nested_array = doc! { "array": [ nested_doc(98) ] };
+    let flags = OpInsertFlags::empty();
+    let name = "test-client-wire_protocol-depth.nested".to_owned();
+    let message = Message::new_insert(1, flags, name, vec![nested_array]).unwrap();
+    assert!(message.check_document_depth(DEFAULT_MAX_DOCUMENT_DEPTH).is_ok());
+}
+
+#[test]
+fn document_depth_limit_rejects_deeper_documents() {
+    let flags = OpQueryFlags::empty();
+    let name = "test-client-wire_protocol-depth.nested".to_owned();
+    let message = Message::new_query(1, flags, name, 0, 0, nested_doc(101), None).unwrap();
+    match message.check_document_depth(DEFAULT_MAX_DOCUMENT_DEPTH) {
+        Err(Error::ArgumentError(_)) => (),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+
+    // Arrays count as a nesting level.
+    let nested_array = doc! { "array": [ nested_doc(99) ] };
+    let flags = OpInsertFlags::empty();
+    let name = "test-client-wire_protocol-depth.nested".to_owned();
+    let message = Message::new_insert(1, flags, name, vec![nested_array]).unwrap();
+    match message.check_document_depth(DEFAULT_MAX_DOCUMENT_DEPTH) {
+        Err(Error::ArgumentError(_)) => (),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+}
+
+#[test]
+fn document_depth_limit_is_a_client_option() {
+    let server = MockServer::start(Duration::from_millis(0), |_| {
+        (0, doc! { "ok": 1, "ismaster": true, "n": 1 })
+    });
+
+    let uri = format!("mongodb://127.0.0.1:{}", server.port);
+    let options = ClientOptions::builder().max_document_depth(3).build();
+    let shallow = Client::with_uri_and_options(&uri, options).unwrap();
+    let default = Client::with_uri(&uri).unwrap();
+
+    let doc = nested_doc(4);
+    let coll = shallow.db("test-client-wire_protocol-depth").collection("nested");
+    match coll.insert_one(doc.clone(), None) {
+        Err(Error::ArgumentError(_)) => (),
+        other => panic!("expected an argument error, got {:?}", other),
+    }
+    assert!(server.received().is_empty());
+
+    // The limit of one client does not affect another.
+    let coll = default.db("test-client-wire_protocol-depth").collection("nested");
+    coll.insert_one(doc, None).unwrap();
+    assert_eq!(1, server.received().len());
+}
+
+#[test]
+fn write_with_buffer_reuses_scratch_buffer() {
+    let flags = OpQueryFlags::empty();
+    let name = "test-client-wire_protocol-scratch.buffer".to_owned();
+    let query = doc! { "find": "buffer", "filter": { "x": 1 }, "payload": "x".repeat(1024) };
+    let message = Message::new_query(1, flags, name, 0, 0, query, None).unwrap();
+
+    let mut expected = Vec::new();
+    message.write(&mut expected).unwrap();
+
+    let mut scratch = Vec::new();
+    let mut first = Vec::new();
+    message.write_with_buffer(&mut first, &mut scratch).unwrap();
+    let capacity = scratch.capacity();
+    assert!(capacity >= expected.len());
+
+    for _ in 0..10 {
+        let mut output = Vec::new();
+        message.write_with_buffer(&mut output, &mut scratch).unwrap();
+        assert_eq!(expected, output);
+        assert_eq!(capacity, scratch.capacity());
+    }
+
+    assert_eq!(expected, first);
+}
+
+#[test]
+fn kill_cursors_message_layout() {
+    let message = Message::new_kill_cursors(7, vec![42, -1]);
+
+    let mut bytes = Vec::new();
+    message.write(&mut bytes).unwrap();
+
+    let mut reader = &bytes[..];
+    let length = reader.read_i32::<LittleEndian>().unwrap();
+    assert_eq!(bytes.len() as i32, length);
+    assert_eq!(40, length);
+    assert_eq!(7, reader.read_i32::<LittleEndian>().unwrap());
+    assert_eq!(0, reader.read_i32::<LittleEndian>().unwrap());
+    assert_eq!(2007, reader.read_i32::<LittleEndian>().unwrap());
+
+    // ZERO, the number of cursor ids, and then the ids themselves.
+    assert_eq!(0, reader.read_i32::<LittleEndian>().unwrap());
+    assert_eq!(2, reader.read_i32::<LittleEndian>().unwrap());
+    assert_eq!(42, reader.read_i64::<LittleEndian>().unwrap());
+    assert_eq!(-1, reader.read_i64::<LittleEndian>().unwrap());
+    assert!(reader.is_empty());
+}
+
+// Serializes an OP_REPLY, optionally wrapped in an OP_COMPRESSED message using the noop
+// compressor.
+fn reply_bytes(doc: &Document, compressed: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.write_i32::<LittleEndian>(0).unwrap();
+    body.write_i64::<LittleEndian>(0).unwrap();
+    body.write_i32::<LittleEndian>(0).unwrap();
+    body.write_i32::<LittleEndian>(1).unwrap();
+    bson::encode_document(&mut body, doc).unwrap();
+
+    let mut message = Vec::new();
+    if compressed {
+        message.write_i32::<LittleEndian>(25 + body.len() as i32).unwrap();
+        message.write_i32::<LittleEndian>(0).unwrap();
+        message.write_i32::<LittleEndian>(0).unwrap();
+        message.write_i32::<LittleEndian>(2012).unwrap();
+        message.write_i32::<LittleEndian>(1).unwrap();
+        message.write_i32::<LittleEndian>(body.len() as i32).unwrap();
+        message.write_u8(0).unwrap();
+    } else {
+        message.write_i32::<LittleEndian>(16 + body.len() as i32).unwrap();
+        message.write_i32::<LittleEndian>(0).unwrap();
+        message.write_i32::<LittleEndian>(0).unwrap();
+        message.write_i32::<LittleEndian>(1).unwrap();
+    }
+    message.extend(body);
+    message
+}
+
+#[test]
+fn read_alternating_compressed_and_plain_replies() {
+    let mut bytes = Vec::new();
+    for i in 0..4 {
+        bytes.extend(reply_bytes(&doc! { "n": i }, i % 2 == 0));
+    }
+
+    let mut connection = Cursor::new(bytes);
+    for i in 0..4 {
+        match Message::read(&mut connection).unwrap() {
+            Message::OpReply { ref documents, .. } => {
+                assert_eq!(&vec![doc! { "n": i }], documents);
+            }
+            other => panic!("expected a reply, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn read_compressed_reply_with_unknown_compressor_fails() {
+    let mut bytes = reply_bytes(&doc! { "n": 1 }, true);
+    bytes[24] = 3;
+
+    match Message::read(&mut Cursor::new(bytes)) {
+        Err(Error::ResponseError(ref msg)) => assert!(msg.contains("compressor id 3")),
+        other => panic!("expected a response error, got {:?}", other),
+    }
+}
@@ -42,8 +42,14 @@
 extern crate approx;
 #[macro_use(bson, doc)]
 extern crate bson;
+extern crate byteorder;
+extern crate chrono;
 extern crate mongodb;
+#[cfg(feature = "ssl")]
+extern crate openssl;
 extern crate rand;
+#[cfg(feature = "tls-rustls")]
+extern crate rustls;
 extern crate semver;
 #[macro_use]
 extern crate serde_derive;
@@ -70,3 +76,5 @@ mod sdam;
 mod server_selection;
 #[cfg(feature = "ssl")]
 mod ssl;
+#[cfg(feature = "tls-rustls")]
+mod tls_rustls;
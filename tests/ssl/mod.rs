@@ -1,7 +1,17 @@
+use std::net::TcpListener;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use mongodb::{Client, ClientOptions, ThreadedClient};
+use mongodb::connstring::{self, ConnectionString};
+use mongodb::pool::ConnectionPool;
 use mongodb::db::ThreadedDatabase;
+use mongodb::stream::{Stream, StreamConnector, TlsOptions};
+use client::mock::MockServer;
+use openssl::ssl::{self, AlpnError, SslAcceptor, SslAcceptorBuilder, SslContext, SslFiletype,
+                   SslMethod, SslVerifyMode};
 
 #[test]
 fn ssl_connect_and_insert() {
@@ -42,3 +52,133 @@ fn unauthenticated_ssl_connect_and_insert() {
 
     coll.insert_one(doc, None).unwrap();
 }
+
+#[test]
+fn ssl_context_connect_and_insert() {
+    let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    test_path.push("tests");
+    test_path.push("ssl");
+
+    let mut context = SslContext::builder(SslMethod::tls()).unwrap();
+    context.set_ca_file(test_path.join("ca.pem")).unwrap();
+    context
+        .set_certificate_file(test_path.join("client.crt"), SslFiletype::PEM)
+        .unwrap();
+    context
+        .set_private_key_file(test_path.join("client.key"), SslFiletype::PEM)
+        .unwrap();
+    context.set_verify(SslVerifyMode::NONE);
+
+    let options = ClientOptions::with_ssl_context(context.build());
+    let client = Client::connect_with_options("127.0.0.1", 27018, options).unwrap();
+    let db = client.db("test");
+    let coll = db.collection("stuff");
+
+    let doc = doc! { "x": 1 };
+
+    coll.insert_one(doc, None).unwrap();
+}
+
+// Builds a TLS acceptor using the test server certificate.
+fn tls_acceptor() -> SslAcceptorBuilder {
+    let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    test_path.push("tests");
+    test_path.push("ssl");
+
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor
+        .set_private_key_file(test_path.join("server.pem"), SslFiletype::PEM)
+        .unwrap();
+    acceptor
+        .set_certificate_chain_file(test_path.join("server.pem"))
+        .unwrap();
+    acceptor
+}
+
+// Accepts a single TLS connection on a local port, returning the port and a handle that
+// reports whether the handshake succeeded.
+fn accept_once(acceptor: SslAcceptor) -> (u16, thread::JoinHandle<bool>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        acceptor.accept(stream).map(|_| ()).is_ok()
+    });
+    (port, server)
+}
+
+fn unverified_connector(alpn_protocols: Vec<String>) -> StreamConnector {
+    let options = TlsOptions::new()
+        .verify_peer(false)
+        .verify_hostname(false)
+        .alpn_protocols(alpn_protocols);
+    StreamConnector::with_tls(options)
+}
+
+#[test]
+fn ssl_stream_is_tls() {
+    let (port, server) = accept_once(tls_acceptor().build());
+
+    let stream = unverified_connector(Vec::new())
+        .connect("127.0.0.1", port)
+        .unwrap();
+    assert!(stream.is_tls());
+    assert!(server.join().unwrap());
+}
+
+#[test]
+fn alpn_protocols_offered_in_handshake() {
+    // A TLS acceptor that records the ALPN protocols offered by the client.
+    let offered = Arc::new(Mutex::new(Vec::new()));
+    let mut acceptor = tls_acceptor();
+    let recorded = offered.clone();
+    acceptor.set_alpn_select_callback(move |_, client_protocols| {
+        recorded.lock().unwrap().extend_from_slice(client_protocols);
+        ssl::select_next_proto(b"\x07mongodb", client_protocols).ok_or(AlpnError::NOACK)
+    });
+    let (port, server) = accept_once(acceptor.build());
+
+    let connector = unverified_connector(vec![String::from("mongodb"), String::from("h2")]);
+
+    match connector.connect("127.0.0.1", port).unwrap() {
+        Stream::Ssl(stream) => {
+            assert_eq!(Some(&b"mongodb"[..]), stream.ssl().selected_alpn_protocol());
+        }
+        _ => panic!("Expected an SSL stream."),
+    }
+
+    assert!(server.join().unwrap());
+    assert_eq!(b"\x07mongodb\x02h2".to_vec(), *offered.lock().unwrap());
+}
+
+#[test]
+fn connection_metrics_reflect_tls_latency() {
+    let server = MockServer::start_tls(tls_acceptor().build(), Duration::from_millis(200), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let host = connstring::parse_host(&format!("127.0.0.1:{}", server.port)).unwrap();
+    let pool = ConnectionPool::new(host, unverified_connector(Vec::new()));
+
+    let stream = pool.acquire_stream(client.clone()).unwrap();
+    let metrics = stream.connection_metrics();
+    let tls_handshake = metrics.tls_handshake.expect("No TLS handshake recorded");
+    assert!(tls_handshake >= Duration::from_millis(200));
+    assert!(metrics.tcp_connect < Duration::from_millis(200));
+    assert!(metrics.handshake < Duration::from_millis(200));
+    assert_eq!(Some(metrics), pool.last_connection_metrics());
+
+    // A second connection is measured separately, and each keeps its own metrics once
+    // returned to the pool.
+    let other = pool.acquire_stream(client.clone()).unwrap();
+    let other_metrics = other.connection_metrics();
+    assert!(other_metrics.tls_handshake.unwrap() >= Duration::from_millis(200));
+    assert_eq!(Some(other_metrics), pool.last_connection_metrics());
+    assert_eq!(metrics, stream.connection_metrics());
+
+    drop(other);
+    let reused = pool.acquire_stream(client).unwrap();
+    assert_eq!(other_metrics, reused.connection_metrics());
+}
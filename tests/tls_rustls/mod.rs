@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use mongodb::{Client, ClientOptions, CommandType, Error, ThreadedClient};
+use mongodb::connstring::{self, ConnectionString};
+use mongodb::db::ThreadedDatabase;
+use mongodb::pool::ConnectionPool;
+use mongodb::stream::StreamConnector;
+use client::mock::MockServer;
+use rustls::ServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+
+fn test_path() -> PathBuf {
+    let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    test_path.push("tests");
+    test_path.push("tls_rustls");
+    test_path
+}
+
+fn server_config() -> Arc<ServerConfig> {
+    let path = test_path();
+    let chain = CertificateDer::pem_file_iter(path.join("server.crt"))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let key = PrivateKeyDer::from_pem_file(path.join("server.key")).unwrap();
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .unwrap();
+    Arc::new(config)
+}
+
+fn trusting_connector() -> StreamConnector {
+    let ca_file = test_path().join("ca.pem");
+    StreamConnector::with_unauthenticated_rustls(Some(ca_file.to_str().unwrap())).unwrap()
+}
+
+#[test]
+fn rustls_connect_and_run_command() {
+    let server = MockServer::start_rustls(server_config(), |req| match req.query {
+        Some(ref query) if query.contains_key("ping") => (0, doc! { "ok": 1, "pong": true }),
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let options = ClientOptions::builder()
+        .stream_connector(trusting_connector())
+        .build();
+    let client = Client::connect_with_options("127.0.0.1", server.port, options).unwrap();
+    let reply = client
+        .db("test")
+        .command(doc! { "ping": 1 }, CommandType::Suppressed, None)
+        .unwrap();
+    assert_eq!(Some(true), reply.get_bool("pong").ok());
+}
+
+#[test]
+fn rustls_stream_is_tls() {
+    let server = MockServer::start_rustls(server_config(), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    let client = Client::with_config(ConnectionString::new("i-dont-exist", 27017), None, None)
+        .unwrap();
+    let host = connstring::parse_host(&format!("localhost:{}", server.port)).unwrap();
+    let pool = ConnectionPool::new(host, trusting_connector());
+
+    let stream = pool.acquire_stream(client).unwrap();
+    assert!(stream.is_tls());
+    assert!(stream.connection_metrics().tls_handshake.is_some());
+}
+
+#[test]
+fn rustls_rejects_untrusted_server() {
+    let server = MockServer::start_rustls(server_config(), |_| {
+        (0, doc! { "ok": 1, "ismaster": true })
+    });
+
+    // Without the test CA, the server's certificate only chains to an unknown root.
+    let connector = StreamConnector::with_unauthenticated_rustls(None).unwrap();
+    assert!(connector.connect("127.0.0.1", server.port).is_err());
+}
+
+#[test]
+fn rustls_rejects_missing_ca_file() {
+    assert!(StreamConnector::with_unauthenticated_rustls(Some("does-not-exist.pem")).is_err());
+}
+
+#[test]
+#[cfg(not(feature = "ssl"))]
+fn rustls_connect_with_tls_uri() {
+    let server = MockServer::start_rustls(server_config(), |req| match req.query {
+        Some(ref query) if query.contains_key("ping") => (0, doc! { "ok": 1, "pong": true }),
+        _ => (0, doc! { "ok": 1, "ismaster": true }),
+    });
+
+    let ca_file = test_path().join("ca.pem");
+    let uri = format!(
+        "mongodb://127.0.0.1:{}/?tls=true&tlsCAFile={}",
+        server.port,
+        ca_file.to_str().unwrap()
+    );
+    let client = Client::with_uri(&uri).unwrap();
+    let reply = client
+        .db("test")
+        .command(doc! { "ping": 1 }, CommandType::Suppressed, None)
+        .unwrap();
+    assert_eq!(Some(true), reply.get_bool("pong").ok());
+}
+
+#[test]
+#[cfg(not(feature = "ssl"))]
+fn rustls_uri_with_certificate_key_file() {
+    let path = test_path();
+    let uri = format!(
+        "mongodb://localhost/?tls=true&tlsCAFile={}&tlsCertificateKeyFile={}",
+        path.join("ca.pem").to_str().unwrap(),
+        path.join("client.pem").to_str().unwrap()
+    );
+    let connector = connstring::parse(&uri).unwrap().stream_connector().unwrap();
+    assert!(connector.unwrap().is_tls());
+
+    let uri = "mongodb://localhost/?tls=true&tlsCertificateKeyFile=does-not-exist.pem";
+    assert!(connstring::parse(uri).unwrap().stream_connector().is_err());
+}
+
+#[test]
+#[cfg(not(feature = "ssl"))]
+fn rustls_uri_rejects_relaxed_verification() {
+    let uris = vec![
+        "mongodb://localhost/?tls=true&tlsAllowInvalidCertificates=true",
+        "mongodb://localhost/?tls=true&tlsAllowInvalidHostnames=true",
+        "mongodb://localhost/?tls=true&tlsInsecure=true",
+    ];
+
+    for uri in uris {
+        match connstring::parse(uri).unwrap().stream_connector() {
+            Err(Error::ArgumentError(ref msg)) => assert!(msg.contains("'tls-rustls'")),
+            Err(err) => panic!("Expected an argument error for {}, got {:?}", uri, err),
+            Ok(_) => panic!("Expected an argument error for {}.", uri),
+        }
+    }
+
+    // Explicitly keeping verification enabled is allowed.
+    let uri = "mongodb://localhost/?tls=true&tlsAllowInvalidCertificates=false";
+    assert!(connstring::parse(uri).unwrap().stream_connector().is_ok());
+}